@@ -17,7 +17,10 @@ extern crate filebuffer;
 fn main() {
     for fname in env::args().skip(1) {
         println!("==> {} <==", &fname);
-        let fbuffer = FileBuffer::open(&fname).expect("failed to open file");
+        // Like coreutils, read standard input for `-`. Filebuffer reads pipes into memory, because
+        // they cannot be mapped.
+        let path = if fname == "-" { "/dev/stdin" } else { &fname[..] };
+        let fbuffer = FileBuffer::open(path).expect("failed to open file");
         let lines = str::from_utf8(&fbuffer).expect("not valid UTF-8").lines();
         for line in lines.take(10) {
             println!("{}", line);
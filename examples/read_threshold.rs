@@ -0,0 +1,59 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+// This example measures the crossover between reading a file into memory and mapping it, to help
+// pick a value for `OpenOptions::read_threshold()`. For a range of file sizes, it opens a file
+// many times in both ways, touches every byte, and prints the average time per open. Run it with
+// `--release`, and optionally pass a directory to create the test files in; the default is the
+// temporary directory. The files are in the page cache, so this measures the cost of the system
+// calls and page faults, not of the disk.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+use filebuffer::OpenOptions;
+
+extern crate filebuffer;
+
+/// Opens the file at `path` `iterations` times, and returns the average time per open in
+/// microseconds.
+fn time_open(path: &PathBuf, read_threshold: usize, iterations: u32) -> f64 {
+    let mut options = OpenOptions::new();
+    options.read_threshold(read_threshold);
+
+    let start = Instant::now();
+    let mut sum = 0_u64;
+    for _ in 0..iterations {
+        let fbuffer = options.open(path).expect("failed to open file");
+        // Touch every byte, so the mapping pays for its page faults.
+        sum += fbuffer.iter().map(|&b| b as u64).sum::<u64>();
+    }
+    let elapsed = start.elapsed();
+
+    // Print nothing, but make sure the sum is not optimized away.
+    assert!(sum < u64::MAX);
+    elapsed.as_secs_f64() * 1e6 / iterations as f64
+}
+
+fn main() {
+    let dir = env::args().nth(1).map(PathBuf::from).unwrap_or_else(env::temp_dir);
+    let path = dir.join(format!("filebuffer-read-threshold-{}", std::process::id()));
+
+    println!("{:>10} {:>10} {:>10}", "size", "read (us)", "map (us)");
+    for &size in &[1 << 10, 4 << 10, 16 << 10, 64 << 10, 256 << 10, 1 << 20] {
+        let contents: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &contents).expect("failed to write test file");
+
+        let iterations = (64 << 20) / size as u32;
+        let read_us = time_open(&path, usize::MAX, iterations);
+        let map_us = time_open(&path, 0, iterations);
+        println!("{:>10} {:>10.2} {:>10.2}", size, read_us, map_us);
+    }
+
+    fs::remove_file(&path).expect("failed to remove test file");
+}
@@ -18,7 +18,10 @@ extern crate filebuffer;
 
 fn main() {
     for fname in env::args().skip(1) {
-        let fbuffer = FileBuffer::open(&fname).expect("failed to open file");
+        // Like coreutils, read standard input for `-`. Filebuffer reads pipes into memory, because
+        // they cannot be mapped.
+        let path = if fname == "-" { "/dev/stdin" } else { &fname[..] };
+        let fbuffer = FileBuffer::open_sequential(path).expect("failed to open file");
         let mut hasher = Sha256::new();
         hasher.input(&fbuffer);
 
@@ -26,7 +26,7 @@ fn main() {
         loop {
             let consumed_len = {
                 let buffer = reader.fill_buf().expect("failed to read from file");
-                if buffer.len() == 0 {
+                if buffer.is_empty() {
                     // End of file.
                     break;
                 }
@@ -0,0 +1,136 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This mod contains a cache that shares mappings of the same file between opens.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Weak};
+use std::time::SystemTime;
+
+use super::{FileBuffer, OpenOptions, error_naming_path, get_file_id, open_path};
+
+/// A cache entry: the mapping, and the size and modification time of the file when it was mapped.
+struct Entry {
+    fbuffer: Weak<FileBuffer>,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// A cache that hands out the same mapping for repeated opens of the same file.
+///
+/// Opening the same file twice, through the same path, or through different paths such as hard
+/// links, normally creates two independent mappings. A `FileBufferCache` identifies files by their
+/// device and inode number (the volume serial number and file index on Windows), and while a
+/// buffer for the file is alive, `open()` returns that buffer rather than mapping the file again.
+/// This saves address space in batch jobs that touch the same files many times.
+///
+/// The cache does not keep buffers alive: when the last `Arc` of a buffer is dropped, the file is
+/// unmapped. A cached buffer is only handed out if the size and modification time of the file did
+/// not change since it was mapped; otherwise the file is mapped again.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use filebuffer::FileBufferCache;
+///
+/// let mut cache = FileBufferCache::new();
+/// let a = cache.open("src/lib.rs").unwrap();
+/// let b = cache.open("src/../src/lib.rs").unwrap();
+/// assert!(Arc::ptr_eq(&a, &b));
+/// ```
+#[derive(Default)]
+pub struct FileBufferCache {
+    entries: HashMap<(u64, u64), Entry>,
+}
+
+impl FileBufferCache {
+    /// Creates an empty cache.
+    pub fn new() -> FileBufferCache {
+        FileBufferCache {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Maps the file at `path` into memory, or returns the existing mapping of the same file.
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Arc<FileBuffer>> {
+        let path = path.as_ref();
+        let options = OpenOptions::new();
+        let file = open_path(path, &options).map_err(|err| error_naming_path(path, err))?;
+        let id = get_file_id(&file)?;
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+        let modified = metadata.modified().ok();
+
+        if let Some(entry) = self.entries.get(&id) {
+            if entry.len == len && entry.modified == modified {
+                if let Some(fbuffer) = entry.fbuffer.upgrade() {
+                    return Ok(fbuffer);
+                }
+            }
+        }
+
+        // Take the opportunity to forget about buffers that have been dropped, so the cache does
+        // not grow without bound.
+        self.entries.retain(|_, entry| entry.fbuffer.strong_count() > 0);
+
+        let fbuffer = FileBuffer::from_file(file, &options)
+            .map_err(|err| error_naming_path(path, err))?;
+        let fbuffer = Arc::new(fbuffer.with_path(path));
+        let entry = Entry {
+            fbuffer: Arc::downgrade(&fbuffer),
+            len,
+            modified,
+        };
+        self.entries.insert(id, entry);
+        Ok(fbuffer)
+    }
+}
+
+impl fmt::Debug for FileBufferCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileBufferCache")
+            .field("len", &self.entries.len())
+            .finish()
+    }
+}
+
+#[test]
+fn open_shares_mappings_of_the_same_file() {
+    use std::fs;
+
+    let path = super::write_temp_file("cache", 10);
+    let link = path.with_extension("link");
+    let _ = fs::remove_file(&link);
+    fs::hard_link(&path, &link).unwrap();
+
+    let mut cache = FileBufferCache::new();
+    let a = cache.open(&path).unwrap();
+    let b = cache.open(&link).unwrap();
+    let other = cache.open("src/lib.rs").unwrap();
+    assert!(Arc::ptr_eq(&a, &b));
+    assert!(!Arc::ptr_eq(&a, &other));
+
+    // After the last buffer is dropped, the file is mapped again.
+    drop((a, b));
+    let c = cache.open(&path).unwrap();
+    assert_eq!(&c[..], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9][..]);
+
+    // When the file changes, it is mapped again, even while the old buffer is alive.
+    let mut file = fs::OpenOptions::new().append(true).open(&link).unwrap();
+    io::Write::write_all(&mut file, &[10]).unwrap();
+    let d = cache.open(&path).unwrap();
+    assert!(!Arc::ptr_eq(&c, &d));
+    assert_eq!(&d[..], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10][..]);
+
+    drop((c, d, file));
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&link).unwrap();
+}
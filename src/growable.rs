@@ -0,0 +1,129 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This mod contains a file buffer that can grow along with the file it maps.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use super::{FileBuffer, OpenOptions, open_naming_path, open_path};
+
+/// A memory-mapped file that can be extended when the file grows.
+///
+/// A `FileBuffer` covers the file as it was when it was opened. A `GrowableFileBuffer` keeps the
+/// file open, so that `refresh()` can extend the mapping to bytes appended later, for example by
+/// another process writing a log. This makes it possible to follow a file, like `tail -f` does.
+///
+/// The same safety caveats as for `FileBuffer` apply. In particular, truncating the file while it
+/// is mapped causes undefined behavior when the removed part is accessed.
+pub struct GrowableFileBuffer {
+    file: fs::File,
+    buffer: FileBuffer,
+    options: OpenOptions,
+}
+
+impl GrowableFileBuffer {
+    /// Maps the file at `path` into memory, keeping the file open to allow growing the mapping.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<GrowableFileBuffer> {
+        let path = path.as_ref();
+        open_naming_path(path, || {
+            let options = OpenOptions::new();
+            let file = open_path(path, &options)?;
+            let buffer = FileBuffer::from_file(file.try_clone()?, &options)?.with_path(path);
+            let gbuffer = GrowableFileBuffer {
+                file,
+                buffer,
+                options,
+            };
+            Ok(gbuffer)
+        })
+    }
+
+    /// Extends the mapping to cover the current size of the file.
+    ///
+    /// Returns the number of bytes that were added to the buffer, which is 0 if the file did not
+    /// grow. The mapping is never shrunk. On Linux the mapping is resized with `mremap`, elsewhere
+    /// the file is mapped again, so the address of the buffer may change.
+    pub fn refresh(&mut self) -> io::Result<usize> {
+        let file_len = self.file.metadata()?.len();
+        let old_len = self.buffer.len();
+
+        if file_len <= old_len as u64 {
+            return Ok(0);
+        }
+
+        if file_len > isize::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::Other, "file is larger than address space"));
+        }
+
+        let new_len = file_len as usize;
+        self.buffer.remap(&self.file, new_len, &self.options)?;
+        Ok(new_len - old_len)
+    }
+}
+
+impl Deref for GrowableFileBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl AsRef<[u8]> for GrowableFileBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl fmt::Debug for GrowableFileBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GrowableFileBuffer")
+            .field("len", &self.buffer.len())
+            .finish()
+    }
+}
+
+#[test]
+fn refresh_extends_the_mapping() {
+    use std::io::Write;
+
+    let path = super::write_temp_file("growable", 10);
+    let mut gbuffer = GrowableFileBuffer::open(&path).unwrap();
+    assert_eq!(&gbuffer[..], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9][..]);
+    assert_eq!(gbuffer.refresh().unwrap(), 0);
+
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[42; 5000]).unwrap();
+    let added = gbuffer.refresh().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(added, 5000);
+    assert_eq!(gbuffer.len(), 5010);
+    assert_eq!(gbuffer[9], 9);
+    assert_eq!(gbuffer[5009], 42);
+}
+
+#[test]
+fn refresh_extends_an_empty_mapping() {
+    use std::io::Write;
+
+    let path = super::write_temp_file("growable-empty", 0);
+    let mut gbuffer = GrowableFileBuffer::open(&path).unwrap();
+    assert!(gbuffer.is_empty());
+
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"Filebuffer").unwrap();
+    let added = gbuffer.refresh().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(added, 10);
+    assert_eq!(&gbuffer[..], &b"Filebuffer"[..]);
+}
@@ -20,13 +20,57 @@
 
 #![warn(missing_docs)]
 
+use std::alloc::{self, Layout};
+use std::borrow::{Borrow, BorrowMut, Cow};
 use std::cmp;
+use std::convert::TryFrom;
+use std::ffi::{CStr, FromBytesWithNulError};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::fs;
-use std::ops::Deref;
-use std::path::Path;
+use std::mem;
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::slice;
+use std::str;
+use std::sync::{Arc, Mutex, Weak, mpsc};
+use std::task::Poll;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use stats::StatsCounters;
+
+#[cfg(unix)]
+use std::ops::Range;
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+#[cfg(windows)]
+use std::os::windows::io::RawHandle;
+
+// Emits a `tracing` event at the given level with the `tracing` feature. Without it, this expands
+// to nothing, so the fields are not even evaluated.
+macro_rules! trace_event {
+    ($level:ident, $($args:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($args)+);
+    };
+}
+
+mod cache;
+mod growable;
+mod options;
+mod pool;
+mod prefetch;
+mod reader;
+mod stats;
+mod subbuffer;
+
+#[cfg(feature = "mapped-with")]
+mod mapped_with;
 
 #[cfg(unix)]
 mod unix;
@@ -34,274 +78,5200 @@ mod unix;
 #[cfg(windows)]
 mod windows;
 
-#[cfg(unix)]
-use unix::{PlatformData, get_page_size, map_file, unmap_file, prefetch};
+#[cfg(unix)]
+use unix::{
+    PlatformData, advise, advise_fd, advise_file, advise_free, adopt_heap, clone_file,
+    get_allocation_granularity, get_available_memory, get_backed_len, get_cpu_count,
+    get_current_file_len, get_file_id, get_file_len, get_page_size, get_readahead_len,
+    is_heap_backed, is_memory_backed, is_stream, leak_owned_memory, lock_memory, map_anonymous,
+    map_file, map_file_cow, open_path, protect_readonly, read_file, unmap_file, prefetch,
+    prefetch_deep, prefetch_ranges, read_file_range, readahead,
+};
+
+#[cfg(unix)]
+use unix::{get_data_ranges, get_fault_counts, get_raw_fd, get_resident, open_path_at};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use unix::{is_huge_page_backed, read_direct};
+
+#[cfg(unix)]
+use unix::remap_file;
+
+#[cfg(all(unix, feature = "memmap2"))]
+use unix::adopt_mmap;
+
+#[cfg(windows)]
+use windows::{get_fault_counts, get_raw_handle, get_region_info};
+
+#[cfg(all(windows, feature = "memmap2"))]
+use windows::adopt_mmap;
+
+#[cfg(windows)]
+pub use windows::RegionInfo;
+
+#[cfg(windows)]
+use windows::{
+    PlatformData, advise, advise_fd, advise_file, advise_free, adopt_heap, clone_file,
+    get_allocation_granularity, get_available_memory, get_backed_len, get_cpu_count,
+    get_current_file_len, get_file_id, get_file_len, get_resident, get_page_size, get_readahead_len,
+    is_heap_backed, is_memory_backed, is_stream, leak_owned_memory, lock_memory, map_anonymous,
+    map_file, map_file_cow, open_path, protect_readonly, read_file, unmap_file, prefetch,
+    prefetch_deep, prefetch_ranges, read_file_range, readahead,
+};
+
+pub use cache::FileBufferCache;
+pub use growable::GrowableFileBuffer;
+pub use options::{OpenOptions, WritablePolicy};
+pub use pool::FileBufferPool;
+pub use prefetch::PrefetchHandle;
+pub use reader::{Reader, ResidentReader};
+pub use stats::BufferStats;
+pub use subbuffer::SubBuffer;
+
+#[cfg(feature = "mapped-with")]
+pub use mapped_with::MappedWith;
+
+/// A memory-mapped file.
+///
+/// # Safety
+///
+/// **On Unix-ish platforms, external modifications to the file made after the file buffer was
+/// opened can show up in this file buffer.** In particular, if a file is truncated after opening,
+/// accessing the removed part causes undefined behavior. On Windows it is possible to prevent this
+/// by opening the file in exclusive mode, but that functionality is not available in stable Rust
+/// currently. (Filebuffer will be updated after stabilization.)
+///
+/// It is recommended to ensure that other applications do not write to the file when it is mapped,
+/// possibly by marking the file read-only. (Though even this is no guarantee.) See
+/// `OpenOptions::writable_policy()` to enforce that.
+pub struct FileBuffer {
+    page_size: usize,
+    buffer: *const u8,
+    length: usize,
+
+    #[allow(dead_code)] // This field is not dead, it might have an effectful destructor.
+    platform_data: PlatformData,
+
+    // Only present when the buffer was opened with `OpenOptions::track_stats(true)`.
+    stats: Option<Box<StatsCounters>>,
+
+    // The mechanism that `prefetch()` uses, see `set_prefetch_backend()`.
+    prefetch_backend: PrefetchBackend,
+
+    // The distance from the page-aligned start of the mapping to `buffer`, which is nonzero only
+    // for buffers opened with `try_open_range()` at an unaligned offset. The mapping spans
+    // `view_offset + length` bytes.
+    view_offset: usize,
+
+    // The path that the buffer was opened from, if it was opened from a path, see `path()`.
+    path: Option<PathBuf>,
+
+    // The length of the file when it was mapped, see `file_len()`.
+    file_len: u64,
+
+    // The access pattern advice that was applied when the buffer was opened, which is restored
+    // when the last `SequentialAdvice` guard is dropped, and the number of live guards.
+    open_advice: Advice,
+    sequential_guards: Mutex<usize>,
+}
+
+/// A writable memory-mapped region.
+///
+/// Unlike `FileBuffer`, the memory is writable. The region is private to the process; writes are
+/// never written back to a file.
+pub struct FileBufferMut {
+    page_size: usize,
+    buffer: *mut u8,
+    length: usize,
+
+    #[allow(dead_code)] // This field is not dead, it might have an effectful destructor.
+    platform_data: PlatformData,
+}
+
+/// A hint about how a slice of a mapping is going to be accessed.
+///
+/// On Unix-ish platforms these correspond to the `MADV_*` flags of `madvise`. See
+/// `FileBuffer::advise()`. Windows has no equivalent, so there advice has no effect, except for
+/// `WillNeed`, which `FileBuffer::advise()` applies with `PrefetchVirtualMemory`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Advice {
+    /// No special treatment. This is the default for a mapping.
+    Normal,
+    /// Expect sequential access: read ahead aggressively, and evict pages soon after access.
+    Sequential,
+    /// Expect random access: reading ahead is of little use.
+    Random,
+    /// Expect access in the near future: start reading in the pages now.
+    WillNeed,
+    /// Do not expect access in the near future: the pages may be evicted.
+    DontNeed,
+    /// Expect no access soon: the pages are evicted before others under memory pressure, see
+    /// `FileBuffer::advise_cold()`.
+    Cold,
+    /// Expect no access soon: the pages are reclaimed right away, see
+    /// `FileBuffer::advise_pageout()`.
+    PageOut,
+    /// The pages are no longer needed: the kernel may reclaim them lazily, see
+    /// `FileBuffer::advise_free()`.
+    Free,
+}
+
+/// The mechanism that `FileBuffer::prefetch()` uses to ask the kernel to read in pages.
+///
+/// Which one reads ahead more aggressively depends on the kernel and the storage, so the only way
+/// to choose is to measure. See `FileBuffer::set_prefetch_backend()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum PrefetchBackend {
+    /// Advise on the mapping with `madvise(MADV_WILLNEED)`, or with `PrefetchVirtualMemory` on
+    /// Windows. This is the default.
+    ///
+    /// On Linux, for ranges of 8 MiB or more of a mapped file, this also submits `readahead`
+    /// calls for the start of the range, sized to the readahead window and queue depth of the
+    /// block device as reported in `/sys`, to keep the command queue of fast devices full.
+    Madvise,
+    /// Read the file into the page cache with `readahead` on the file descriptor. This is only
+    /// available on Linux. Elsewhere, and for buffers that are not backed by a file, this falls
+    /// back to `Madvise`.
+    Readahead,
+}
+
+impl Default for PrefetchBackend {
+    fn default() -> PrefetchBackend {
+        PrefetchBackend::Madvise
+    }
+}
+
+/// Rounds `size` up to the nearest multiple of `power_of_two`.
+fn round_up_to(size: usize, power_of_two: usize) -> usize {
+    (size + (power_of_two - 1)) & !(power_of_two - 1)
+}
+
+#[test]
+fn verify_round_up_to() {
+    assert_eq!(1024, round_up_to(23, 1024));
+    assert_eq!(1024, round_up_to(1024, 1024));
+    assert_eq!(2048, round_up_to(1025, 1024));
+}
+
+/// Rounds `size` down to the nearest multiple of `power_of_two`.
+fn round_down_to(size: usize, power_of_two: usize) -> usize {
+    size & !(power_of_two - 1)
+}
+
+/// A zeroed, page-aligned heap allocation of whole pages, which holds the contents of a file that
+/// was read rather than mapped, see `OpenOptions::read_threshold()`. Because the allocation does
+/// not share pages with other allocations, advice and residency queries that operate on whole
+/// pages can be applied to it as to a mapping.
+#[derive(Debug)]
+struct HeapPages {
+    ptr: *mut u8,
+    length: usize,
+    layout: Layout,
+}
+
+impl HeapPages {
+    /// Allocates `length` zeroed bytes, rounded up to whole pages. `length` must not be 0.
+    fn new(length: usize, page_size: usize) -> HeapPages {
+        debug_assert!(length > 0, "cannot allocate an empty buffer");
+        let layout = Layout::from_size_align(round_up_to(length, page_size), page_size)
+            .expect("page size must be a power of two");
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() { alloc::handle_alloc_error(layout); }
+        HeapPages { ptr, length, layout }
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    fn len(&self) -> usize {
+        self.length
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.length) }
+    }
+
+    /// Returns a pointer to the allocation, which spans `length` rounded up to whole pages.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr
+    }
+}
+
+impl Drop for HeapPages {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// Advises a buffer as `Advice::Sequential` for as long as it lives. When the last guard for a
+/// buffer is dropped, the access pattern advice that the buffer was opened with is restored.
+struct SequentialAdvice<'a>(&'a FileBuffer);
+
+impl<'a> SequentialAdvice<'a> {
+    fn new(fbuffer: &'a FileBuffer) -> SequentialAdvice<'a> {
+        // Hold the lock while advising, so a guard that is dropped concurrently cannot restore
+        // the old advice after this one applied its own.
+        let mut guards = fbuffer.sequential_guards.lock().unwrap_or_else(|err| err.into_inner());
+        if *guards == 0 {
+            // Advice is only a hint, failing to apply it is not a reason to fail.
+            let _ = fbuffer.advise(0, fbuffer.length, Advice::Sequential);
+        }
+        *guards += 1;
+        SequentialAdvice(fbuffer)
+    }
+}
+
+impl<'a> Drop for SequentialAdvice<'a> {
+    fn drop(&mut self) {
+        let fbuffer = self.0;
+        let mut guards = fbuffer.sequential_guards.lock().unwrap_or_else(|err| err.into_inner());
+        *guards -= 1;
+        if *guards == 0 {
+            let _ = fbuffer.advise(0, fbuffer.length, fbuffer.open_advice);
+        }
+    }
+}
+
+#[test]
+fn verify_round_down_to() {
+    assert_eq!(0, round_down_to(23, 1024));
+    assert_eq!(1024, round_down_to(1024, 1024));
+    assert_eq!(1024, round_down_to(1025, 1024));
+}
+
+/// Rounds every `(offset, length)` pair in `ranges` outwards to multiples of `page_size`, skipping
+/// empty ranges, and merges ranges that touch or overlap after rounding. The result is sorted by
+/// offset, whatever the order of `ranges`.
+fn align_ranges(ranges: &[(usize, usize)], page_size: usize) -> Vec<(usize, usize)> {
+    let mut bounds: Vec<(usize, usize)> = ranges
+        .iter()
+        .filter(|&&(_, length)| length > 0)
+        .map(|&(offset, length)| {
+            (round_down_to(offset, page_size), round_up_to(offset + length, page_size))
+        })
+        .collect();
+
+    // After sorting, a range can only touch or overlap the range before it, or ranges that were
+    // merged into that one already.
+    bounds.sort_unstable();
+
+    let mut aligned_ranges: Vec<(usize, usize)> = Vec::with_capacity(bounds.len());
+    for (aligned_offset, aligned_end) in bounds {
+        if let Some(last) = aligned_ranges.last_mut() {
+            let (last_offset, last_length) = *last;
+            let last_end = last_offset + last_length;
+            if aligned_offset <= last_end {
+                *last = (last_offset, cmp::max(last_end, aligned_end) - last_offset);
+                continue;
+            }
+        }
+
+        aligned_ranges.push((aligned_offset, aligned_end - aligned_offset));
+    }
+
+    aligned_ranges
+}
+
+#[test]
+fn verify_align_ranges() {
+    // Touching and overlapping ranges are merged, empty ranges are dropped. The order of the
+    // ranges does not matter: (3072, 1) touches both the ranges before and after it.
+    let ranges = [(10, 20), (1024, 1), (0, 0), (2000, 100), (5000, 10), (3072, 1)];
+    assert_eq!(align_ranges(&ranges, 1024), [(0, 5120)]);
+    let ranges = [(5000, 10), (2000, 100), (10, 20), (3072, 1), (1024, 1)];
+    assert_eq!(align_ranges(&ranges, 1024), [(0, 5120)]);
+    let ranges = [(8192, 1), (0, 1), (4096, 1), (100, 5000)];
+    assert_eq!(align_ranges(&ranges, 1024), [(0, 5120), (8192, 1024)]);
+}
+
+/// Returns the number of bytes resident in physical memory in the range of `length` bytes starting
+/// at `offset` in the mapping at `buffer`, which must be page-aligned.
+///
+/// Residency is queried with `get_resident`, for at most `residency.len()` pages at once. This is
+/// a free function, rather than part of `FileBuffer::resident_len()`, so that tests can exercise
+/// the alignment arithmetic with different page sizes and a fake residency function.
+fn resident_len_impl<F>(
+    buffer: *const u8,
+    offset: usize,
+    length: usize,
+    page_size: usize,
+    residency: &mut [bool],
+    mut get_resident: F,
+) -> usize
+where
+    F: FnMut(*const u8, usize, &mut [bool]),
+{
+    let aligned_offset = round_down_to(offset, page_size);
+    let aligned_length = round_up_to(length + (offset - aligned_offset), page_size);
+    let num_pages = aligned_length / page_size;
+
+    let mut pages_checked = 0;
+    let mut pages_resident = 0;
+
+    while pages_checked < num_pages {
+        let pages_to_check = cmp::min(residency.len(), num_pages - pages_checked);
+        let check_offset = aligned_offset + pages_checked * page_size;
+        let check_buffer = buffer.wrapping_add(check_offset);
+        let check_length = pages_to_check * page_size;
+        get_resident(check_buffer, check_length, residency);
+
+        // Count the number of resident pages.
+        match residency[..pages_to_check].iter().position(|resident| !resident) {
+            Some(non_resident) => {
+                // The index of the non-resident page is the number of resident pages.
+                pages_resident += non_resident;
+                break;
+            }
+            None => {
+                pages_resident += pages_to_check;
+                pages_checked += pages_to_check;
+            }
+        }
+    }
+
+    // The resident pages start at the aligned offset, but the resident length counts from
+    // `offset`. If not even the first page is resident, nothing is.
+    let resident_length = (pages_resident * page_size).saturating_sub(offset - aligned_offset);
+
+    // Never return more than the requested length. The resident length might be larger than
+    // the length of the buffer, because it is rounded up to the page size.
+    cmp::min(length, resident_length)
+}
+
+/// Sets bit `i` of the returned bitmap if page `i` of the buffer is resident.
+///
+/// Like `resident_len_impl()`, residency is queried at most `residency.len()` pages at a time.
+fn resident_bitmap_impl<F>(
+    buffer: *const u8,
+    length: usize,
+    page_size: usize,
+    residency: &mut [bool],
+    mut get_resident: F,
+) -> Vec<u64>
+where
+    F: FnMut(*const u8, usize, &mut [bool]),
+{
+    let num_pages = round_up_to(length, page_size) / page_size;
+    let mut bitmap = vec![0_u64; (num_pages + 63) / 64];
+
+    let mut pages_checked = 0;
+    while pages_checked < num_pages {
+        let pages_to_check = cmp::min(residency.len(), num_pages - pages_checked);
+        let check_buffer = buffer.wrapping_add(pages_checked * page_size);
+        get_resident(check_buffer, pages_to_check * page_size, residency);
+
+        for (i, &resident) in residency[..pages_to_check].iter().enumerate() {
+            let page = pages_checked + i;
+            if resident { bitmap[page / 64] |= 1 << (page % 64); }
+        }
+        pages_checked += pages_to_check;
+    }
+
+    bitmap
+}
+
+/// Returns a fake residency function for a mapping at `base`, where only the first
+/// `resident_pages` pages are resident.
+#[cfg(test)]
+fn fake_get_resident(
+    base: usize,
+    page_size: usize,
+    resident_pages: usize,
+) -> impl FnMut(*const u8, usize, &mut [bool]) {
+    move |buffer, length, residency| {
+        assert_eq!(0, (buffer as usize) % page_size);
+        assert_eq!(0, length % page_size);
+        let first_page = (buffer as usize - base) / page_size;
+        for (i, resident) in residency[..length / page_size].iter_mut().enumerate() {
+            *resident = first_page + i < resident_pages;
+        }
+    }
+}
+
+#[test]
+fn verify_resident_len_impl() {
+    for &page_size in &[512, 4096, 16384] {
+        // The buffer is never dereferenced, any page-aligned address will do.
+        let base = page_size * 16;
+        let buffer = base as *const u8;
+        let mut residency = [false; 3];
+
+        let mut resident_len = |offset, length, resident_pages| {
+            let get_resident = fake_get_resident(base, page_size, resident_pages);
+            resident_len_impl(buffer, offset, length, page_size, &mut residency, get_resident)
+        };
+
+        // Everything resident, including across batches of 3 pages.
+        assert_eq!(resident_len(0, page_size * 10, 10), page_size * 10);
+        assert_eq!(resident_len(7, page_size * 10 - 7, 10), page_size * 10 - 7);
+
+        // Residency stops at the first non-resident page, also beyond the first batch.
+        assert_eq!(resident_len(0, page_size * 10, 4), page_size * 4);
+        assert_eq!(resident_len(page_size + 1, page_size * 9, 4), page_size * 3 - 1);
+
+        // Unaligned offsets in a non-resident page report nothing resident.
+        assert_eq!(resident_len(0, page_size * 10, 0), 0);
+        assert_eq!(resident_len(page_size * 4 + 1, 10, 4), 0);
+
+        // The resident length never exceeds the requested length.
+        assert_eq!(resident_len(3, 5, 10), 5);
+
+        // A buffer that ends partway through its last page. Residency has page granularity, so
+        // if that page is resident, all of the requested bytes in it are, otherwise none are.
+        let length = page_size * 2 + 100;
+        assert_eq!(resident_len(0, length, 3), length);
+        assert_eq!(resident_len(0, length, 2), page_size * 2);
+        assert_eq!(resident_len(length - 1, 1, 3), 1);
+        assert_eq!(resident_len(length - 1, 1, 2), 0);
+        assert_eq!(resident_len(page_size * 2 + 1, 99, 3), 99);
+        assert_eq!(resident_len(0, 0, 10), 0);
+    }
+}
+
+#[test]
+fn verify_resident_bitmap_impl() {
+    let page_size = 4096;
+    let base = page_size * 16;
+    let buffer = base as *const u8;
+    let mut residency = [false; 3];
+
+    let mut bitmap = |length, resident_pages| {
+        let get_resident = fake_get_resident(base, page_size, resident_pages);
+        resident_bitmap_impl(buffer, length, page_size, &mut residency, get_resident)
+    };
+
+    assert_eq!(bitmap(0, 0), Vec::<u64>::new());
+    assert_eq!(bitmap(1, 1), vec![0b1]);
+    assert_eq!(bitmap(page_size * 10, 4), vec![0b1111]);
+    assert_eq!(bitmap(page_size * 10 - 1, 10), vec![0b11_1111_1111]);
+    assert_eq!(bitmap(page_size * 64, 64), vec![u64::MAX]);
+    assert_eq!(bitmap(page_size * 65, 64), vec![u64::MAX, 0]);
+    assert_eq!(bitmap(page_size * 65, 65), vec![u64::MAX, 1]);
+}
+
+/// Includes `path` in the message of `err`, and keeps its kind.
+fn error_naming_path(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("failed to open {}: {}", path.display(), err))
+}
+
+/// Calls `open`, and includes `path` in the message of the error if that fails.
+fn open_naming_path<T, F: FnOnce() -> io::Result<T>>(path: &Path, open: F) -> io::Result<T> {
+    open().map_err(|err| error_naming_path(path, err))
+}
+
+/// Advises the kernel to make all regular files in `dir` resident.
+///
+/// This is like `FileBuffer::prewarm()` for the files in a directory, but the buffers are dropped
+/// right away; the page cache retains the pages after a file is unmapped. The advice is
+/// asynchronous, so the reads for all files overlap. Subdirectories, symbolic links, and other
+/// files that are not regular files are skipped. See `prefetch_dir_recursive()` to include
+/// subdirectories.
+///
+/// When a file cannot be opened, the remaining files are still prefetched, and the first error is
+/// returned at the end.
+pub fn prefetch_dir<P: AsRef<Path>>(dir: P) -> io::Result<()> {
+    prefetch_dir_impl(dir.as_ref(), false)
+}
+
+/// Advises the kernel to make all regular files in `dir` and its subdirectories resident.
+///
+/// This is like `prefetch_dir()`, but it descends into subdirectories. Symbolic links to
+/// directories are not followed.
+pub fn prefetch_dir_recursive<P: AsRef<Path>>(dir: P) -> io::Result<()> {
+    prefetch_dir_impl(dir.as_ref(), true)
+}
+
+/// Maps every regular file in `dir` and advises the kernel to make it resident, and does the same
+/// for subdirectories if `recursive` is true. Keeps going after an error, and returns the first.
+fn prefetch_dir_impl(dir: &Path, recursive: bool) -> io::Result<()> {
+    let mut result = Ok(());
+    for entry in fs::read_dir(dir)? {
+        let entry_result = entry.and_then(|entry| {
+            // Unlike `fs::metadata`, `file_type` does not follow symlinks, so symlinks are
+            // skipped, and the recursion cannot loop.
+            let file_type = entry.file_type()?;
+            if file_type.is_file() {
+                // The page cache retains the pages after the buffer is unmapped.
+                let fbuffer = FileBuffer::open(entry.path())?;
+                fbuffer.prefetch(0, fbuffer.len());
+                Ok(())
+            } else if file_type.is_dir() && recursive {
+                prefetch_dir_impl(&entry.path(), recursive)
+            } else {
+                Ok(())
+            }
+        });
+        if result.is_ok() {
+            result = entry_result;
+        }
+    }
+    result
+}
+
+/// Faults in the pages of the buffer one chunk at a time, until the entire buffer is resident, or
+/// until the last strong reference to the buffer is dropped.
+fn fault_in_background(weak: Weak<FileBuffer>) {
+    let mut offset = 0;
+    while let Some(fbuffer) = weak.upgrade() {
+        if offset >= fbuffer.len() { return; }
+
+        // Advise the kernel first so it can read the chunk with a single request, then touch
+        // every page of it, which blocks this thread, rather than the reader, until it is mapped.
+        let length = cmp::min(fbuffer.chunk_len_hint(), fbuffer.len() - offset);
+        fbuffer.prefetch(offset, length);
+        touch_pages(unsafe { fbuffer.buffer.add(offset) }, length, fbuffer.page_size);
+        offset += length;
+    }
+}
+
+/// Touches one byte in every page of the `length` bytes at `buffer`, to fault them in.
+fn touch_pages(buffer: *const u8, length: usize, page_size: usize) {
+    for offset in (0..length).step_by(page_size) {
+        unsafe { ptr::read_volatile(buffer.add(offset)); }
+    }
+}
+
+impl FileBuffer {
+    /// Returns the page-aligned start of the mapping, which precedes the buffer by `view_offset`.
+    fn mapping_start(&self) -> *const u8 {
+        self.buffer.wrapping_sub(self.view_offset)
+    }
+
+    /// Rounds a slice of the buffer outwards to page boundaries, and returns its offset relative
+    /// to the start of the mapping, and its length.
+    fn page_span(&self, offset: usize, length: usize) -> (usize, usize) {
+        let offset = self.view_offset + offset;
+        let aligned_offset = round_down_to(offset, self.page_size);
+        let aligned_length = round_up_to(length + (offset - aligned_offset), self.page_size);
+        (aligned_offset, aligned_length)
+    }
+
+    /// Resolves `range` into an `(offset, length)` pair. Unbounded ends resolve to the buffer
+    /// bounds. Panics if a bound lies past `usize::MAX`, which is outside of any buffer.
+    fn resolve_range<R: RangeBounds<usize>>(&self, range: R) -> (usize, usize) {
+        let out_of_bounds = "range lies outside of the buffer";
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1).expect(out_of_bounds),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1).expect(out_of_bounds),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.length,
+        };
+        assert!(start <= end, "range start must not exceed range end");
+        (start, end - start)
+    }
+
+    /// Returns a buffer of length zero, without touching the filesystem.
+    ///
+    /// This is useful as a placeholder, and in tests. Like the buffer for an empty file, it does
+    /// not map anything: it dereferences to an empty slice, the methods that advise the kernel
+    /// do nothing, and dropping it does not unmap anything. Unlike for an empty file, there is no
+    /// file, so `raw_fd()` returns `None`.
+    pub fn empty() -> FileBuffer {
+        // Mapping zero bytes does not map anything, so it cannot fail.
+        let (buffer, platform_data) = map_anonymous(0).expect("mapping zero bytes cannot fail");
+        FileBuffer {
+            page_size: get_page_size(),
+            buffer,
+            length: 0,
+            platform_data,
+            stats: None,
+            prefetch_backend: PrefetchBackend::Madvise,
+            view_offset: 0,
+            path: None,
+            file_len: 0,
+            open_advice: Advice::Normal,
+            sequential_guards: Mutex::new(0),
+        }
+    }
+
+    /// Maps the file at `path` into memory.
+    ///
+    /// This is equivalent to `OpenOptions::new().open(path)`. Use `OpenOptions` for more control
+    /// over how the file is opened.
+    ///
+    /// The length of the buffer is the length of the file at the time it is opened. If the file
+    /// grows later, the buffer does not: data appended to the file is not visible through it. Use
+    /// `is_stale()` to detect this, and `reload()` to map the file again.
+    ///
+    /// On Linux, `path` can also be a block device, such as `/dev/sdb` or a loop device, in which
+    /// case the entire device is mapped. Reading a block device usually requires elevated
+    /// privileges, or membership of the `disk` group. Character devices cannot be mapped, opening
+    /// one fails with an error of kind `InvalidInput`.
+    ///
+    /// Pipes, sockets, and terminals, such as `/dev/stdin` when input is piped into a program,
+    /// cannot be mapped either. These are read until their end into memory instead, so this blocks
+    /// until the writer closes the stream. For such inputs the buffer is an in-memory copy: it is
+    /// always entirely resident, and advice to drop pages has no effect on it. There is no file
+    /// to go back to, so the buffer cannot be reloaded, and it is never stale.
+    ///
+    /// The message of an error names the path, its kind is that of the underlying error.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileBuffer> {
+        OpenOptions::new().open(path)
+    }
+
+    /// Maps the file at `path` into memory, and applies the access pattern `advice` to it.
+    ///
+    /// This is equivalent to `OpenOptions::new().advise(advice).open(path)`. Advice is most
+    /// effective when it is given before the first access, which this guarantees. Failure to apply
+    /// the advice does not cause opening to fail.
+    pub fn open_with_hint<P: AsRef<Path>>(path: P, advice: Advice) -> io::Result<FileBuffer> {
+        OpenOptions::new().advise(advice).open(path)
+    }
+
+    /// Maps the file at `path` into memory, if it cannot be modified by arbitrary other users.
+    ///
+    /// This is equivalent to `OpenOptions::new().writable_policy(WritablePolicy::Refuse).open()`.
+    /// On Unix-ish platforms, a file that is writable by its group or by other users is refused
+    /// with an error of kind `PermissionDenied`. On Windows, a file that does not have the
+    /// read-only attribute is refused. See `WritablePolicy` to make the file read-only instead.
+    pub fn open_readonly_enforced<P: AsRef<Path>>(path: P) -> io::Result<FileBuffer> {
+        OpenOptions::new().writable_policy(WritablePolicy::Refuse).open(path)
+    }
+
+    /// Maps the file at `path` into memory, for reading it from start to end.
+    ///
+    /// This advises the kernel that the file will be read sequentially, so it reads ahead more
+    /// aggressively. On Linux, Android, and FreeBSD, the advice is applied to the file with
+    /// `posix_fadvise` right after it is opened, before it is mapped, so that even the first
+    /// readahead benefits from it. Then on all platforms the mapping is advised as
+    /// `Advice::Sequential`, as `open_with_hint()` does. Failure to apply the advice does not cause
+    /// opening to fail.
+    pub fn open_sequential<P: AsRef<Path>>(path: P) -> io::Result<FileBuffer> {
+        let path = path.as_ref();
+        open_naming_path(path, || {
+            let mut options = OpenOptions::new();
+            options.advise(Advice::Sequential);
+            let file = open_path(path, &options)?;
+            let _ = advise_fd(&file, 0, 0, Advice::Sequential);
+            Ok(FileBuffer::from_file(file, &options)?.with_path(path))
+        })
+    }
+
+    /// Maps the file at `path` into memory, and bundles it with a value derived from its bytes.
+    ///
+    /// The value may borrow from the bytes, for example when `f` parses the file into a structure
+    /// of zero-copy views. The returned holder owns both the buffer and the value, so it can be
+    /// stored and moved without `leak()`. See `MappedWith` for the requirements on the type of
+    /// the value. The compiler cannot infer that type from `f`, so it must be named, as in
+    /// `FileBuffer::map_with::<&'static [u8], _, _>(path, |bytes| &bytes[..4])`.
+    ///
+    /// This method is only available with the `mapped-with` feature enabled.
+    #[cfg(feature = "mapped-with")]
+    pub fn map_with<Y, P, F>(path: P, f: F) -> io::Result<MappedWith<Y>>
+    where
+        Y: for<'a> yoke::Yokeable<'a>,
+        P: AsRef<Path>,
+        F: for<'de> FnOnce(&'de [u8]) -> <Y as yoke::Yokeable<'de>>::Output,
+    {
+        Ok(MappedWith::new(FileBuffer::open(path)?, f))
+    }
+
+    /// Maps the file at `path` into memory, in a buffer that can be shared.
+    ///
+    /// This is equivalent to `FileBuffer::open(path).map(Arc::new)`. A `FileBuffer` is `Send` and
+    /// `Sync`, so it can be shared among threads. The file is unmapped when the last `Arc` is
+    /// dropped, and not before. A cache can hold on to a `Weak` reference, obtained with
+    /// `Arc::downgrade()`, to hand out the buffer for as long as it is in use elsewhere, without
+    /// keeping it mapped itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use filebuffer::FileBuffer;
+    ///
+    /// let fbuffer = FileBuffer::open_arc("src/lib.rs").unwrap();
+    /// let cache_entry = Arc::downgrade(&fbuffer);
+    ///
+    /// // As long as the buffer is in use, the cache can hand it out.
+    /// assert_eq!(&cache_entry.upgrade().unwrap()[3..13], &b"Filebuffer"[..]);
+    ///
+    /// // When the last user drops it, the file is unmapped.
+    /// drop(fbuffer);
+    /// assert!(cache_entry.upgrade().is_none());
+    /// ```
+    pub fn open_arc<P: AsRef<Path>>(path: P) -> io::Result<Arc<FileBuffer>> {
+        FileBuffer::open(path).map(Arc::new)
+    }
+
+    /// Maps all files in `paths` into memory, and advises the kernel to make them resident.
+    ///
+    /// This is useful at startup, to warm the page cache before the files are needed. The advice
+    /// is asynchronous: the kernel starts reading in a file and returns before it is done, so the
+    /// reads for all files overlap, even though the files are opened one by one. Returns the
+    /// buffers in the order of `paths`, or the first error that occurs while opening.
+    pub fn prewarm<P, I>(paths: I) -> io::Result<Vec<FileBuffer>>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = P>,
+    {
+        let mut fbuffers = Vec::new();
+        for path in paths {
+            let fbuffer = FileBuffer::open(path)?;
+            fbuffer.prefetch(0, fbuffer.len());
+            fbuffers.push(fbuffer);
+        }
+        Ok(fbuffers)
+    }
+
+    /// Maps all files in `paths` into memory.
+    ///
+    /// Returns the buffers in the order of `paths`, or the first error that occurs while opening,
+    /// after which the remaining paths are not opened. The message of the error names the path
+    /// that failed, its kind is that of the underlying error. Unlike `prewarm()`, this does not
+    /// prefetch anything. See `open_each()` to open all files regardless of failures.
+    pub fn open_all<P, I>(paths: I) -> io::Result<Vec<FileBuffer>>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = P>,
+    {
+        paths.into_iter().map(FileBuffer::open).collect()
+    }
+
+    /// Maps all files in `paths` into memory, and returns the result for every file.
+    ///
+    /// This is like `open_all()`, but a failure to open one file does not stop the others from
+    /// being opened. The results are in the order of `paths`, and like for `open_all()`, the
+    /// message of an error names the path that failed.
+    pub fn open_each<P, I>(paths: I) -> Vec<io::Result<FileBuffer>>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = P>,
+    {
+        paths.into_iter().map(FileBuffer::open).collect()
+    }
+
+    /// Maps the file at `path` into memory, giving up after `timeout`.
+    ///
+    /// On a network filesystem with an unresponsive server, opening a file can block for a long
+    /// time. This method opens and maps the file on a worker thread, and returns an error of kind
+    /// `TimedOut` if that does not complete within `timeout`.
+    ///
+    /// A blocked `open` or `mmap` cannot be cancelled. After a timeout the worker thread is
+    /// abandoned, and it lives on until the system call returns, which may be never. If it does
+    /// return, the file is unmapped and closed right away.
+    pub fn open_timeout<P: AsRef<Path>>(path: P, timeout: Duration) -> io::Result<FileBuffer> {
+        let path = path.as_ref().to_path_buf();
+        let (sender, receiver) = mpsc::channel();
+
+        // If the receiver is gone by the time the file is mapped, sending fails, and the buffer
+        // is dropped along with the error.
+        thread::Builder::new()
+            .name("filebuffer-open".to_string())
+            .spawn(move || { let _ = sender.send(FileBuffer::open(path)); })?;
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "opening the file timed out"))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::Other, "the thread opening the file panicked"))
+            }
+        }
+    }
+
+    /// Maps the file at `path` into memory, and faults it in on a background thread.
+    ///
+    /// Reading from a mapping blocks on a page fault when the page is not resident. For threads
+    /// that must not block, such as audio or control loop threads, this method maps the file and
+    /// returns right away, while a background thread prefetches the file and touches every page,
+    /// from front to back. Combined with `resident_len()` to check that a slice is resident before
+    /// reading it, the calling thread never blocks on disk I/O, except for the `open` and `mmap`
+    /// calls themselves, which may need to read file system metadata.
+    ///
+    /// A read that races ahead of the background thread can still fault. The background thread
+    /// holds only a weak reference to the buffer between chunks, and it stops when the buffer is
+    /// dropped. If it holds the last reference at that moment, the file is unmapped on the
+    /// background thread.
+    pub fn open_nonblocking<P: AsRef<Path>>(path: P) -> io::Result<Arc<FileBuffer>> {
+        let fbuffer = Arc::new(FileBuffer::open(path)?);
+        let weak = Arc::downgrade(&fbuffer);
+        thread::Builder::new()
+            .name("filebuffer-fault-in".to_string())
+            .spawn(move || fault_in_background(weak))?;
+        Ok(fbuffer)
+    }
+
+    /// Maps the file at `path`, relative to the directory `dir`, into memory.
+    ///
+    /// This is equivalent to `OpenOptions::new().open_at(dir, path)`. See
+    /// `OpenOptions::open_at()` for details. This method is only available on Unix-ish platforms.
+    #[cfg(unix)]
+    pub fn open_at<D: AsRawFd, P: AsRef<Path>>(dir: &D, path: P) -> io::Result<FileBuffer> {
+        OpenOptions::new().open_at(dir, path)
+    }
+
+    /// Maps the file at `path` into memory through a file mapping object named `name`.
+    ///
+    /// This is equivalent to `OpenOptions::new().mapping_name(name).open(path)`. Processes that
+    /// map a file with the same name share the same mapping object. See
+    /// `OpenOptions::mapping_name()` for details.
+    ///
+    /// This method is only available on Windows.
+    #[cfg(windows)]
+    pub fn open_named<P: AsRef<Path>>(path: P, name: &str) -> io::Result<FileBuffer> {
+        OpenOptions::new().mapping_name(name).open(path)
+    }
+
+    /// Maps the file at `path` into memory as a writable copy, and applies `patches` to it.
+    ///
+    /// Every patch is an `(offset, bytes)` pair, and `bytes` is copied into the buffer at
+    /// `offset`, in the order of the patches. The patches are applied to a private copy-on-write
+    /// mapping, so they are never written to the file, and only the patched pages are copied.
+    /// This is useful to test parsers against mutated inputs, or to overlay a few changes onto a
+    /// read-only data file. See also `FileBufferMut::open_cow()`.
+    ///
+    /// Returns an error of kind `InvalidInput` if a patch does not lie entirely within the file.
+    pub fn open_cow_patched<P: AsRef<Path>>(
+        path: P,
+        patches: &[(usize, &[u8])],
+    ) -> io::Result<FileBufferMut> {
+        let mut fbuffer = FileBufferMut::open_cow(path)?;
+        for &(offset, bytes) in patches {
+            match offset.checked_add(bytes.len()) {
+                Some(end) if end <= fbuffer.len() => fbuffer[offset..end].copy_from_slice(bytes),
+                _ => {
+                    let msg = "patch lies outside of the file";
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                }
+            }
+        }
+        Ok(fbuffer)
+    }
+
+    /// Reads the file at `path` into memory with direct I/O, bypassing the page cache.
+    ///
+    /// Reading a file through a mapping populates the page cache. When processing a file much
+    /// larger than physical memory once, this can evict everything else that the system needs
+    /// from the cache. This method instead opens the file with `O_DIRECT`, and reads it into
+    /// page-aligned heap memory. The result is not a mapping of the file: the entire file is
+    /// read up front, it counts towards the memory usage of the process, and later changes to the
+    /// file do not show up in the buffer. Methods that advise on the file, such as
+    /// `advise_file()`, have no effect, but the buffer otherwise behaves like any other.
+    ///
+    /// Direct I/O requires reads to be aligned to the logical block size of the device, which
+    /// this method takes care of by reading into page-aligned memory. Not all filesystems support
+    /// direct I/O, for example tmpfs does not; opening a file on such a filesystem fails with
+    /// `EINVAL`. This method is only available on Linux and Android.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn open_direct<P: AsRef<Path>>(path: P) -> io::Result<FileBuffer> {
+        let path = path.as_ref();
+        open_naming_path(path, || {
+            let (buffer, length, platform_data) = read_direct(path)?;
+            let fbuffer = FileBuffer {
+                page_size: get_page_size(),
+                buffer,
+                length,
+                platform_data,
+                stats: None,
+                prefetch_backend: PrefetchBackend::Madvise,
+                view_offset: 0,
+                path: Some(path.to_path_buf()),
+                file_len: length as u64,
+                open_advice: Advice::Normal,
+                sequential_guards: Mutex::new(0),
+            };
+            Ok(fbuffer)
+        })
+    }
+
+    /// Maps a file into memory, and locks all of it into physical memory.
+    ///
+    /// The pages of the buffer are locked with `mlock` on Unix-ish platforms and `VirtualLock` on
+    /// Windows, and then faulted in, so accessing the buffer never blocks on disk afterwards. The
+    /// lock is held until the buffer is dropped. Locked memory is limited by `RLIMIT_MEMLOCK` on
+    /// Unix-ish platforms and by the working set size on Windows; when the file does not fit, the
+    /// error from the operating system is returned, for example `ENOMEM` or `EPERM`.
+    ///
+    /// Before returning, this verifies with `resident_len()` that the entire file is resident,
+    /// and returns an error otherwise. On Windows, and with the `no-residency` feature, residency
+    /// cannot be queried, so there the check relies on the lock alone.
+    ///
+    /// Streams such as pipes cannot be mapped, and they are read into memory instead. The lock
+    /// would outlive such a buffer, so opening a stream with this method fails with an error of
+    /// kind `InvalidInput`.
+    pub fn open_locked<P: AsRef<Path>>(path: P) -> io::Result<FileBuffer> {
+        let fbuffer = FileBuffer::open(path)?;
+
+        // There is nothing to lock for empty files.
+        if fbuffer.buffer.is_null() { return Ok(fbuffer); }
+
+        if is_heap_backed(&fbuffer.platform_data) {
+            let msg = "cannot lock a stream that was read into memory";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+
+        let (aligned_offset, aligned_length) = fbuffer.page_span(0, fbuffer.length);
+        let start = unsafe { fbuffer.mapping_start().add(aligned_offset) };
+        lock_memory(start, aligned_length)?;
+        touch_pages(start, aligned_length, fbuffer.page_size);
+
+        if fbuffer.resident_len(0, fbuffer.length) < fbuffer.length {
+            let msg = "the file could not be made entirely resident";
+            return Err(io::Error::new(io::ErrorKind::Other, msg));
+        }
+
+        Ok(fbuffer)
+    }
+
+    /// Maps `length` bytes of the file at `path` into memory, starting at `offset`.
+    ///
+    /// This is equivalent to `OpenOptions::new().range(offset, length).open(path)`. See
+    /// `OpenOptions::range()` for the requirements on `offset`.
+    pub fn open_range<P: AsRef<Path>>(
+        path: P,
+        offset: u64,
+        length: usize,
+    ) -> io::Result<FileBuffer> {
+        OpenOptions::new().range(offset, length).open(path)
+    }
+
+    /// Maps `length` bytes of the file at `path` into memory, starting at `offset`, which need not
+    /// be aligned.
+    ///
+    /// Unlike `open_range()`, this does not require `offset` to be a multiple of the allocation
+    /// granularity. The file is mapped from the enclosing boundary instead, and the buffer
+    /// dereferences to the slice that starts at byte `offset`. The few bytes before it are mapped,
+    /// but they are not part of the buffer.
+    ///
+    /// Returns `Ok(None)` if `offset` lies past the end of the file, so a caller that maps windows
+    /// of a file can tell that it has reached the end. If `offset` lies within the file but the
+    /// range extends past its end, this returns an error of kind `InvalidInput`. Other errors are
+    /// those of opening and mapping the file.
+    pub fn try_open_range<P: AsRef<Path>>(
+        path: P,
+        offset: u64,
+        length: usize,
+    ) -> io::Result<Option<FileBuffer>> {
+        let path = path.as_ref();
+        open_naming_path(path, || {
+            let options = OpenOptions::new();
+            let file = open_path(path, &options)?;
+            let file_len = get_file_len(&file)?;
+
+            if offset > file_len {
+                return Ok(None);
+            }
+
+            if length as u64 > file_len - offset {
+                let msg = format!(
+                    "range of {} bytes at offset {} extends past the end of the file, at {} bytes",
+                    length, offset, file_len,
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+
+            // The distance to the boundary is less than the allocation granularity, so it fits.
+            let view_offset = (offset % get_allocation_granularity() as u64) as usize;
+            let aligned_offset = offset - view_offset as u64;
+            let mapped_len = if length == 0 { 0 } else { view_offset + length };
+
+            let mut fbuffer =
+                FileBuffer::map_range(file, aligned_offset, mapped_len, file_len, &options)?;
+            if !fbuffer.buffer.is_null() {
+                fbuffer.buffer = unsafe { fbuffer.buffer.add(view_offset) };
+                fbuffer.length = length;
+                fbuffer.view_offset = view_offset;
+            }
+            Ok(Some(fbuffer.with_path(path)))
+        })
+    }
+
+    /// Maps the aligned region of the file at `path` that covers `length` bytes at `offset`, and
+    /// returns it together with the position of byte `offset` in the buffer.
+    ///
+    /// This is a lower-level counterpart of `try_open_range()`, for callers that manage windows
+    /// of a file themselves. Rather than hiding the bytes before `offset`, the buffer starts at
+    /// the aligned base of the mapping, so its offsets map one-to-one onto the pages that
+    /// `prefetch()`, `advise()`, and `resident_len()` operate on. The relationships are:
+    ///
+    /// * The buffer starts at file offset `offset - in_view_offset`, which is a multiple of the
+    ///   page size on Unix-ish platforms, and of the allocation granularity (typically 64 KiB)
+    ///   on Windows, as `OpenOptions::range()` requires.
+    /// * The returned `in_view_offset` is less than that alignment, and the requested bytes are
+    ///   `fbuffer[in_view_offset..]`, which is exactly `length` bytes long.
+    /// * The buffer itself, and therefore every page of it, starts on a page boundary in memory.
+    ///
+    /// Like `open_range()`, the mapping is advised as `Advice::Random`. If the requested range
+    /// does not lie within the file, this returns an error of kind `InvalidInput`.
+    pub fn open_region_aligned<P: AsRef<Path>>(
+        path: P,
+        offset: u64,
+        length: usize,
+    ) -> io::Result<(FileBuffer, usize)> {
+        // The distance to the boundary is less than the allocation granularity, so it fits.
+        let in_view_offset = (offset % get_allocation_granularity() as u64) as usize;
+        let mapped_len = in_view_offset.checked_add(length).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "range lies outside of the file")
+        })?;
+        let fbuffer = FileBuffer::open_range(path, offset - in_view_offset as u64, mapped_len)?;
+        Ok((fbuffer, in_view_offset))
+    }
+
+    /// Maps the file at `path` into memory if it was modified after `since`.
+    ///
+    /// Returns `Ok(None)` if the modification time of the file is not later than `since`. This is
+    /// useful for incremental processing, to skip inputs that did not change since the previous
+    /// run. The modification time is read from the opened file, so it belongs to the file that is
+    /// mapped, even if the path is replaced concurrently. The metadata also provides the length of
+    /// the file, so this takes no more system calls than `open()` does.
+    ///
+    /// On platforms where the modification time is not available, this returns an error.
+    pub fn open_if_newer<P: AsRef<Path>>(
+        path: P,
+        since: SystemTime,
+    ) -> io::Result<Option<FileBuffer>> {
+        let path = path.as_ref();
+        open_naming_path(path, || {
+            let mut options = OpenOptions::new();
+            let file = open_path(path, &options)?;
+            let metadata = file.metadata()?;
+
+            if metadata.modified()? <= since {
+                return Ok(None);
+            }
+
+            // The length of other kinds of files, such as block devices, is not in the metadata.
+            if metadata.is_file() {
+                options.known_len(metadata.len());
+            }
+
+            let fbuffer = FileBuffer::from_file(file, &options)?;
+            Ok(Some(fbuffer.with_path(path)))
+        })
+    }
+
+    /// Opens the file at `path` with the standard library `opts`, and maps it into memory.
+    ///
+    /// This is an escape hatch for open flags that `OpenOptions` does not model, such as
+    /// `custom_flags` on Unix, or `share_mode` on Windows. The file is mapped read-only, so the
+    /// file must be opened with read access, otherwise mapping fails. To ensure that, `read(true)`
+    /// is set on a copy of `opts` before opening; `opts` itself is not modified. Other than that,
+    /// the options are passed to the operating system as they are.
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        opts: &fs::OpenOptions,
+    ) -> io::Result<FileBuffer> {
+        let path = path.as_ref();
+        open_naming_path(path, || {
+            let file = opts.clone().read(true).open(path)?;
+            let fbuffer = FileBuffer::from_file(file, &OpenOptions::new())?;
+            Ok(fbuffer.with_path(path))
+        })
+    }
+
+    /// Maps exactly `len` bytes of the file at `path` into memory, starting at the beginning.
+    ///
+    /// This is useful when the length reported by the file metadata is unreliable, for example on
+    /// some network filesystems, but the number of bytes to map is known. The length is checked
+    /// against the end of the file, found by seeking to it, when the file is mapped. Accessing a
+    /// mapping beyond the end of the file causes undefined behavior, so if `len` exceeds the
+    /// length of the file, this returns an error of kind `InvalidInput` instead.
+    pub fn open_with_len<P: AsRef<Path>>(path: P, len: usize) -> io::Result<FileBuffer> {
+        let path = path.as_ref();
+        open_naming_path(path, || {
+            use std::io::Seek;
+
+            let options = OpenOptions::new();
+            let mut file = open_path(path, &options)?;
+            let file_len = file.seek(io::SeekFrom::End(0))?;
+
+            if len as u64 > file_len {
+                let msg = "length exceeds the length of the file";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+
+            let fbuffer = FileBuffer::map_range(file, 0, len, file_len, &options)?;
+            Ok(fbuffer.with_path(path))
+        })
+    }
+
+    /// Records `path` as the path that the buffer was opened from.
+    fn with_path(mut self, path: &Path) -> FileBuffer {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+
+    /// Maps an opened file into memory, as configured by `options`.
+    fn from_file(file: fs::File, options: &OpenOptions) -> io::Result<FileBuffer> {
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+
+        let result = FileBuffer::from_file_untraced(file, options);
+
+        #[cfg(feature = "tracing")]
+        match result {
+            Ok(ref fbuffer) => tracing::debug!(
+                length = fbuffer.length,
+                file_len = fbuffer.file_len,
+                read = is_heap_backed(&fbuffer.platform_data),
+                elapsed_us = start.elapsed().as_micros() as u64,
+                "mapped file",
+            ),
+            Err(ref err) => tracing::debug!(error = %err, "failed to map file"),
+        }
+
+        result
+    }
+
+    /// Implements `from_file()`, which traces the outcome.
+    fn from_file_untraced(file: fs::File, options: &OpenOptions) -> io::Result<FileBuffer> {
+        // A known length implies a regular file, so it saves the system calls that query this.
+        if options.known_len.is_none() && is_stream(&file)? {
+            return FileBuffer::from_stream(file, options);
+        }
+
+        // A known length saves the system call that queries it. Debug builds make the call
+        // anyway, to catch a wrong length before it turns into `SIGBUS`.
+        let file_len = match options.known_len {
+            Some(len) if cfg!(debug_assertions) => {
+                if len > get_file_len(&file)? {
+                    let msg = "known length exceeds the length of the file";
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                }
+                len
+            }
+            Some(len) => len,
+            None => get_file_len(&file)?,
+        };
+
+        if options.forbid_empty && file_len == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file is empty"));
+        }
+
+        let (offset, length) = match options.range {
+            Some((offset, length)) => {
+                let end = offset.checked_add(length as u64);
+                if end.map_or(true, |end| end > file_len) {
+                    let msg = "range lies outside of the file";
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                }
+                if offset % get_allocation_granularity() as u64 != 0 {
+                    let msg = "range offset is not a multiple of the allocation granularity";
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                }
+                (offset, length as u64)
+            }
+            None => (0, file_len),
+        };
+
+        if length > isize::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::Other, "file is larger than address space"));
+        }
+
+        FileBuffer::map_range(file, offset, length as usize, file_len, options)
+    }
+
+    /// Reads a stream that cannot be mapped, such as a pipe, into memory until its end.
+    fn from_stream(mut file: fs::File, options: &OpenOptions) -> io::Result<FileBuffer> {
+        if options.range.is_some() {
+            let msg = "cannot map a range of a pipe or other stream";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+
+        let mut bytes = Vec::new();
+        io::Read::read_to_end(&mut file, &mut bytes)?;
+
+        if options.forbid_empty && bytes.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file is empty"));
+        }
+
+        let length = bytes.len();
+        let (buffer, platform_data) = if length == 0 {
+            map_file(file, 0, 0, options)?
+        } else {
+            let mut heap = HeapPages::new(length, get_page_size());
+            heap.as_mut_slice().copy_from_slice(&bytes);
+            // The stream has been read to its end, there is nothing left to do with it.
+            adopt_heap(None, heap)
+        };
+
+        Ok(FileBuffer {
+            page_size: get_page_size(),
+            buffer,
+            length,
+            platform_data,
+            stats: if options.track_stats { Some(Box::new(StatsCounters::new())) } else { None },
+            prefetch_backend: PrefetchBackend::Madvise,
+            view_offset: 0,
+            path: None,
+            file_len: length as u64,
+            open_advice: Advice::Normal,
+            sequential_guards: Mutex::new(0),
+        })
+    }
+
+    /// Maps `length` bytes of an opened file of `file_len` bytes into memory, starting at
+    /// `offset`. The range must lie within the file.
+    fn map_range(
+        file: fs::File,
+        offset: u64,
+        length: usize,
+        file_len: u64,
+        options: &OpenOptions,
+    ) -> io::Result<FileBuffer> {
+        // Reading a small file is cheaper than mapping it, and it takes no address space. Options
+        // that only make sense for a mapping force a mapping.
+        let read = offset == 0
+            && length as u64 == file_len
+            && length > 0
+            && length <= options.read_threshold
+            && options.range.is_none()
+            && !options.requires_mapping();
+
+        let (buffer, platform_data) = if read {
+            read_file(file, length)?
+        } else {
+            map_file(file, offset, length, options)?
+        };
+        let mut fbuffer = FileBuffer {
+            page_size: get_page_size(),
+            buffer,
+            length,
+            platform_data,
+            stats: if options.track_stats { Some(Box::new(StatsCounters::new())) } else { None },
+            prefetch_backend: PrefetchBackend::Madvise,
+            view_offset: 0,
+            path: None,
+            file_len,
+            open_advice: Advice::Normal,
+            sequential_guards: Mutex::new(0),
+        };
+
+        // A range is almost always a window for random access into a larger structure, for which
+        // the default readahead would only pull in neighboring pages needlessly.
+        let default_advice = if options.range.is_some() { Some(Advice::Random) } else { None };
+        if let Some(advice) = options.advice.or(default_advice) {
+            if !fbuffer.buffer.is_null() && !read {
+                // Advice is only a hint, failing to apply it is not a reason to fail opening.
+                let aligned_length = round_up_to(fbuffer.length, fbuffer.page_size);
+                let _ = advise(fbuffer.buffer, &fbuffer.platform_data, aligned_length, advice);
+            }
+            if advice == Advice::Sequential || advice == Advice::Random {
+                fbuffer.open_advice = advice;
+            }
+        }
+
+        Ok(fbuffer)
+    }
+
+    /// Changes the length of the mapping to `new_length` bytes of `file`, which must be the file
+    /// that the buffer maps, starting at offset 0.
+    fn remap(
+        &mut self,
+        file: &fs::File,
+        new_length: usize,
+        options: &OpenOptions,
+    ) -> io::Result<()> {
+        debug_assert_eq!(self.view_offset, 0, "only buffers mapped from offset 0 can be remapped");
+
+        // On Unix-ish platforms the existing mapping can be resized directly, which on Linux can
+        // often be done in place.
+        #[cfg(unix)]
+        {
+            if !self.buffer.is_null() && new_length > 0 && !is_heap_backed(&self.platform_data) {
+                self.buffer =
+                    remap_file(self.buffer, new_length, file, &mut self.platform_data, options)?;
+                self.length = new_length;
+                return Ok(());
+            }
+        }
+
+        // Otherwise, map the file again; assigning drops the old mapping.
+        let (buffer, platform_data) = map_file(file.try_clone()?, 0, new_length, options)?;
+        *self = FileBuffer {
+            page_size: self.page_size,
+            buffer,
+            length: new_length,
+            platform_data,
+            stats: self.stats.take(),
+            prefetch_backend: self.prefetch_backend,
+            view_offset: 0,
+            path: self.path.take(),
+            file_len: new_length as u64,
+            open_advice: Advice::Normal,
+            sequential_guards: Mutex::new(0),
+        };
+        Ok(())
+    }
+
+    /// Maps the file again at its current size, to pick up changes made to it after it was mapped.
+    ///
+    /// The file is not opened again: the buffer keeps the file open, and this maps that same file.
+    /// The new mapping covers the entire file, also when the buffer was opened for a range of it,
+    /// and it is mapped with the default options. Statistics and the prefetch backend carry over.
+    /// Because this takes `&mut self`, no slices of the old mapping can be alive while it is
+    /// replaced. If mapping the file fails, the buffer is left unchanged.
+    ///
+    /// If the file was deleted, or replaced by renaming a different file over it, the buffer still
+    /// refers to the original file, so reloading does not pick up the replacement. To follow a
+    /// path rather than a file, open the path again instead. On Unix-ish platforms a deleted file
+    /// lives on for as long as the buffer keeps it open. On Windows a mapped file cannot be
+    /// deleted. Buffers that do not map a file, such as `FileBuffer::empty()`, cannot be reloaded,
+    /// and this returns an error of kind `InvalidInput` for them.
+    pub fn reload(&mut self) -> io::Result<()> {
+        let file = clone_file(&self.platform_data)?;
+        let mut fbuffer = FileBuffer::from_file(file, &OpenOptions::new())?;
+        fbuffer.stats = self.stats.take();
+        fbuffer.prefetch_backend = self.prefetch_backend;
+        fbuffer.path = self.path.take();
+
+        // Assigning drops the old mapping.
+        *self = fbuffer;
+        Ok(())
+    }
+
+    /// Returns the number of bytes resident in physical memory, starting from `offset`.
+    ///
+    /// The slice `[offset..offset + resident_len]` can be accessed without causing page faults or
+    /// disk access. Note that this is only a snapshot, and the kernel might decide to evict pages
+    /// or make them resident at any time.
+    ///
+    /// The returned resident length is at most `length`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    ///
+    /// # Remarks
+    ///
+    /// Windows does not expose a mechanism to query which pages are resident in physical
+    /// memory. Therefore this function optimistically claims that the entire range is resident
+    /// on Windows. The same holds on Unix-ish platforms where the kernel does not implement
+    /// `mincore`, which is the case in some sandboxes and emulators.
+    ///
+    /// It also holds on every platform when the crate is built with the `no-residency`
+    /// feature, which compiles out the use of `mincore`. This affects `resident_len()` and all
+    /// other methods that query residency, such as `resident_bitmap()` and `resident_reader()`.
+    ///
+    /// On Linux, files on tmpfs, such as those in `/dev/shm`, live in memory. For those, this
+    /// function reports the entire range as resident without querying the kernel. This is
+    /// inaccurate only if the pages were swapped out. Mappings of such files are also advised to
+    /// use transparent huge pages.
+    pub fn resident_len(&self, offset: usize, length: usize) -> usize {
+        // There is a tradeoff here: to store residency information, we need an array of booleans.
+        // The requested range can potentially be very large and it is only known at runtime. We
+        // could allocate a vector here, but that requires a heap allocation just to get residency
+        // information (which might in turn cause a page fault). Instead, check at most 32 pages at
+        // once. This means more syscalls for large ranges, but it saves us the heap allocation,
+        // and for ranges up to 32 pages (128 KiB typically) there is only one syscall.
+        self.resident_len_batched::<32>(offset, length)
+    }
+
+    /// Returns the number of bytes resident in physical memory, starting from `offset`, checking
+    /// at most `N` pages per syscall.
+    ///
+    /// This is like `resident_len()`, which checks 32 pages at once, but the number of pages per
+    /// batch is a parameter. The residency information for a batch is stored on the stack, so a
+    /// larger `N` means fewer syscalls for large ranges, at the cost of more stack space.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer, or if `N` is 0.
+    pub fn resident_len_batched<const N: usize>(&self, offset: usize, length: usize) -> usize {
+        assert!(N > 0, "the batch size must be at least one page");
+        self.resident_len_with_scratch(offset, length, &mut [false; N])
+    }
+
+    /// Returns the number of bytes resident in physical memory, starting from `offset`, using
+    /// `scratch` to store the residency of one batch of pages.
+    ///
+    /// This is like `resident_len_batched()`, but the caller provides the storage, so its size
+    /// need not be known at compile time, and one large buffer can be reused across many calls
+    /// without allocating for every call. Every syscall checks at most `scratch.len()` pages. The
+    /// contents of `scratch` on entry do not matter, and on return they are unspecified.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer, or if `scratch` is empty.
+    pub fn resident_len_with_scratch(
+        &self,
+        offset: usize,
+        length: usize,
+        scratch: &mut [bool],
+    ) -> usize {
+        assert!(!scratch.is_empty(), "the scratch buffer must hold at least one page");
+
+        // The specified offset and length must lie within the buffer.
+        assert!(offset + length <= self.length);
+
+        // This is a no-op for empty files.
+        if self.buffer.is_null() { return 0; }
+
+        // Files that live in memory are resident, there is no need to ask `mincore`.
+        if is_memory_backed(&self.platform_data) { return length; }
+
+        resident_len_impl(
+            self.mapping_start(),
+            offset + self.view_offset,
+            length,
+            self.page_size,
+            scratch,
+            get_resident,
+        )
+    }
+
+    /// Returns the number of bytes resident in physical memory, starting from the start of `range`.
+    ///
+    /// This is like `resident_len()`, but it takes a range rather than an offset and length. For
+    /// example, `fbuffer.resident_len_range(..4096)` checks the first 4096 bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn resident_len_range<R: RangeBounds<usize>>(&self, range: R) -> usize {
+        let (offset, length) = self.resolve_range(range);
+        self.resident_len(offset, length)
+    }
+
+    /// Returns the offset of the first byte at or after `offset` that is not resident in physical
+    /// memory, or `None` if everything from `offset` to the end of the buffer is resident.
+    ///
+    /// This is the dual of `resident_len()`: it tells a prefetch scheduler where the next gap in
+    /// residency is. Like `resident_len()`, it queries residency in batches, and it stops at the
+    /// first page that is not resident. On Windows, everything is reported as resident.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` lies past the end of the buffer.
+    pub fn next_nonresident(&self, offset: usize) -> Option<usize> {
+        assert!(offset <= self.length);
+        let resident_len = self.resident_len(offset, self.length - offset);
+        if offset + resident_len == self.length {
+            None
+        } else {
+            Some(offset + resident_len)
+        }
+    }
+
+    /// Returns the bytes starting at `offset` that are resident in physical memory, up to the
+    /// first byte that is not.
+    ///
+    /// This is `&fbuffer[offset..offset + resident_len(offset, len - offset)]` in one call: the
+    /// returned slice can be read without blocking on page faults, but it may be empty. Unlike
+    /// `poll_resident_chunk()`, this does not prefetch anything, and the slice is not capped. Like
+    /// `resident_len()`, on Windows this returns everything from `offset` to the end.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` lies past the end of the buffer.
+    pub fn resident_slice(&self, offset: usize) -> &[u8] {
+        assert!(offset <= self.length);
+        let resident_len = self.resident_len(offset, self.length - offset);
+        &self[offset..offset + resident_len]
+    }
+
+    /// Returns the residency of every page of the buffer as a bitmap.
+    ///
+    /// Bit `i % 64` of element `i / 64` is set if page `i` is resident in physical memory. The
+    /// bitmap has one bit per page, `ceil(len / page_size())` in total, rounded up to a
+    /// multiple of 64 with trailing zero bits.
+    ///
+    /// Unlike `resident_len()`, this allocates, and it queries the entire buffer rather than
+    /// stopping at the first page that is not resident. Like `resident_len()`, on Windows every
+    /// page is reported as resident.
+    pub fn resident_bitmap(&self) -> Vec<u64> {
+        // This is a no-op for empty files.
+        if self.buffer.is_null() { return Vec::new(); }
+
+        let mut residency = [false; 32];
+        resident_bitmap_impl(
+            self.mapping_start(),
+            self.view_offset + self.length,
+            self.page_size,
+            &mut residency,
+            get_resident,
+        )
+    }
+
+    /// Returns the number of pages of the buffer that are resident in physical memory, without
+    /// allocating, or `None` on platforms where residency cannot be observed.
+    fn count_resident_pages(&self) -> Option<usize> {
+        if cfg!(any(windows, feature = "no-residency")) { return None; }
+
+        // There are no pages for empty files.
+        if self.buffer.is_null() { return Some(0); }
+
+        let mapping_len = round_up_to(self.view_offset + self.length, self.page_size);
+        let mut residency = [false; 32];
+        let mut count = 0;
+        for batch_start in (0..mapping_len).step_by(residency.len() * self.page_size) {
+            let num_pages = cmp::min(residency.len(), (mapping_len - batch_start) / self.page_size);
+            let batch_residency = &mut residency[..num_pages];
+            let batch_buffer = self.mapping_start().wrapping_add(batch_start);
+            get_resident(batch_buffer, num_pages * self.page_size, batch_residency);
+            count += batch_residency.iter().filter(|&&resident| resident).count();
+        }
+
+        Some(count)
+    }
+
+    /// Returns the offsets of the pages in a slice of the buffer that are resident in physical
+    /// memory.
+    ///
+    /// Every offset is the start of a resident page, relative to the start of the buffer. The
+    /// slice is rounded outwards to page boundaries, so the first offset can lie before `offset`.
+    /// For a buffer that starts in the middle of a page, see `try_open_range()`, the page that
+    /// contains the first byte is reported at offset 0. The offsets are in ascending order.
+    ///
+    /// This queries residency like `resident_bitmap()` does, and like it, on Windows every page is
+    /// reported as resident.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn resident_pages(&self, offset: usize, length: usize) -> Vec<usize> {
+        // The specified offset and length must lie within the buffer.
+        assert!(offset + length <= self.length);
+
+        // This is a no-op for empty files.
+        if self.buffer.is_null() || length == 0 { return Vec::new(); }
+
+        let (aligned_offset, aligned_length) = self.page_span(offset, length);
+        let mut residency = [false; 32];
+        let bitmap = resident_bitmap_impl(
+            unsafe { self.mapping_start().add(aligned_offset) },
+            aligned_length,
+            self.page_size,
+            &mut residency,
+            get_resident,
+        );
+
+        (0..aligned_length / self.page_size)
+            .filter(|page| bitmap[page / 64] & (1 << (page % 64)) != 0)
+            .map(|page| (aligned_offset + page * self.page_size).saturating_sub(self.view_offset))
+            .collect()
+    }
+
+    /// Returns the system page size.
+    ///
+    /// Residency, prefetching, and eviction all work with page granularity. Offsets passed to
+    /// `OpenOptions::range()` must be aligned to it (on Windows to the allocation granularity,
+    /// which is a multiple of it).
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Returns the path that the buffer was opened from.
+    ///
+    /// This is the path as it was passed to `open()` or a similar constructor, it is not made
+    /// absolute, and for `OpenOptions::open_at()` it is relative to the directory. If the file
+    /// was renamed or deleted after it was opened, the path no longer refers to it. Buffers that
+    /// were not opened from a path, such as `FileBuffer::empty()` and buffers converted from a
+    /// `memmap2::Mmap`, return `None`.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the file descriptor of the mapped file, for system calls that this crate does not
+    /// wrap, such as `fstat` or `fcntl`.
+    ///
+    /// The descriptor is owned by the buffer and stays open for as long as the buffer lives. It
+    /// must not be closed by the caller. Writing to the file through it changes the contents of
+    /// the buffer, which violates the assumption that the mapped file is not modified. Buffers
+    /// that have no file, such as `FileBuffer::empty()`, buffers converted from a
+    /// `memmap2::Mmap`, and buffers of streams that were read into memory, return `None`.
+    ///
+    /// `FileBuffer` does not implement `AsFd`, because that trait requires Rust 1.63, which is
+    /// newer than the minimum supported version of this crate. It does not implement `AsRawFd`
+    /// either, because that would have to panic for buffers without a file.
+    #[cfg(unix)]
+    pub fn raw_fd(&self) -> Option<RawFd> {
+        get_raw_fd(&self.platform_data)
+    }
+
+    /// Returns the handle of the mapped file, for system calls that this crate does not wrap.
+    ///
+    /// The handle is owned by the buffer and stays open for as long as the buffer lives. It must
+    /// not be closed by the caller. Writing to the file through it changes the contents of the
+    /// buffer, which violates the assumption that the mapped file is not modified. Buffers that
+    /// have no file return `None`, see `raw_fd()`.
+    ///
+    /// For the same reasons as for `raw_fd()`, `FileBuffer` does not implement `AsHandle` or
+    /// `AsRawHandle`.
+    #[cfg(windows)]
+    pub fn raw_handle(&self) -> Option<RawHandle> {
+        get_raw_handle(&self.platform_data)
+    }
+
+    /// Returns the length of the underlying file, as it was when the buffer was mapped.
+    ///
+    /// For a buffer that maps a range of a file, such as one opened with `open_range()` or
+    /// `try_open_range()`, `len()` is the length of the range, and this is the length of the
+    /// entire file. For a buffer that maps an entire file, the two are equal. The length is not
+    /// queried again, so it does not reflect later changes to the file; see `reload()`. For
+    /// buffers that do not map a file, such as `FileBuffer::empty()`, this equals `len()`.
+    pub fn file_len(&self) -> u64 {
+        self.file_len
+    }
+
+    /// Returns the length of the buffer as `u64`.
+    ///
+    /// This is `len()` widened to `u64`, the type of file offsets and of `file_len()`. Code that
+    /// computes offsets into files larger than the address space, on 32-bit platforms, can stay
+    /// in `u64` throughout, rather than converting back and forth, which risks truncation.
+    pub fn len_u64(&self) -> u64 {
+        self.length as u64
+    }
+
+    /// Returns whether the length of the underlying file changed since the buffer was mapped.
+    ///
+    /// The buffer never grows or shrinks by itself: when a different process appends to the file,
+    /// the buffer still covers only the bytes that were there when it was mapped, and the new
+    /// bytes are not visible through it. This method compares the current length of the file with
+    /// `file_len()`, which costs one `fstat` call, so a watcher can cheaply decide when to call
+    /// `reload()`. For buffers that do not map a file, this returns `false`.
+    ///
+    /// Only the length is compared. A file that was modified in place without changing its length
+    /// is not detected as stale, but such changes are usually visible in the buffer anyway,
+    /// because the buffer maps the pages of the file that the kernel caches.
+    pub fn is_stale(&self) -> io::Result<bool> {
+        match get_current_file_len(&self.platform_data)? {
+            Some(len) => Ok(len != self.file_len),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns a good chunk length for processing the file in chunks.
+    ///
+    /// When the kernel makes the file resident in physical memory, it does so with page
+    /// granularity, but in practice it reads ahead in much larger units, typically 128 KiB.
+    /// Processing the file in chunks of a single page underutilizes that readahead, and queries
+    /// residency more often than necessary. Therefore this returns the readahead window, rounded
+    /// up to a multiple of the page size. Use `page_size()` for the page size itself.
+    ///
+    /// On Linux, the window is `read_ahead_kb` of the device that holds the file, as found in
+    /// `/sys`. It is read on the first call, and cached in the buffer. This is the same window
+    /// that `prefetch()` sizes its `readahead` calls to. Elsewhere, for files that are not on a
+    /// block device, and for buffers that were read into memory, this returns 128 KiB.
+    pub fn chunk_len_hint(&self) -> usize {
+        let readahead_len = get_readahead_len(&self.platform_data).unwrap_or(128 * 1024);
+        round_up_to(readahead_len, self.page_size)
+    }
+
+    /// Returns the resident bytes of the chunk starting at `offset`, without blocking on page
+    /// faults.
+    ///
+    /// This is a building block for event loops that process the file as it becomes resident. If
+    /// the byte at `offset` is resident, this returns `Poll::Ready` with the resident bytes
+    /// starting at `offset`, at most `chunk_len_hint()` of them. Otherwise it returns
+    /// `Poll::Pending`. Either way, the part of the chunk that is not resident is prefetched, so
+    /// the caller can do other work and poll again later. There is no waker, because the kernel
+    /// does not signal when pages become resident. At the end of the buffer, this returns an empty
+    /// ready slice.
+    ///
+    /// On Windows, residency cannot be queried, so this is always ready there.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` lies past the end of the buffer.
+    pub fn poll_resident_chunk(&self, offset: usize) -> Poll<&[u8]> {
+        assert!(offset <= self.length);
+        let length = cmp::min(self.chunk_len_hint(), self.length - offset);
+        if length == 0 { return Poll::Ready(&[]); }
+
+        let resident_len = self.resident_len(offset, length);
+        if resident_len < length {
+            self.prefetch(offset + resident_len, length - resident_len);
+        }
+
+        if resident_len == 0 {
+            Poll::Pending
+        } else {
+            Poll::Ready(&self[offset..offset + resident_len])
+        }
+    }
+
+    /// Returns a reader over the buffer, which implements `Read`, `BufRead`, and `Seek`.
+    ///
+    /// The reader reads from the mapping directly, without copying into an intermediate buffer;
+    /// see `Reader`.
+    pub fn reader(&self) -> Reader<'_> {
+        Reader::new(self)
+    }
+
+    /// Returns a reader over the buffer that only returns bytes that are resident in memory.
+    ///
+    /// Reads from the returned reader never block on page faults; see `ResidentReader` for the
+    /// exact semantics.
+    pub fn resident_reader(&self) -> ResidentReader<'_> {
+        ResidentReader::new(self)
+    }
+
+    /// Splits a shared buffer into two owned sub-buffers at the first page boundary at or after
+    /// `at`.
+    ///
+    /// The front sub-buffer covers the buffer up to the boundary, and the back sub-buffer covers
+    /// the rest. If there is no page boundary between `at` and the end of the buffer, the back
+    /// sub-buffer is empty. Both hold a reference to the buffer, so they can be handed to
+    /// different consumers, for example different stages of a pipeline. Because they do not
+    /// share pages, prefetching or evicting a slice of one does not affect the other.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at` lies past the end of the buffer.
+    pub fn split_off_aligned(self: &Arc<Self>, at: usize) -> (SubBuffer, SubBuffer) {
+        assert!(at <= self.length);
+
+        // Page boundaries are relative to the start of the mapping, not of the buffer.
+        let aligned_at = round_up_to(self.view_offset + at, self.page_size) - self.view_offset;
+        let aligned_at = cmp::min(aligned_at, self.length);
+
+        let front = SubBuffer::new(self.clone(), 0, aligned_at);
+        let back = SubBuffer::new(self.clone(), aligned_at, self.length - aligned_at);
+        (front, back)
+    }
+
+    /// Advises the kernel to make a slice of the file resident in physical memory.
+    ///
+    /// This method does not block, meaning that when the function returns, the slice is not
+    /// necessarily resident. After this function returns, the kernel may read the requested slice
+    /// from disk and make it resident. Note that this is only an advice, the kernel need not honor
+    /// it.
+    ///
+    /// On Linux, a prefetch of 8 MiB or more of a mapped file also submits `readahead` calls to
+    /// keep the queue of the device full. These do not wait for the reads to complete, but they
+    /// can block while submitting them, for example when the queue of the device is full already.
+    ///
+    /// To check whether the slice is resident at a later time, use `resident_len()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn prefetch(&self, offset: usize, length: usize) {
+        // Prefetching is only an advice, if the kernel does not take it there is nothing to do.
+        let _ = self.advise(offset, length, Advice::WillNeed);
+    }
+
+    /// Advises the kernel to make a slice of the file resident, unless it is resident already.
+    ///
+    /// This checks residency with `resident_len()` first, and only gives the advice when part of
+    /// the slice is not resident. Both cost a system call, so this only pays off when the slice
+    /// is usually resident, for example in a loop that prefetches ahead of where it reads, and
+    /// that revisits the same range. Otherwise `prefetch()`, which never checks, is cheaper.
+    ///
+    /// Returns whether the advice was given. Where residency cannot be observed, such as on
+    /// Windows, or with the `no-residency` feature, this always prefetches and returns true.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn prefetch_if_needed(&self, offset: usize, length: usize) -> bool {
+        let observes_residency = !cfg!(any(windows, feature = "no-residency"));
+        if observes_residency && self.resident_len(offset, length) == length {
+            return false;
+        }
+
+        self.prefetch(offset, length);
+        true
+    }
+
+    /// Advises the kernel to make a slice of the file resident, and returns a handle that tracks
+    /// when it is.
+    ///
+    /// Like `prefetch_checked()`, this advises the kernel right away, and returns an error if the
+    /// kernel refuses the advice. Then a background thread touches every page of the slice, and
+    /// the returned handle becomes ready when it is done, at which point the slice was resident.
+    /// This is best-effort feedback, mainly for Windows, where `PrefetchVirtualMemory` gives no
+    /// indication of progress, and where residency cannot be queried. On Unix-ish platforms,
+    /// `resident_len()` gives more precise information without a background thread. The
+    /// background thread blocks on page faults, so a slice that the kernel did not prefetch is
+    /// faulted in anyway. The thread stops early when the buffer is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn prefetch_async(
+        self: &Arc<Self>,
+        offset: usize,
+        length: usize,
+    ) -> io::Result<PrefetchHandle> {
+        self.prefetch_checked(offset, length)?;
+        PrefetchHandle::spawn(self, offset, length)
+    }
+
+    /// Advises the kernel about how a slice of the buffer is going to be accessed.
+    ///
+    /// On Unix-ish platforms this applies the `madvise` flag that corresponds to `advice` to the
+    /// slice, rounded outwards to page boundaries. `Advice::WillNeed` is applied like `prefetch()`
+    /// does, with the backend set by `set_prefetch_backend()`, and `Advice::Cold`,
+    /// `Advice::PageOut`, and `Advice::Free` are applied like `advise_cold()`, `advise_pageout()`,
+    /// and `advise_free()` do, including their fallbacks. On Windows, only `Advice::WillNeed` has
+    /// an effect, the other advice is accepted and ignored.
+    ///
+    /// This advises on the mapping. See `advise_file()` to advise on the underlying file instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn advise(&self, offset: usize, length: usize, advice: Advice) -> io::Result<()> {
+        if advice == Advice::WillNeed {
+            return self.prefetch_checked(offset, length).map(|_| ());
+        }
+
+        // The specified offset and length must lie within the buffer.
+        assert!(offset + length <= self.length);
+
+        // This is a no-op for empty files. A buffer that was read rather than mapped is not
+        // backed by the file, so there the pages cannot be dropped and read again.
+        if self.buffer.is_null() || is_heap_backed(&self.platform_data) { return Ok(()); }
+
+        let (aligned_offset, aligned_length) = self.page_span(offset, length);
+
+        match advice {
+            Advice::DontNeed | Advice::Cold | Advice::PageOut | Advice::Free => self.record_evict(),
+            _ => {}
+        }
+        trace_event!(DEBUG, offset, length, advice = ?advice, "advise");
+
+        let buffer = unsafe { self.mapping_start().add(aligned_offset) };
+        advise(buffer, &self.platform_data, aligned_length, advice)
+    }
+
+    /// Advises the kernel to make a slice of the file resident, and returns how much of it is
+    /// resident right now.
+    ///
+    /// This is `prefetch()` followed by `resident_len()`. It does not wait for the kernel to read
+    /// anything, so the returned length is a snapshot of what was resident already, up to the
+    /// first page that is not. The caller can process that part right away, and come back later
+    /// for the rest. This sits between `prefetch()`, which does not report anything, and
+    /// `prefetch_sync()`, which blocks until the entire slice is resident.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn prefetch_try(&self, offset: usize, length: usize) -> usize {
+        self.prefetch(offset, length);
+        self.resident_len(offset, length)
+    }
+
+    /// Advises the kernel to make a range of the file resident in physical memory.
+    ///
+    /// This is like `prefetch()`, but it takes a range rather than an offset and length. For
+    /// example, `fbuffer.prefetch_range(1024..)` prefetches everything past the first 1024 bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn prefetch_range<R: RangeBounds<usize>>(&self, range: R) {
+        let (offset, length) = self.resolve_range(range);
+        self.prefetch(offset, length)
+    }
+
+    /// Advises the kernel to make several slices of the file resident in physical memory.
+    ///
+    /// This is like calling `prefetch()` for every `(offset, length)` pair in `ranges`, but with
+    /// fewer syscalls. This is useful before reading a scattered set of records, for example
+    /// records found through an index. The ranges are rounded outwards to page boundaries, and
+    /// ranges that touch or overlap after rounding are merged, in whatever order they are given.
+    /// On Windows, all ranges are advised with a single call to `PrefetchVirtualMemory`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the ranges lies outside of the buffer.
+    pub fn prefetch_ranges(&self, ranges: &[(usize, usize)]) {
+        for &(offset, length) in ranges {
+            // The specified offset and length must lie within the buffer.
+            assert!(offset + length <= self.length);
+        }
+
+        // This is a no-op for empty files.
+        if self.buffer.is_null() { return; }
+
+        let mapping_ranges: Vec<_> = ranges
+            .iter()
+            .map(|&(offset, length)| (self.view_offset + offset, length))
+            .collect();
+        let aligned_ranges = align_ranges(&mapping_ranges, self.page_size);
+        self.record_prefetch(aligned_ranges.iter().map(|&(_, length)| length).sum());
+
+        // Prefetching is only an advice, if the kernel does not take it there is nothing to do.
+        let _ = prefetch_ranges(self.mapping_start(), &aligned_ranges);
+    }
+
+    /// Advises the kernel to make the first and the last page of the buffer resident.
+    ///
+    /// Many file formats start with a header and end with a trailer, such as the central directory
+    /// of a zip file, and a reader needs both before it knows where to seek. For a large file,
+    /// prefetching just those pages is far cheaper than prefetching the entire buffer. The pages
+    /// are prefetched with a single call to `prefetch_ranges()`, so when the buffer fits in one
+    /// page, that page is prefetched once. For an empty buffer this is a no-op.
+    pub fn prefetch_ends(&self) {
+        if self.length == 0 { return; }
+        self.prefetch_ranges(&[(0, 1), (self.length - 1, 1)]);
+    }
+
+    /// Returns the slices of the buffer described by `segments`, after checking that they lie
+    /// within the buffer.
+    ///
+    /// Every segment is an `(offset, length)` pair, for example as read from the program headers
+    /// of an ELF file, or the section table of a PE file. Segments may overlap, and they need not
+    /// be sorted. If a segment does not lie entirely within the buffer, this returns an error of
+    /// kind `InvalidInput` that names the first such segment, which makes it suitable for
+    /// descriptors read from untrusted file contents. Nothing is prefetched, see
+    /// `prefetch_segments()` for that.
+    pub fn map_segments(&self, segments: &[(usize, usize)]) -> io::Result<Vec<&[u8]>> {
+        self.check_segments(segments)?;
+        Ok(segments.iter().map(|&(offset, length)| &self[offset..offset + length]).collect())
+    }
+
+    /// Advises the kernel to make the slices described by `segments` resident.
+    ///
+    /// This checks the segments like `map_segments()` does, and returns the same error if a
+    /// segment lies outside of the buffer. Then it advises exactly those slices, like
+    /// `prefetch_ranges()` does.
+    pub fn prefetch_segments(&self, segments: &[(usize, usize)]) -> io::Result<()> {
+        self.check_segments(segments)?;
+        self.prefetch_ranges(segments);
+        Ok(())
+    }
+
+    /// Returns an error that names the first segment that does not lie within the buffer.
+    fn check_segments(&self, segments: &[(usize, usize)]) -> io::Result<()> {
+        for (i, &(offset, length)) in segments.iter().enumerate() {
+            if offset.checked_add(length).map_or(true, |end| end > self.length) {
+                let msg = format!(
+                    "segment {} at offset {} with length {} lies outside of the buffer",
+                    i, offset, length,
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the mechanism that `prefetch()` uses to ask the kernel to read in pages.
+    ///
+    /// This also applies to `prefetch_checked()` and `prefetch_range()`, but not to the methods
+    /// that advise on the file explicitly, such as `prefetch_background()`. See `PrefetchBackend`
+    /// for the options.
+    pub fn set_prefetch_backend(&mut self, backend: PrefetchBackend) {
+        self.prefetch_backend = backend;
+    }
+
+    /// Advises the kernel to make a slice of the file resident, and reports how that went.
+    ///
+    /// This is like `prefetch()`, but rather than silently ignoring failure, it returns the error
+    /// reported by the kernel. The kernel can refuse the advice under memory pressure. On success,
+    /// it returns the number of bytes advised. Because advice has page granularity, this is the
+    /// length of the requested slice rounded outwards to page boundaries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn prefetch_checked(&self, offset: usize, length: usize) -> io::Result<usize> {
+        // The specified offset and length must lie within the buffer.
+        assert!(offset + length <= self.length);
+
+        // This is a no-op for empty files.
+        if self.buffer.is_null() { return Ok(0); }
+
+        let (aligned_offset, aligned_length) = self.page_span(offset, length);
+
+        self.record_prefetch(aligned_length);
+        trace_event!(TRACE, offset, length, backend = ?self.prefetch_backend, "prefetch");
+        match self.prefetch_backend {
+            PrefetchBackend::Madvise => {
+                let buffer = self.mapping_start();
+                prefetch_deep(buffer, &self.platform_data, aligned_offset, aligned_length)?;
+            }
+            PrefetchBackend::Readahead => {
+                let buffer = self.mapping_start();
+                readahead(buffer, &self.platform_data, aligned_offset, aligned_length)?;
+            }
+        }
+
+        Ok(aligned_length)
+    }
+
+    /// Makes a slice of the file resident, blocking until it is.
+    ///
+    /// Unlike `prefetch()`, which is only advice, this reads the slice from the file with regular
+    /// reads, so when it returns, the slice is in the page cache. This is a reliable fallback for
+    /// filesystems that ignore `MADV_WILLNEED`. It blocks for as long as reading from disk takes.
+    /// Because it reads the file rather than touching the mapping, it cannot raise `SIGBUS` when
+    /// the file was truncated. Instead it returns an error of kind `UnexpectedEof`.
+    ///
+    /// The slice is read even when it is resident already, in which case the reads are served
+    /// from the page cache. The slice can of course be evicted again under memory pressure before
+    /// it is accessed. For a buffer that was read into memory rather than mapped, such as one
+    /// from `open_direct()`, the slice is in memory already, and this does nothing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn prefetch_sync(&self, offset: usize, length: usize) -> io::Result<()> {
+        // The specified offset and length must lie within the buffer.
+        assert!(offset + length <= self.length);
+
+        self.record_prefetch(self.page_span(offset, length).1);
+
+        // The bytes of a heap-backed buffer are in memory already, and its file may have been
+        // opened with `O_DIRECT`, which rejects the unaligned reads below.
+        if is_heap_backed(&self.platform_data) { return Ok(()); }
+
+        // Read even if `resident_len()` reports the slice as resident: it does so without looking
+        // on Windows, with `no-residency`, and for memory-backed files, and a read is the only way
+        // to find out that the file was truncated.
+        read_file_range(&self.platform_data, self.view_offset + offset, length)
+    }
+
+    /// Makes the entire buffer resident by faulting in its pages from `threads` threads at once.
+    ///
+    /// The buffer is split into `threads` regions of whole pages, and every region is faulted in
+    /// on its own thread, by touching one byte in every page. On storage that handles many
+    /// requests in parallel, such as NVMe drives, this warms the page cache much faster than
+    /// faulting in the pages from a single thread. This method blocks until all threads are done.
+    /// If `threads` is 0, one thread per processor is used. If a thread cannot be spawned, its
+    /// region is faulted in on the calling thread instead.
+    ///
+    /// As with any page fault, a fault in a part of the file that was truncated raises `SIGBUS`
+    /// on Unix-ish platforms.
+    pub fn prefault_parallel(&self, threads: usize) {
+        // This is a no-op for empty files.
+        if self.buffer.is_null() { return; }
+
+        let threads = if threads == 0 { get_cpu_count() } else { threads };
+        let mapping_len = self.view_offset + self.length;
+        let region_len = round_up_to((mapping_len + threads - 1) / threads, self.page_size);
+        let page_size = self.page_size;
+
+        let mut handles = Vec::new();
+        for region_start in (0..mapping_len).step_by(region_len) {
+            let length = cmp::min(region_len, mapping_len - region_start);
+            // Raw pointers are not `Send`, so pass the address as an integer. This is sound,
+            // because all threads are joined below, before `self` can be unmapped.
+            let address = unsafe { self.mapping_start().add(region_start) } as usize;
+            let spawned = thread::Builder::new()
+                .name("filebuffer-prefault".to_string())
+                .spawn(move || touch_pages(address as *const u8, length, page_size));
+            match spawned {
+                Ok(handle) => handles.push(handle),
+                Err(_) => touch_pages(address as *const u8, length, page_size),
+            }
+        }
+
+        for handle in handles {
+            // Touching pages does not panic, and if it did, there would be nothing to recover.
+            let _ = handle.join();
+        }
+    }
+
+    /// Advises the kernel to make a slice of the file resident, for a scan from back to front.
+    ///
+    /// The kernel assumes that a file is read from front to back, and on a page fault it reads
+    /// ahead past the faulting page. For formats that are parsed from a trailer backwards, such
+    /// as zip archives, that readahead is wasted. This method advises the slice as
+    /// `Advice::Random`, which disables readahead for it, and then prefetches exactly the slice.
+    /// The random access advice stays in effect for the slice afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn prefetch_descending(&self, offset: usize, length: usize) {
+        // The specified offset and length must lie within the buffer.
+        assert!(offset + length <= self.length);
+
+        // This is a no-op for empty files.
+        if self.buffer.is_null() { return; }
+
+        let (aligned_offset, aligned_length) = self.page_span(offset, length);
+
+        // Advice is only a hint, if the kernel does not take it there is nothing to do.
+        self.record_prefetch(aligned_length);
+        let buffer = unsafe { self.mapping_start().add(aligned_offset) };
+        let _ = advise(buffer, &self.platform_data, aligned_length, Advice::Random);
+        let _ = prefetch(buffer, aligned_length);
+    }
+
+    /// Advises the kernel that a slice of the file is no longer needed.
+    ///
+    /// Where supported (the BSDs and macOS), this uses `MADV_FREE`, which lets the kernel reclaim
+    /// the pages lazily, only when it comes under memory pressure. Elsewhere, including on Linux,
+    /// which supports `MADV_FREE` only for anonymous memory, this falls back to `MADV_DONTNEED`,
+    /// which drops the pages immediately. The contents of the buffer do not change; the pages are
+    /// read from disk again when they are accessed later. On Windows this is a no-op.
+    ///
+    /// For anonymous memory, such as a buffer that was made from `FileBufferMut::anonymous()`,
+    /// macOS uses `MADV_FREE_REUSABLE` instead, which also stops the pages from counting towards
+    /// the memory footprint of the process. A later `prefetch()` marks them as in use again.
+    ///
+    /// Like for `prefetch()`, the slice is rounded outwards to page boundaries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn advise_free(&self, offset: usize, length: usize) -> io::Result<()> {
+        self.advise(offset, length, Advice::Free)
+    }
+
+    /// Advises the kernel that a range of the file is no longer needed.
+    ///
+    /// This is like `advise_free()`, but it takes a range rather than an offset and length. For
+    /// example, `fbuffer.advise_free_range(..4096)` releases the first 4096 bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn advise_free_range<R: RangeBounds<usize>>(&self, range: R) -> io::Result<()> {
+        let (offset, length) = self.resolve_range(range);
+        self.advise_free(offset, length)
+    }
+
+    /// Advises the kernel that a slice of the file is unlikely to be accessed soon.
+    ///
+    /// On Linux 5.4 and later this uses `MADV_COLD`, which deactivates the pages, so they are
+    /// reclaimed before other pages under memory pressure. Unlike `advise_free()`, the pages are
+    /// not dropped right away. On older kernels and other platforms this is a no-op.
+    ///
+    /// Like for `prefetch()`, the slice is rounded outwards to page boundaries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn advise_cold(&self, offset: usize, length: usize) -> io::Result<()> {
+        self.advise(offset, length, Advice::Cold)
+    }
+
+    /// Advises the kernel to reclaim the pages of a slice of the file right away.
+    ///
+    /// On Linux 5.4 and later this uses `MADV_PAGEOUT`, which reclaims the pages immediately.
+    /// On older kernels and other Unix-ish platforms, this falls back to `MADV_DONTNEED`. The
+    /// contents of the buffer do not change; the pages are read from disk again when they are
+    /// accessed later. On Windows this is a no-op.
+    ///
+    /// Like for `prefetch()`, the slice is rounded outwards to page boundaries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn advise_pageout(&self, offset: usize, length: usize) -> io::Result<()> {
+        self.advise(offset, length, Advice::PageOut)
+    }
+
+    /// Resets the access pattern advice for the entire buffer to the default.
+    ///
+    /// This undoes `Advice::Sequential` and `Advice::Random`, whether given with `advise()`,
+    /// `advise_file()`, or `OpenOptions::advise()`, so the kernel goes back to its default
+    /// readahead. This allows a long-lived buffer to move between phases with different access
+    /// patterns, for example from a sequential bulk load to random lookups. It applies
+    /// `MADV_NORMAL` to the mapping, and `POSIX_FADV_NORMAL` to the file on the platforms that
+    /// `advise_file()` supports. On Windows this is a no-op.
+    pub fn advise_normal(&self) -> io::Result<()> {
+        self.advise(0, self.length, Advice::Normal)?;
+        self.advise_file(0, self.length, Advice::Normal)
+    }
+
+    /// Advises the kernel about the access pattern of a slice of the underlying file.
+    ///
+    /// Unlike the advice given by `OpenOptions::advise()`, which applies to the mapping with
+    /// `madvise`, this advises on the file itself with `posix_fadvise`. For cold files this can
+    /// trigger more effective readahead, and `Advice::DontNeed` drops the pages of the file from
+    /// the page cache. The offset is relative to the start of the buffer, also for buffers that
+    /// map a range of the file.
+    ///
+    /// `posix_fadvise` has no equivalent of `Advice::Cold`, which is ignored, and
+    /// `Advice::PageOut` and `Advice::Free` are applied as `Advice::DontNeed`.
+    ///
+    /// This is supported on Linux, Android, and FreeBSD. On other platforms, including macOS and
+    /// Windows, this is a no-op.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn advise_file(&self, offset: usize, length: usize, advice: Advice) -> io::Result<()> {
+        // The specified offset and length must lie within the buffer.
+        assert!(offset + length <= self.length);
+
+        // This is a no-op for empty ranges; a length of 0 would mean "until the end of the file".
+        if length == 0 { return Ok(()); }
+
+        match advice {
+            Advice::WillNeed => self.record_prefetch(length),
+            Advice::DontNeed | Advice::PageOut | Advice::Free => self.record_evict(),
+            _ => {}
+        }
+        trace_event!(DEBUG, offset, length, advice = ?advice, "advise file");
+
+        advise_file(&self.platform_data, self.view_offset + offset, length, advice)
+    }
+
+    /// Returns the ranges of the buffer that are backed by data, rather than by holes in a sparse
+    /// file.
+    ///
+    /// Holes read as zeroes, but reading them still faults in zero-filled pages. Tools that
+    /// process large sparse files, such as disk images, can use this to read and prefetch only
+    /// the populated ranges. The ranges are sorted and do not overlap. Their boundaries are
+    /// determined by the filesystem, typically at block granularity, and a range reported as data
+    /// may contain zeroes as well.
+    ///
+    /// The ranges are found with `lseek` with `SEEK_DATA` and `SEEK_HOLE` on Linux, Android, and
+    /// FreeBSD. Filesystems that do not track holes report the entire file as data. On other
+    /// platforms the entire buffer is reported as data. This method is only available on Unix-ish
+    /// platforms.
+    #[cfg(unix)]
+    pub fn data_ranges(&self) -> io::Result<Vec<Range<usize>>> {
+        let view_offset = self.view_offset;
+        let ranges = get_data_ranges(&self.platform_data)?
+            .into_iter()
+            .filter(|range| range.end > view_offset)
+            .map(|range| range.start.saturating_sub(view_offset)..range.end - view_offset)
+            .collect();
+        Ok(ranges)
+    }
+
+    /// Asks the kernel to read a slice of the underlying file into the page cache in the
+    /// background.
+    ///
+    /// This is equivalent to `advise_file(offset, length, Advice::WillNeed)`. It complements
+    /// `prefetch()`, which advises on the mapping rather than on the file. Which one is more
+    /// effective depends on the workload.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn prefetch_background(&self, offset: usize, length: usize) -> io::Result<()> {
+        self.advise_file(offset, length, Advice::WillNeed)
+    }
+
+    /// Returns statistics about the advice given for this buffer, if it was opened with
+    /// `OpenOptions::track_stats(true)`, or `None` otherwise.
+    pub fn stats(&self) -> Option<BufferStats> {
+        self.stats.as_ref().map(|stats| stats.snapshot())
+    }
+
+    fn record_prefetch(&self, bytes: usize) {
+        if let Some(ref stats) = self.stats { stats.record_prefetch(bytes); }
+    }
+
+    fn record_evict(&self) {
+        if let Some(ref stats) = self.stats { stats.record_evict(); }
+    }
+
+    /// Returns whether the byte at `offset` lies in a mapping that is backed by huge pages.
+    ///
+    /// This reads `/proc/self/smaps`, and reports whether the `AnonHugePages` or `FilePmdMapped`
+    /// count of the mapping that contains the byte is nonzero. The kernel reports huge pages per
+    /// mapping, so this is true when any part of the mapping is backed by huge pages, not
+    /// necessarily the page at `offset`. This is meant for diagnostics, for example to verify that
+    /// transparent huge pages are in effect.
+    ///
+    /// This method is only available on Linux and Android.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` lies outside of the buffer.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn huge_page_backed(&self, offset: usize) -> io::Result<bool> {
+        assert!(offset < self.length);
+        is_huge_page_backed(self.buffer as usize + offset)
+    }
+
+    /// Returns the state and protection of the pages at the start of the mapping.
+    ///
+    /// This queries the mapping with `VirtualQuery`, which is useful for debugging and for
+    /// tracking address space usage. Note that it does not tell whether pages are resident in
+    /// physical memory. Returns an error of kind `InvalidInput` for an empty buffer, which is
+    /// not mapped.
+    ///
+    /// This method is only available on Windows.
+    #[cfg(windows)]
+    pub fn mapped_region_info(&self) -> io::Result<RegionInfo> {
+        if self.buffer.is_null() {
+            let msg = "an empty buffer is not mapped";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+
+        get_region_info(self.buffer)
+    }
+
+    /// Returns the bytes from `from` up to the next occurrence of `byte`, and the offset just past
+    /// that occurrence.
+    ///
+    /// This is a primitive for framing records, such as lines, without copying: pass the returned
+    /// offset as `from` to get the next record. The delimiter itself is not included in the
+    /// returned slice. If `byte` does not occur before the end of the buffer, the remaining tail
+    /// is returned, together with the length of the buffer. Returns `None` when `from` is at or
+    /// past the end of the buffer, which includes any `from` for an empty buffer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use filebuffer::FileBuffer;
+    /// let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    /// let (first_line, next) = fbuffer.split_at_byte(0, b'\n').unwrap();
+    /// assert_eq!(first_line, &b"// Filebuffer -- Fast and simple file reading"[..]);
+    /// assert_eq!(next, first_line.len() + 1);
+    /// ```
+    pub fn split_at_byte(&self, from: usize, byte: u8) -> Option<(&[u8], usize)> {
+        if from >= self.length {
+            return None;
+        }
+
+        let tail = &self[from..];
+        match tail.iter().position(|&b| b == byte) {
+            Some(i) => Some((&tail[..i], from + i + 1)),
+            None => Some((tail, self.length)),
+        }
+    }
+
+    /// Returns whether `ptr` points into the buffer.
+    ///
+    /// This is pure address arithmetic, `ptr` is never dereferenced. A pointer one past the end of
+    /// the buffer does not point into it. This is named `contains_ptr` rather than `contains`,
+    /// because the latter would shadow `<[u8]>::contains`, which tests for a byte value.
+    pub fn contains_ptr(&self, ptr: *const u8) -> bool {
+        let buffer_start = self.buffer as usize;
+        let address = ptr as usize;
+        !self.buffer.is_null() && address >= buffer_start && address - buffer_start < self.length
+    }
+
+    /// Returns `length` bytes of the buffer starting at `offset`, after checking that the file has
+    /// not been truncated since it was mapped.
+    ///
+    /// On Unix-ish platforms, accessing a page of the buffer that lies past the end of the file
+    /// raises `SIGBUS`, which kills the process. This can happen when a different process truncates
+    /// the file while it is mapped. This method looks up the current size of the file, and returns
+    /// an error of kind `UnexpectedEof` if the file no longer covers the slice, turning the crash
+    /// into a recoverable error. An error of kind `InvalidInput` is returned if the slice does not
+    /// lie within the buffer.
+    ///
+    /// This does not close the race entirely: the file can still be truncated between the check
+    /// and accessing the slice. It only catches files that were truncated earlier. On Windows,
+    /// a file cannot be truncated while it is mapped, so there the check always passes.
+    pub fn guarded_slice(&self, offset: usize, length: usize) -> io::Result<&[u8]> {
+        let end = match offset.checked_add(length) {
+            Some(end) if end <= self.length => end,
+            _ => {
+                let msg = "slice lies outside of the buffer";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        };
+
+        if length > 0 && get_backed_len(&self.platform_data)? < self.view_offset + end {
+            let msg = "file was truncated after it was mapped";
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+        }
+
+        Ok(&self[offset..end])
+    }
+
+    /// Returns the contents of the buffer as a slice.
+    ///
+    /// This is equivalent to `&fbuffer[..]`, which goes through `Deref`. For an empty file it
+    /// returns an empty slice.
+    pub fn as_bytes(&self) -> &[u8] {
+        self
+    }
+
+    /// Copies the contents of the buffer into a new vector.
+    ///
+    /// This is equivalent to `fbuffer[..].to_vec()`. Note that this reads the entire file into
+    /// memory, which defeats the purpose of mapping it. See also `From<FileBuffer> for Vec<u8>`.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    /// Copies the contents of the buffer into a new string, and drops the buffer.
+    ///
+    /// This validates the contents as UTF-8 in place first, and only copies them if they are
+    /// valid, so unlike `String::from_utf8(fbuffer.to_vec())`, invalid files are not copied. If
+    /// the contents are not valid UTF-8, the error is returned together with the buffer, so the
+    /// caller can still use it. To borrow the contents as `&str` without copying, use
+    /// `<&str>::try_from(&fbuffer)` instead.
+    // Returning the buffer by value mirrors `String::from_utf8`, which returns its input as well.
+    #[allow(clippy::result_large_err)]
+    pub fn try_into_string(self) -> Result<String, (FileBuffer, str::Utf8Error)> {
+        let string = match str::from_utf8(&self) {
+            Ok(contents) => contents.to_string(),
+            Err(err) => return Err((self, err)),
+        };
+        Ok(string)
+    }
+
+    /// Returns the first `n` bytes of the buffer, or the entire buffer if it is shorter.
+    ///
+    /// Only the returned bytes are prefetched, which makes this a good fit for `head`-like tools
+    /// that look at the start of a large file.
+    pub fn prefix(&self, n: usize) -> &[u8] {
+        let length = cmp::min(n, self.length);
+        self.prefetch(0, length);
+        &self[..length]
+    }
+
+    /// Returns the last `n` bytes of the buffer, or the entire buffer if it is shorter.
+    ///
+    /// Only the pages that contain the returned bytes are prefetched, not the rest of the file,
+    /// which makes this a good fit for `tail`-like tools that look at the end of a large file.
+    pub fn suffix(&self, n: usize) -> &[u8] {
+        let offset = self.length - cmp::min(n, self.length);
+        self.prefetch(offset, self.length - offset);
+        &self[offset..]
+    }
+
+    /// Returns the bytes in `range`, or `None` if the range does not lie within the buffer.
+    ///
+    /// Unlike indexing, this does not panic for out-of-range or reversed ranges, which makes it
+    /// suitable for offsets that come from untrusted file contents. An empty range at the very end
+    /// of the buffer is within the buffer. Like `prefetch_range()`, this accepts any kind of
+    /// range, such as `a..b`, `a..=b`, or `a..`.
+    pub fn reslice<R: RangeBounds<usize>>(&self, range: R) -> Option<&[u8]> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1)?,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.length,
+        };
+
+        if start > end || end > self.length {
+            return None;
+        }
+
+        Some(&self[start..end])
+    }
+
+    /// Returns `length` bytes starting at `offset`, without checking that they lie within the
+    /// buffer.
+    ///
+    /// This is an escape hatch for hot loops that validated their offsets against `len()`
+    /// already, where the bounds checks of indexing show up in profiles. The slice is built from
+    /// the mapping directly, so it does not go through `Deref` either.
+    ///
+    /// # Safety
+    ///
+    /// `offset + length` must not exceed `len()`, and the addition must not overflow. Calling
+    /// this with a range outside of the buffer is undefined behavior, even if the returned slice
+    /// is not used. As for every access to a mapping, the file must also not have been truncated
+    /// to end before `offset + length`, or accessing the slice raises `SIGBUS`.
+    pub unsafe fn slice_unchecked(&self, offset: usize, length: usize) -> &[u8] {
+        debug_assert!(offset <= self.length && length <= self.length - offset);
+
+        // The buffer of an empty file is null, and a slice must not be built from a null pointer.
+        if self.buffer.is_null() { return &[]; }
+
+        slice::from_raw_parts(self.buffer.add(offset), length)
+    }
+
+    /// Returns `length` bytes starting at `offset`, or `None` if the range does not lie within the
+    /// buffer.
+    ///
+    /// A `FileBuffer` is a single contiguous mapping, so the result is always borrowed. The `Cow`
+    /// return type leaves room for buffers that consist of multiple mappings, where a range that
+    /// straddles a boundary would have to be copied, to offer the same method. This is named
+    /// `get_or_copy` rather than `get`, because the latter would shadow `<[u8]>::get`.
+    pub fn get_or_copy(&self, offset: usize, length: usize) -> Option<Cow<'_, [u8]>> {
+        let end = offset.checked_add(length)?;
+        self.reslice(offset..end).map(Cow::Borrowed)
+    }
+
+    /// Reinterprets the buffer as a slice of `T`, without copying.
+    ///
+    /// This is useful for files that consist of an array of fixed-size records, such as an index
+    /// of `u32`s. The buffer must be aligned for `T`, and its length must be a multiple of the size
+    /// of `T`, otherwise this returns an error of kind `InvalidData`. A mapping starts at a page
+    /// boundary, so the alignment requirement is met for all but the most unusual types. Values
+    /// are read with the byte order of the platform.
+    ///
+    /// # Safety
+    ///
+    /// Every bit pattern must be a valid value of `T`. This holds for primitive integers and
+    /// floats, and for arrays and `#[repr(C)]` structs of those, but not for types such as `bool`,
+    /// `char`, enums, or references. Note that a `#[repr(C)]` struct with padding bytes does not
+    /// have this property either.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is a zero-sized type.
+    pub unsafe fn as_slice_of<T: Copy>(&self) -> io::Result<&[T]> {
+        let size = mem::size_of::<T>();
+        assert!(size != 0, "cannot view a buffer as a slice of a zero-sized type");
+
+        if self.buffer.is_null() {
+            return Ok(&[]);
+        }
+
+        if self.buffer as usize % mem::align_of::<T>() != 0 {
+            let msg = "buffer is not aligned for the requested type";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+
+        if self.length % size != 0 {
+            let msg = "buffer length is not a multiple of the size of the requested type";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+
+        Ok(slice::from_raw_parts(self.buffer as *const T, self.length / size))
+    }
+
+    /// Splits the buffer into arrays of `N` bytes, and a remainder of fewer than `N` bytes.
+    ///
+    /// This mirrors `as_chunks` on slices in the standard library. It is useful for files that
+    /// consist of fixed-size records: unlike `chunks_exact`, the records are arrays, so indexing
+    /// into a record needs no bounds check, and unlike `as_slice_of()`, this is safe, because
+    /// arrays of bytes have no alignment requirement and every bit pattern is valid. Nothing is
+    /// copied. The remainder is the part at the end of the buffer that does not fill a record,
+    /// which is empty if the length of the buffer is a multiple of `N`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is 0.
+    pub fn as_chunks<const N: usize>(&self) -> (&[[u8; N]], &[u8]) {
+        assert!(N != 0, "chunk size must not be zero");
+        let num_chunks = self.length / N;
+        let (chunks, remainder) = self.split_at(num_chunks * N);
+        // `[u8; N]` has the same layout as `N` consecutive bytes, and an alignment of 1.
+        let ptr = chunks.as_ptr() as *const [u8; N];
+        let chunks = unsafe { slice::from_raw_parts(ptr, num_chunks) };
+        (chunks, remainder)
+    }
+
+    /// Returns a hex dump of a slice of the buffer, in the format of `xxd`.
+    ///
+    /// Every line shows the offset into the buffer of its first byte, up to 16 bytes in hex, in
+    /// groups of two, and the same bytes as ASCII, where non-printable bytes are shown as `.`.
+    /// This is useful for debugging parsers of binary formats.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use filebuffer::FileBuffer;
+    /// let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    /// assert_eq!(
+    ///     fbuffer.dump(3, 20),
+    ///     "00000003: 4669 6c65 6275 6666 6572 202d 2d20 4661  Filebuffer -- Fa\n\
+    ///      00000013: 7374 2061                                st a\n",
+    /// );
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn dump(&self, offset: usize, length: usize) -> String {
+        use std::fmt::Write;
+
+        // The specified offset and length must lie within the buffer.
+        assert!(offset + length <= self.length);
+
+        self.prefetch(offset, length);
+
+        let mut output = String::new();
+        for (i, line) in self[offset..offset + length].chunks(16).enumerate() {
+            // Writing to a string does not fail, so the results can be ignored.
+            let _ = write!(output, "{:08x}:", offset + i * 16);
+
+            for j in 0..16 {
+                if j % 2 == 0 { output.push(' '); }
+                match line.get(j) {
+                    Some(byte) => { let _ = write!(output, "{:02x}", byte); }
+                    None => output.push_str("  "),
+                }
+            }
+
+            output.push_str("  ");
+            for &byte in line {
+                let is_printable = byte.is_ascii_graphic() || byte == b' ';
+                output.push(if is_printable { byte as char } else { '.' });
+            }
+            output.push('\n');
+        }
+
+        output
+    }
+
+    /// Returns the offset of `slice` in the buffer, if `slice` was borrowed from the buffer.
+    ///
+    /// This turns a slice into the buffer, for example a record returned by a parser, back into a
+    /// file offset. Returns `None` if `slice` does not lie entirely within the buffer. An empty
+    /// slice at the very end of the buffer, such as `&fbuffer[fbuffer.len()..]`, lies within the
+    /// buffer at offset `len()`.
+    pub fn offset_of(&self, slice: &[u8]) -> Option<usize> {
+        // This is pure address arithmetic, the pointers are never dereferenced.
+        let buffer_start = self.buffer as usize;
+        let slice_start = slice.as_ptr() as usize;
+
+        if self.buffer.is_null() || slice_start < buffer_start {
+            return None;
+        }
+
+        let offset = slice_start - buffer_start;
+        if offset <= self.length && slice.len() <= self.length - offset {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    /// Copies the bytes starting at `offset` into `dst`, filling `dst` entirely.
+    ///
+    /// Unlike slicing the buffer, this returns an error of kind `UnexpectedEof` rather than
+    /// panicking when the range lies outside of the buffer. This makes it suitable for offsets
+    /// read from untrusted file contents. The range is prefetched before it is copied.
+    pub fn copy_to(&self, offset: usize, dst: &mut [u8]) -> io::Result<()> {
+        if offset > self.length || dst.len() > self.length - offset {
+            let msg = "range to copy lies outside of the buffer";
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+        }
+
+        self.prefetch(offset, dst.len());
+        dst.copy_from_slice(&self[offset..offset + dst.len()]);
+        Ok(())
+    }
+
+    /// Copies the bytes starting at `offset` into `buf`, and returns the number of bytes copied.
+    ///
+    /// This has the same signature and behavior as `read_at` in `std::os::unix::fs::FileExt`, so
+    /// the buffer can stand in for a file in code that does positioned reads, but every read is a
+    /// copy from the mapping rather than a system call. Fewer bytes than `buf.len()` are copied
+    /// only at the end of the buffer, and an `offset` at or past the end copies nothing and returns
+    /// 0. The range is prefetched before it is copied. This never fails, the result is an
+    /// `io::Result` only for compatibility with `FileExt`.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.length as u64 {
+            return Ok(0);
+        }
+
+        let offset = offset as usize;
+        let len = cmp::min(buf.len(), self.length - offset);
+        self.prefetch(offset, len);
+        buf[..len].copy_from_slice(&self[offset..offset + len]);
+        Ok(len)
+    }
+
+    /// Copies the bytes starting at `offset` into `buf`, filling `buf` entirely.
+    ///
+    /// This has the same signature and behavior as `read_exact_at` in
+    /// `std::os::unix::fs::FileExt`: if the range extends past the end of the buffer, this returns
+    /// an error of kind `UnexpectedEof`. See also `copy_to()`, which takes a `usize` offset.
+    pub fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        if offset > self.length as u64 {
+            let msg = "range to copy lies outside of the buffer";
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+        }
+
+        self.copy_to(offset as usize, buf)
+    }
+
+    /// Copies the `N` bytes starting at `offset` into an array.
+    ///
+    /// This is convenient for reading fixed-width fields and headers, for example
+    /// `u32::from_le_bytes(fbuffer.read_array(offset)?)`. Like `copy_to()`, the bytes are
+    /// prefetched before they are copied. Returns `None` if the range lies outside of the buffer.
+    pub fn read_array<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        let mut array = [0_u8; N];
+        self.copy_to(offset, &mut array).ok()?;
+        Some(array)
+    }
+
+    /// Compares the buffer with `other` in constant time.
+    ///
+    /// Comparing with `==` stops at the first difference, so the time it takes reveals how long
+    /// the common prefix is. This is a problem when comparing secrets, such as a mapped key file.
+    /// This method instead compares all bytes of `other`, regardless of where they differ, and
+    /// it does not stop early when the lengths differ either. The time it takes depends only on
+    /// the length of `other`, which should be the expected value, not on the contents of the
+    /// buffer. Bytes past the end of the buffer are compared as if they were zero, and a length
+    /// mismatch makes the comparison fail.
+    pub fn ct_eq(&self, other: &[u8]) -> bool {
+        let mut diff = (self.length ^ other.len()) as u64;
+        for (i, &expected) in other.iter().enumerate() {
+            let actual = if i < self.length { self[i] } else { 0 };
+            diff |= (actual ^ expected) as u64;
+        }
+
+        // Read the accumulated difference through a volatile read, so the compiler cannot reason
+        // about its value and turn the loop into one that exits early.
+        unsafe { ptr::read_volatile(&diff) == 0 }
+    }
+
+    /// Returns a fast, non-cryptographic hash of the entire buffer.
+    ///
+    /// On Unix-ish platforms, modifications made to the file by other processes can show up in
+    /// the buffer while it is mapped. Comparing the hash before and after processing the buffer
+    /// detects such modifications with high probability. This is a practical check, not a
+    /// guarantee: the file could be modified and restored in between, and a malicious writer can
+    /// produce collisions. Buffers of files that were read rather than mapped never change, see
+    /// `OpenOptions::read_threshold()`. The hash is the 64-bit FNV-1a hash of the contents.
+    ///
+    /// Like `digest()`, this prefetches the next chunk of the buffer while hashing a chunk.
+    pub fn content_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut hash = FNV_OFFSET_BASIS;
+        let chunk_len = self.chunk_len_hint();
+        let mut offset = 0;
+
+        while offset < self.length {
+            let next_offset = cmp::min(offset + chunk_len, self.length);
+            let next_length = cmp::min(chunk_len, self.length - next_offset);
+            self.prefetch(next_offset, next_length);
+            for &byte in &self[offset..next_offset] {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+            offset = next_offset;
+        }
+
+        hash
+    }
+
+    /// Returns whether the file at `other` has the same contents as the buffer.
+    ///
+    /// The other file is opened with the default options. When the lengths differ, this returns
+    /// `false` without touching either buffer. Otherwise the buffers are compared one chunk of
+    /// `chunk_len_hint()` bytes at a time, prefetching the next chunk of both while comparing a
+    /// chunk, and the comparison stops at the first chunk that differs. Files that differ early
+    /// on are therefore not read in their entirety, unlike when comparing the full slices.
+    pub fn content_equals_path<P: AsRef<Path>>(&self, other: P) -> io::Result<bool> {
+        let other = FileBuffer::open(other)?;
+        if other.length != self.length { return Ok(false); }
+
+        let chunk_len = self.chunk_len_hint();
+        let mut offset = 0;
+
+        while offset < self.length {
+            let next_offset = cmp::min(offset + chunk_len, self.length);
+            let next_length = cmp::min(chunk_len, self.length - next_offset);
+            self.prefetch(next_offset, next_length);
+            other.prefetch(next_offset, next_length);
+            if self[offset..next_offset] != other[offset..next_offset] { return Ok(false); }
+            offset = next_offset;
+        }
+
+        Ok(true)
+    }
+
+    /// Hashes the entire buffer with the hash function `D`.
+    ///
+    /// The buffer is fed to the hasher in chunks of `chunk_len_hint()` bytes. Before hashing a
+    /// chunk, the next chunk is prefetched, so reading from disk overlaps with hashing.
+    ///
+    /// This method is only available with the `digest` feature enabled.
+    #[cfg(feature = "digest")]
+    pub fn digest<D: digest::Digest>(&self) -> digest::Output<D> {
+        let mut hasher = D::new();
+        let chunk_len = self.chunk_len_hint();
+        let mut offset = 0;
+
+        while offset < self.length {
+            let next_offset = cmp::min(offset + chunk_len, self.length);
+            let next_length = cmp::min(chunk_len, self.length - next_offset);
+            self.prefetch(next_offset, next_length);
+            hasher.update(&self[offset..next_offset]);
+            offset = next_offset;
+        }
+
+        hasher.finalize()
+    }
+
+    /// Feeds the resident bytes starting at `from` to `hasher`, and returns where it stopped.
+    ///
+    /// Hashing stops at the first byte that is not resident in physical memory, or at the end of
+    /// the buffer, so this never blocks on a page fault. The bytes from the returned offset
+    /// onwards are prefetched, so a later call can continue from there with the same hasher,
+    /// until the returned offset equals `len()`. This allows verifying a file incrementally while
+    /// it is being read in, for example by a different process that downloads it. The hasher is
+    /// passed by reference rather than created here, so that its state carries over between
+    /// calls, and so bytes can be fed to it in other ways too.
+    ///
+    /// Like `resident_len()`, on Windows this treats all bytes as resident, so there it hashes
+    /// the remainder of the buffer in one go.
+    ///
+    /// This method is only available with the `digest` feature enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `from` lies past the end of the buffer.
+    #[cfg(feature = "digest")]
+    pub fn digest_resident<D: digest::Digest>(&self, hasher: &mut D, from: usize) -> usize {
+        assert!(from <= self.length);
+        let chunk_len = self.chunk_len_hint();
+        let mut offset = from;
+
+        while offset < self.length {
+            let length = cmp::min(chunk_len, self.length - offset);
+            let resident_len = self.resident_len(offset, length);
+            hasher.update(&self[offset..offset + resident_len]);
+            offset += resident_len;
+
+            if resident_len < length {
+                self.prefetch(offset, cmp::min(chunk_len, self.length - offset));
+                break;
+            }
+        }
+
+        offset
+    }
+
+    /// Returns an iterator over overlapping windows of `size` bytes, advancing by `step` bytes.
+    ///
+    /// The windows start at offsets 0, `step`, `2 * step`, and so on, for as long as the window
+    /// fits in the buffer; a partial window at the end is not returned. The windows are slices of
+    /// the mapping, nothing is copied. As the iterator advances, it prefetches one chunk of
+    /// `chunk_len_hint()` bytes past the end of the current window, so a rolling hash or scanner
+    /// does not block on a page fault at every page boundary.
+    ///
+    /// Because the windows are visited in order, the mapping is advised as `Advice::Sequential`
+    /// while the iterator is alive, for more aggressive readahead. When the last such iterator is
+    /// dropped, the access pattern advice that the buffer was opened with is restored, for
+    /// example `Advice::Random` for a range, or `Advice::Normal` by default, so a later phase of
+    /// random access does not suffer from it. Advice given with `advise()` in the meantime is
+    /// replaced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` or `step` is 0.
+    pub fn sliding(&self, size: usize, step: usize) -> impl Iterator<Item = &[u8]> + '_ {
+        assert!(size > 0, "window size must not be zero");
+        assert!(step > 0, "window step must not be zero");
+
+        let count = if size > self.length { 0 } else { (self.length - size) / step + 1 };
+        let chunk_len = self.chunk_len_hint();
+        let mut prefetched_end = 0;
+
+        // The closure owns the guard, so the advice is reset when the iterator is dropped.
+        let guard = SequentialAdvice::new(self);
+
+        (0..count).map(move |i| {
+            let _ = &guard;
+            let offset = i * step;
+            let end = offset + size;
+            if end > prefetched_end {
+                let prefetch_start = cmp::max(prefetched_end, offset);
+                prefetched_end = cmp::min(end.saturating_add(chunk_len), self.length);
+                self.prefetch(prefetch_start, prefetched_end - prefetch_start);
+            }
+            &self[offset..end]
+        })
+    }
+
+    /// Returns a parallel iterator over non-overlapping chunks of the buffer.
+    ///
+    /// The chunk length is `chunk_len` rounded up to a multiple of the page size, so chunks do not
+    /// share pages. The last chunk may be shorter. Unlike calling `par_chunks` on the slice, when
+    /// a worker takes a chunk, that chunk and the next one are prefetched, so the workers do not
+    /// all block on page faults at the same time, and reading from disk overlaps with processing.
+    ///
+    /// This method is only available with the `rayon` feature enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_len` is 0.
+    #[cfg(feature = "rayon")]
+    pub fn par_chunks(
+        &self,
+        chunk_len: usize,
+    ) -> impl rayon::iter::IndexedParallelIterator<Item = &[u8]> + '_ {
+        use rayon::prelude::*;
+
+        assert!(chunk_len > 0, "chunk length must not be zero");
+        let chunk_len = round_up_to(chunk_len, self.page_size);
+
+        self[..].par_chunks(chunk_len).enumerate().map(move |(i, chunk)| {
+            let offset = i * chunk_len;
+            self.prefetch(offset, cmp::min(2 * chunk_len, self.length - offset));
+            chunk
+        })
+    }
+
+    /// Leaks the file buffer as a byte slice.
+    ///
+    /// This prevents the buffer from being unmapped, keeping the file mapped until the program
+    /// ends. This is not as bad as it sounds, because the kernel is free to evict pages from
+    /// physical memory in case of memory pressure. Because the file is mapped read-only, it can
+    /// always be read from disk again.
+    ///
+    /// If the file buffer is going to be open for the entire duration of the program anyway, this
+    /// method can avoid some lifetime issues. Still, it is good practice to close the file buffer
+    /// if possible. This method should be a last resort.
+    pub fn leak(mut self) -> &'static [u8] {
+        let buffer = if self.buffer.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.buffer, self.length) }
+        };
+
+        // Prevent `drop()` from freeing the buffer.
+        self.buffer = ptr::null();
+        self.length = 0;
+        leak_owned_memory(&mut self.platform_data);
+
+        buffer
+    }
+
+    /// Leaks one reference to a shared file buffer, and returns the buffer as a byte slice.
+    ///
+    /// This is like `leak()`, but for a buffer that is shared: the reference that is passed in
+    /// is never released, so the buffer is never unmapped, but other clones of the `Arc` remain
+    /// usable. This avoids having to clone the buffer before leaking it, when the buffer is shared
+    /// and lives for the entire duration of the program anyway. The same caveats as for `leak()`
+    /// apply.
+    pub fn leak_ref(self: Arc<Self>) -> &'static [u8] {
+        let fbuffer: &'static Arc<FileBuffer> = Box::leak(Box::new(self));
+        &fbuffer[..]
+    }
+}
+
+// There is no possibility of data races when passing `&FileBuffer` across threads,
+// because the buffer is read-only. The only interior mutability is in state beside the buffer:
+// the statistics counters are atomics, and the count of sequential advice guards, like the cached
+// device parameters in the platform data on Linux, is behind a `Mutex`. These are `Sync` already.
+unsafe impl Sync for FileBuffer {}
+
+// It is safe to move a `FileBuffer` into a different thread.
+unsafe impl Send for FileBuffer {}
+
+/// Returns an empty buffer, see `FileBuffer::empty()`.
+impl Default for FileBuffer {
+    fn default() -> FileBuffer {
+        FileBuffer::empty()
+    }
+}
+
+impl Drop for FileBuffer {
+    fn drop(&mut self) {
+        if !self.buffer.is_null() {
+            unmap_file(self.mapping_start(), self.view_offset + self.length, &self.platform_data);
+        }
+    }
+}
+
+impl Deref for FileBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.buffer.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.buffer, self.length) }
+        }
+    }
+}
+
+// The debug representation summarizes the buffer rather than printing its contents. Printing the
+// contents would fault in the entire file, and for large files the output would be unusable.
+/// Prints a summary of the buffer, rather than its contents. This does not access the mapping,
+/// so it does not fault in pages. The number of resident pages is queried like `resident_len()`
+/// does, and it is printed as unknown where residency cannot be observed.
+impl fmt::Debug for FileBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("FileBuffer");
+        debug
+            .field("path", &self.path)
+            .field("len", &self.length)
+            .field("page_size", &self.page_size);
+        match self.count_resident_pages() {
+            Some(pages) => debug.field("resident", &format_args!("{} pages", pages)),
+            None => debug.field("resident", &format_args!("unknown")),
+        };
+        debug.finish()
+    }
+}
+
+impl AsRef<[u8]> for FileBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.deref()
+    }
+}
+
+/// Compares the contents of the buffers, byte by byte.
+///
+/// Comparing takes time linear in the length of the buffers, and it faults in the pages that are
+/// compared, which can mean reading large parts of both files from disk.
+impl PartialEq for FileBuffer {
+    fn eq(&self, other: &FileBuffer) -> bool {
+        self.deref() == other.deref()
+    }
+}
+
+impl Eq for FileBuffer {}
+
+/// Orders buffers lexicographically by their contents, like byte slices.
+///
+/// Like comparing for equality, this takes time linear in the length of the common prefix of the
+/// buffers, and it faults in the pages that are compared.
+impl PartialOrd for FileBuffer {
+    fn partial_cmp(&self, other: &FileBuffer) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FileBuffer {
+    fn cmp(&self, other: &FileBuffer) -> cmp::Ordering {
+        self.deref().cmp(other.deref())
+    }
+}
+
+/// Hashes the contents of the buffer, like a byte slice, which reads the entire buffer.
+impl Hash for FileBuffer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.deref().hash(state)
+    }
+}
+
+/// Borrows the contents of the buffer. Because a buffer compares and hashes like its contents,
+/// a map keyed by buffers can be queried with a byte slice.
+impl Borrow<[u8]> for FileBuffer {
+    fn borrow(&self) -> &[u8] {
+        self.deref()
+    }
+}
+
+/// Interprets the buffer as UTF-8 text, without copying.
+impl<'a> TryFrom<&'a FileBuffer> for &'a str {
+    type Error = str::Utf8Error;
+
+    fn try_from(fbuffer: &'a FileBuffer) -> Result<&'a str, str::Utf8Error> {
+        str::from_utf8(fbuffer)
+    }
+}
+
+/// Borrows the buffer as a byte slice.
+///
+/// This is equivalent to dereferencing the buffer, but it lets a buffer be passed to generic code
+/// that is bounded on `Into<&[u8]>`.
+impl<'a> From<&'a FileBuffer> for &'a [u8] {
+    fn from(fbuffer: &'a FileBuffer) -> &'a [u8] {
+        fbuffer
+    }
+}
+
+/// Interprets the buffer as a nul-terminated C string, without copying.
+///
+/// The buffer must end in a nul byte, and must not contain any other nul bytes.
+// `FromBytesWithNulError` is stable since Rust 1.10, but Clippy confuses it with its re-export in
+// `core::ffi`, which is newer.
+#[allow(clippy::incompatible_msrv)]
+impl<'a> TryFrom<&'a FileBuffer> for &'a CStr {
+    type Error = FromBytesWithNulError;
+
+    fn try_from(fbuffer: &'a FileBuffer) -> Result<&'a CStr, FromBytesWithNulError> {
+        CStr::from_bytes_with_nul(fbuffer)
+    }
+}
+
+/// Takes over a mapping that was created with the `memmap2` crate, so the residency and prefetch
+/// methods of this crate can be used on it.
+///
+/// The buffer keeps the `Mmap` alive, and the mapping is unmapped when the buffer is dropped. The
+/// buffer has no access to the mapped file, so methods that advise on the file, such as
+/// `advise_file()`, have no effect, and `raw_fd()` returns `None`.
+///
+/// The methods of this crate assume that a mapping starts at a page boundary. A mapping that was
+/// created at an offset that is not a multiple of the page size does not, and converting it fails
+/// with an error of kind `InvalidInput`.
+///
+/// This impl is only available with the `memmap2` feature.
+#[cfg(feature = "memmap2")]
+impl TryFrom<memmap2::Mmap> for FileBuffer {
+    type Error = io::Error;
+
+    fn try_from(mmap: memmap2::Mmap) -> io::Result<FileBuffer> {
+        // An empty `Mmap` may still own a mapping, but there is nothing to read from it, so it
+        // can be unmapped right away.
+        if mmap.is_empty() { return Ok(FileBuffer::empty()); }
+
+        let page_size = get_page_size();
+        if mmap.as_ptr() as usize % page_size != 0 {
+            let msg = "mapping does not start at a page boundary";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+
+        let length = mmap.len();
+        let (buffer, platform_data) = adopt_mmap(mmap);
+        let fbuffer = FileBuffer {
+            page_size,
+            buffer,
+            length,
+            platform_data,
+            stats: None,
+            prefetch_backend: PrefetchBackend::Madvise,
+            view_offset: 0,
+            path: None,
+            file_len: length as u64,
+            open_advice: Advice::Normal,
+            sequential_guards: Mutex::new(0),
+        };
+        Ok(fbuffer)
+    }
+}
+
+/// Copies the contents of the buffer into a new vector, and unmaps the buffer.
+///
+/// Note that this reads the entire file into memory, which defeats the purpose of mapping it.
+/// This is a convenience for when an owned copy turns out to be needed after all, not a
+/// recommended way of reading a file.
+impl From<FileBuffer> for Vec<u8> {
+    fn from(fbuffer: FileBuffer) -> Vec<u8> {
+        fbuffer.to_vec()
+    }
+}
+
+impl FileBufferMut {
+    /// Maps `length` bytes of zeroed, page-aligned, writable memory that is not backed by a file.
+    ///
+    /// This is useful for scratch space and test fixtures that should behave like a mapped file.
+    /// The memory is obtained with `mmap(MAP_ANONYMOUS)` on Unix-ish platforms, and from a file
+    /// mapping backed by the paging file on Windows.
+    pub fn anonymous(length: usize) -> io::Result<FileBufferMut> {
+        let (buffer, platform_data) = map_anonymous(length)?;
+        let fbuffer = FileBufferMut {
+            page_size: get_page_size(),
+            buffer,
+            length,
+            platform_data,
+        };
+        Ok(fbuffer)
+    }
+
+    /// Maps the file at `path` into memory as a private, writable copy.
+    ///
+    /// The mapping is copy-on-write: it is created with `MAP_PRIVATE` on Unix-ish platforms, and
+    /// with `FILE_MAP_COPY` on Windows. Writes to the buffer are never written back to the file,
+    /// and they are not visible to other mappings of the file. Only the pages that are written to
+    /// are copied, the remaining pages are shared with the page cache.
+    pub fn open_cow<P: AsRef<Path>>(path: P) -> io::Result<FileBufferMut> {
+        let path = path.as_ref();
+        open_naming_path(path, || {
+            let file = open_path(path, &OpenOptions::new())?;
+            let length = get_file_len(&file)?;
+
+            if length > isize::MAX as u64 {
+                let msg = "file is larger than address space";
+                return Err(io::Error::new(io::ErrorKind::Other, msg));
+            }
+
+            let (buffer, platform_data) = map_file_cow(file, length as usize)?;
+            let fbuffer = FileBufferMut {
+                page_size: get_page_size(),
+                buffer,
+                length: length as usize,
+                platform_data,
+            };
+            Ok(fbuffer)
+        })
+    }
+
+    /// Makes the buffer read-only in place, and returns it as a `FileBuffer`.
+    ///
+    /// This is useful to seal a buffer after filling it, so it cannot accidentally be written to
+    /// afterwards. Pending writes are flushed to the file first, with `msync` on Unix-ish
+    /// platforms and `FlushViewOfFile` on Windows, and then the write permission is dropped with
+    /// `mprotect` or `VirtualProtect`. The buffer stays at the same address, so pointers into it
+    /// remain valid. For copy-on-write and anonymous buffers, writes are never written to a file,
+    /// so there is nothing to flush, and the buffer keeps the written contents.
+    pub fn into_readonly(self) -> io::Result<FileBuffer> {
+        if !self.buffer.is_null() {
+            protect_readonly(self.buffer, self.length, &self.platform_data)?;
+        }
+
+        // Move the mapping into the new buffer, without running the destructor that unmaps it.
+        let fbuffer = mem::ManuallyDrop::new(self);
+        let fbuffer = FileBuffer {
+            page_size: fbuffer.page_size,
+            buffer: fbuffer.buffer,
+            length: fbuffer.length,
+            platform_data: unsafe { ptr::read(&fbuffer.platform_data) },
+            stats: None,
+            prefetch_backend: PrefetchBackend::Madvise,
+            view_offset: 0,
+            path: None,
+            file_len: fbuffer.length as u64,
+            open_advice: Advice::Normal,
+            sequential_guards: Mutex::new(0),
+        };
+        Ok(fbuffer)
+    }
+
+    /// Advises the kernel that a slice of the buffer is no longer needed.
+    ///
+    /// Where supported (Linux 4.5 and later, the BSDs, and macOS), this uses `MADV_FREE`, which
+    /// lets the kernel reclaim the pages lazily, only when it comes under memory pressure. A write
+    /// to a page before it is reclaimed cancels the reclamation of that page. Elsewhere this falls
+    /// back to `MADV_DONTNEED`, which drops the pages immediately. Either way, **the contents of
+    /// the slice are unspecified afterwards**, until they are overwritten. Reading a freed page
+    /// yields either its old contents or zeroes.
+    ///
+    /// On macOS, for buffers from `anonymous()`, this uses `MADV_FREE_REUSABLE` instead, so the
+    /// freed pages are accounted as reclaimable and no longer count towards the memory footprint
+    /// of the process.
+    ///
+    /// Only pages that lie entirely within the slice are freed, so bytes outside of the slice keep
+    /// their contents. On Windows this is a no-op.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the buffer.
+    pub fn advise_free(&mut self, offset: usize, length: usize) -> io::Result<()> {
+        // The specified offset and length must lie within the buffer.
+        assert!(offset + length <= self.length);
+
+        // Unlike for read-only buffers, we cannot round outwards here, that would discard the
+        // contents of the neighbouring bytes.
+        let aligned_offset = round_up_to(offset, self.page_size);
+        let aligned_end = round_down_to(offset + length, self.page_size);
+
+        // This is a no-op for empty buffers, and if the slice does not span a full page.
+        if self.buffer.is_null() || aligned_end <= aligned_offset { return Ok(()); }
+
+        let buffer = unsafe { self.buffer.add(aligned_offset) };
+        advise_free(buffer, &self.platform_data, aligned_end - aligned_offset)
+    }
+
+    /// Returns the buffer as a mutable slice, to fill it in place.
+    ///
+    /// This is the same slice that the buffer dereferences to mutably, as a method that is easier
+    /// to find. It makes it possible to use the mapping as the destination of `Read::read()`, or
+    /// of a foreign function that fills a buffer, without copying through an intermediate buffer.
+    /// The slice is always initialized: an anonymous buffer starts out zeroed, and a buffer
+    /// opened with `open_cow()` starts out with the contents of the file.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+// See the corresponding impls for `FileBuffer`. Mutation requires `&mut FileBufferMut`, so
+// sharing `&FileBufferMut` across threads is fine too.
+unsafe impl Sync for FileBufferMut {}
+unsafe impl Send for FileBufferMut {}
+
+impl Drop for FileBufferMut {
+    fn drop(&mut self) {
+        if !self.buffer.is_null() {
+            unmap_file(self.buffer, self.length, &self.platform_data);
+        }
+    }
+}
+
+impl Deref for FileBufferMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.buffer.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.buffer, self.length) }
+        }
+    }
+}
+
+impl DerefMut for FileBufferMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        if self.buffer.is_null() {
+            &mut []
+        } else {
+            unsafe { slice::from_raw_parts_mut(self.buffer, self.length) }
+        }
+    }
+}
+
+impl fmt::Debug for FileBufferMut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileBufferMut")
+            .field("len", &self.length)
+            .finish()
+    }
+}
+
+impl AsRef<[u8]> for FileBufferMut {
+    fn as_ref(&self) -> &[u8] {
+        self.deref()
+    }
+}
+
+impl AsMut<[u8]> for FileBufferMut {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.deref_mut()
+    }
+}
+
+impl Borrow<[u8]> for FileBufferMut {
+    fn borrow(&self) -> &[u8] {
+        self.deref()
+    }
+}
+
+impl BorrowMut<[u8]> for FileBufferMut {
+    fn borrow_mut(&mut self) -> &mut [u8] {
+        self.deref_mut()
+    }
+}
+
+#[test]
+fn open_file() {
+    let fbuffer = FileBuffer::open("src/lib.rs");
+    assert!(fbuffer.is_ok());
+}
+
+#[test]
+fn make_resident() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+
+    // Touch the first page to make it resident.
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    // Now at least that part should be resident.
+    assert_eq!(fbuffer.resident_len(3, 10), 10);
+}
+
+#[test]
+fn prefetch_is_not_harmful() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+
+    // It is impossible to test that this actually works without root access to instruct the kernel
+    // to drop its caches, but at least we can verify that calling `prefetch` is not harmful.
+    fbuffer.prefetch(0, fbuffer.len());
+
+    // Reading from the file should still work as normal.
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+}
+
+#[test]
+fn prefetch_descending_is_not_harmful() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    fbuffer.prefetch_descending(len - 100, 100);
+    fbuffer.prefetch_descending(0, len);
+    fbuffer.prefetch_descending(len, 0);
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    empty.prefetch_descending(0, 0);
+}
+
+#[test]
+fn empty_buffer_behaves_like_an_empty_file() {
+    let empty = FileBuffer::empty();
+    assert_eq!(empty.len(), 0);
+    assert_eq!(&empty[..], &[][..]);
+    assert_eq!(empty.resident_len(0, 0), 0);
+    empty.prefetch(0, 0);
+    empty.prefetch_sync(0, 0).unwrap();
+    empty.advise_free(0, 0).unwrap();
+    empty.advise_file(0, 0, Advice::Sequential).unwrap();
+    assert_eq!(FileBuffer::default().len(), 0);
+}
+
+#[test]
+fn prefetch_with_readahead_backend_advises_whole_pages() {
+    let mut fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    fbuffer.set_prefetch_backend(PrefetchBackend::Readahead);
+    let page_size = fbuffer.page_size();
+    assert_eq!(fbuffer.prefetch_checked(1, 10).unwrap(), page_size);
+    fbuffer.prefetch(0, fbuffer.len());
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    let mut anonymous = FileBufferMut::anonymous(page_size * 2).unwrap().into_readonly().unwrap();
+    anonymous.set_prefetch_backend(PrefetchBackend::Readahead);
+    assert_eq!(anonymous.prefetch_checked(page_size, page_size).unwrap(), page_size);
+}
+
+#[test]
+fn as_bytes_and_to_vec_return_the_contents() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    assert_eq!(fbuffer.as_bytes(), &fbuffer[..]);
+    assert_eq!(fbuffer.to_vec(), fs::read("src/lib.rs").unwrap());
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(empty.as_bytes().is_empty());
+    assert!(empty.to_vec().is_empty());
+}
+
+#[test]
+fn resident_len_is_exact_at_a_partial_last_page() {
+    let page_size = get_page_size();
+    let len = page_size * 2 + 100;
+    let path = write_temp_file("resident-len-partial-page", len);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    fbuffer.prefetch_sync(0, len).unwrap();
+
+    assert_eq!(fbuffer.resident_len(0, len), len);
+    assert_eq!(fbuffer.resident_len(len - 1, 1), 1);
+    assert_eq!(fbuffer.resident_len(len, 0), 0);
+    assert_eq!(fbuffer.next_nonresident(page_size), None);
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+// The buffer holds atomic statistics counters and a mutex for sequential advice, which do not
+// affect how it hashes.
+#[allow(clippy::mutable_key_type)]
+fn buffers_can_be_looked_up_by_contents() {
+    use std::collections::HashMap;
+
+    let mut map = HashMap::new();
+    map.insert(FileBuffer::open("src/empty_file_for_testing.rs").unwrap(), "empty");
+    map.insert(FileBuffer::open("src/lib.rs").unwrap(), "lib");
+
+    let contents = fs::read("src/lib.rs").unwrap();
+    assert_eq!(map.get(&contents[..]), Some(&"lib"));
+    assert_eq!(map.get(&b""[..]), Some(&"empty"));
+    assert_eq!(map.get(&b"Filebuffer"[..]), None);
+
+    let mut fbuffer_mut = FileBufferMut::anonymous(16).unwrap();
+    let slice: &mut [u8] = fbuffer_mut.borrow_mut();
+    slice[0] = 1;
+    let slice: &[u8] = fbuffer_mut.borrow();
+    assert_eq!(slice[0], 1);
+}
+
+#[test]
+fn buffers_compare_by_contents() {
+    let lib = FileBuffer::open("src/lib.rs").unwrap();
+    let lib_again = FileBuffer::open("src/lib.rs").unwrap();
+    let empty = FileBuffer::empty();
+    let path = write_temp_file("compare-by-contents", 10);
+    let counting = FileBuffer::open(&path).unwrap();
+
+    assert_eq!(lib, lib_again);
+    assert_ne!(lib, empty);
+    assert_eq!(empty, FileBuffer::open("src/empty_file_for_testing.rs").unwrap());
+
+    // The temp file starts with a zero byte, so it sorts before the source file.
+    let mut buffers = vec![lib, empty, counting];
+    buffers.sort();
+    assert_eq!(buffers[0].len(), 0);
+    assert_eq!(buffers[1].len(), 10);
+    assert_eq!(buffers[2], lib_again);
+    assert!(buffers[1] < buffers[2]);
+
+    drop(buffers);
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn resident_len_reports_tmpfs_files_as_resident() {
+    // Not every system mounts tmpfs at `/dev/shm`, skip the test there.
+    let path = Path::new("/dev/shm").join(format!("filebuffer-tmpfs-{}", std::process::id()));
+    if fs::write(&path, vec![7_u8; 100_000]).is_err() { return; }
+
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    if is_memory_backed(&fbuffer.platform_data) {
+        assert_eq!(fbuffer.resident_len(0, 100_000), 100_000);
+        assert_eq!(fbuffer.resident_len(99_999, 1), 1);
+    }
+    assert_eq!(fbuffer[99_999], 7);
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn read_array_copies_fixed_size_fields() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    assert_eq!(fbuffer.read_array::<10>(3), Some(*b"Filebuffer"));
+    assert_eq!(fbuffer.read_array::<4>(len - 4).unwrap(), fbuffer[len - 4..]);
+    assert_eq!(fbuffer.read_array::<0>(len), Some([]));
+    assert_eq!(fbuffer.read_array::<4>(len - 3), None);
+    assert_eq!(fbuffer.read_array::<1>(len), None);
+    assert_eq!(fbuffer.read_array::<1>(usize::MAX), None);
+}
+
+#[test]
+fn open_nonblocking_faults_in_the_file() {
+    let fbuffer = FileBuffer::open_nonblocking("src/lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    // The background thread keeps going until the entire file is resident. Wait for it, but for
+    // at most about ten seconds, so a prefetcher that does nothing fails the test rather than
+    // hanging it. Where residency cannot be observed, there is nothing to wait for.
+    if !cfg!(any(windows, feature = "no-residency")) {
+        let became_resident = (0..10_000).any(|_| {
+            let done = fbuffer.next_nonresident(0).is_none();
+            if !done { thread::sleep(Duration::from_millis(1)); }
+            done
+        });
+        assert!(became_resident, "the file did not become resident");
+    }
+
+    // Dropping the buffer while the thread may still run must be fine too.
+    drop(FileBuffer::open_nonblocking("src/lib.rs").unwrap());
+    let empty = FileBuffer::open_nonblocking("src/empty_file_for_testing.rs").unwrap();
+    assert_eq!(empty.len(), 0);
+}
+
+#[test]
+fn resident_slice_agrees_with_resident_len() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    fbuffer.prefetch_sync(0, len).unwrap();
+
+    // On a quiet system the pages do not get evicted between these calls.
+    let slice = fbuffer.resident_slice(10);
+    assert_eq!(slice.len(), fbuffer.resident_len(10, len - 10));
+    assert_eq!(slice, &fbuffer[10..10 + slice.len()]);
+    assert!(fbuffer.resident_slice(len).is_empty());
+    assert!(FileBuffer::empty().resident_slice(0).is_empty());
+}
+
+#[test]
+fn prefault_parallel_makes_the_file_resident() {
+    let len = 4 * 1024 * 1024 + 10;
+    let path = write_temp_file("prefault-parallel", len);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+
+    for &threads in &[0, 1, 3, 10_000] {
+        fbuffer.prefault_parallel(threads);
+        // On a quiet system the pages are not evicted right after they were faulted in.
+        assert_eq!(fbuffer.resident_len(0, len), len);
+    }
+    assert_eq!(fbuffer[len - 1], ((len - 1) % 251) as u8);
+
+    FileBuffer::empty().prefault_parallel(4);
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn poll_resident_chunk_covers_the_buffer() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let mut contents = Vec::new();
+
+    loop {
+        match fbuffer.poll_resident_chunk(contents.len()) {
+            Poll::Ready([]) => break,
+            Poll::Ready(chunk) => {
+                assert!(chunk.len() <= fbuffer.chunk_len_hint());
+                contents.extend_from_slice(chunk);
+            }
+            Poll::Pending => thread::yield_now(),
+        }
+    }
+
+    assert_eq!(&contents[..], &fbuffer[..]);
+
+    let empty = FileBuffer::empty();
+    assert_eq!(empty.poll_resident_chunk(0), Poll::Ready(&[][..]));
+}
+
+#[test]
+fn prefetch_try_reports_the_resident_prefix() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    // Reading the file populates the page cache, which the mapping shares.
+    assert_eq!(fs::read("src/lib.rs").unwrap().len(), len);
+    assert_eq!(fbuffer.prefetch_try(0, len), len);
+    assert!(fbuffer.prefetch_try(10, 20) <= 20);
+    assert_eq!(fbuffer.prefetch_try(len, 0), 0);
+    assert_eq!(FileBuffer::empty().prefetch_try(0, 0), 0);
+}
+
+#[test]
+fn prefetch_sync_makes_the_range_resident() {
+    let path = write_temp_file("prefetch-sync", 100_000);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    fbuffer.prefetch_sync(0, 100_000).unwrap();
+    fbuffer.prefetch_sync(99_999, 1).unwrap();
+    fbuffer.prefetch_sync(100_000, 0).unwrap();
+
+    // Reading the file populates the page cache, which the mapping shares.
+    assert_eq!(fbuffer.resident_len(0, 100_000), 100_000);
+
+    // Truncating drops the pages past the new end, so they must be read, which fails. Windows
+    // does not allow truncating a mapped file.
+    if fs::OpenOptions::new().write(true).open(&path).and_then(|f| f.set_len(5_000)).is_ok() {
+        let truncated = fbuffer.prefetch_sync(90_000, 10_000);
+        assert_eq!(truncated.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn map_segments_returns_the_segments() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    let segments = [(3, 10), (0, 2), (len - 1, 1), (len, 0), (3, 4)];
+    let slices = fbuffer.map_segments(&segments).unwrap();
+    assert_eq!(slices, vec![&b"Filebuffer"[..], b"//", &fbuffer[len - 1..], b"", b"File"]);
+    fbuffer.prefetch_segments(&segments).unwrap();
+    assert!(fbuffer.map_segments(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn map_segments_names_the_first_segment_out_of_range() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    let segments = [(0, 10), (len - 1, 2), (usize::MAX, 2)];
+    let err = fbuffer.map_segments(&segments).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(err.to_string().starts_with("segment 1 at offset"), "{}", err);
+
+    let err = fbuffer.prefetch_segments(&segments[2..]).unwrap_err();
+    assert!(err.to_string().starts_with("segment 0 at offset"), "{}", err);
+}
+
+#[test]
+fn prefetch_checked_reports_page_aligned_length() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let page_size = fbuffer.page_size();
+
+    // A range within a single page is rounded out to the full page.
+    assert_eq!(fbuffer.prefetch_checked(3, 10).unwrap(), page_size);
+
+    // The full file is rounded up to a multiple of the page size.
+    let advised = fbuffer.prefetch_checked(0, fbuffer.len()).unwrap();
+    assert!(advised >= fbuffer.len());
+    assert_eq!(advised % page_size, 0);
+}
+
+#[test]
+fn prefetch_checked_prefetches_large_ranges() {
+    // Large enough to also submit `readahead` calls on Linux.
+    let len = 9 * 1024 * 1024 + 10;
+    let path = write_temp_file("prefetch-large", len);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+
+    let advised = fbuffer.prefetch_checked(0, len).unwrap();
+    assert!(advised >= len);
+    assert_eq!(fbuffer.prefetch_checked(10, len - 10).unwrap(), advised);
+    assert_eq!(fbuffer[len - 1], ((len - 1) % 251) as u8);
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn resolve_range_handles_all_bound_variants() {
+    use std::panic;
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    assert_eq!(fbuffer.resolve_range(3..13), (3, 10));
+    assert_eq!(fbuffer.resolve_range(3..=13), (3, 11));
+    assert_eq!(fbuffer.resolve_range(1024..), (1024, len - 1024));
+    assert_eq!(fbuffer.resolve_range(..4096), (0, 4096));
+    assert_eq!(fbuffer.resolve_range(..=4095), (0, 4096));
+    assert_eq!(fbuffer.resolve_range(..), (0, len));
+    assert_eq!(fbuffer.resolve_range((Bound::Excluded(2), Bound::Excluded(13))), (3, 10));
+
+    // Bounds past `usize::MAX` must panic rather than wrap around to an empty range.
+    let max_included = panic::catch_unwind(|| fbuffer.resolve_range(..=usize::MAX));
+    let max_excluded = panic::catch_unwind(|| {
+        fbuffer.resolve_range((Bound::Excluded(usize::MAX), Bound::Unbounded))
+    });
+    assert!(max_included.is_err());
+    assert!(max_excluded.is_err());
+}
+
+#[test]
+fn range_methods_agree_with_offset_length_methods() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    // Touch every byte to make the file resident.
+    assert!(fbuffer.iter().map(|&x| x as u64).sum::<u64>() > 0);
+
+    fbuffer.prefetch_range(..);
+    fbuffer.prefetch_range(1024..);
+    fbuffer.prefetch_range(..4096);
+    assert_eq!(fbuffer.resident_len_range(..), len);
+    assert_eq!(fbuffer.resident_len_range(3..13), fbuffer.resident_len(3, 10));
+    assert_eq!(fbuffer.resident_len_range(1024..), fbuffer.resident_len(1024, len - 1024));
+    assert_eq!(fbuffer.resident_len_range(..=4095), fbuffer.resident_len(0, 4096));
+
+    fbuffer.advise_free_range(1024..).unwrap();
+    fbuffer.advise_free_range(..).unwrap();
+}
+
+#[test]
+#[should_panic]
+fn range_past_end_panics() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    fbuffer.prefetch_range(..fbuffer.len() + 1);
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn digest_matches_hashing_the_slice() {
+    use digest::Digest;
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let expected = sha2::Sha256::digest(&fbuffer[..]);
+    assert_eq!(fbuffer.digest::<sha2::Sha256>(), expected);
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert_eq!(empty.digest::<sha2::Sha256>(), sha2::Sha256::digest(b""));
+}
+
+#[cfg(feature = "digest")]
+#[test]
+fn digest_resident_resumes_where_it_stopped() {
+    use digest::Digest;
+
+    // Reading the file makes it resident, so hashing gets to the end.
+    let contents = fs::read("src/lib.rs").unwrap();
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(&fbuffer[..100]);
+
+    let mut offset = 100;
+    for _ in 0..1000 {
+        offset = fbuffer.digest_resident(&mut hasher, offset);
+        if offset == fbuffer.len() { break; }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(offset, contents.len());
+    assert_eq!(hasher.finalize(), sha2::Sha256::digest(&contents));
+
+    let mut hasher = sha2::Sha256::new();
+    assert_eq!(fbuffer.digest_resident(&mut hasher, fbuffer.len()), fbuffer.len());
+    assert_eq!(hasher.finalize(), sha2::Sha256::digest(b""));
+}
+
+#[cfg(feature = "memmap2")]
+#[test]
+fn try_from_mmap_keeps_the_mapping_alive() {
+    let file = fs::File::open("src/lib.rs").unwrap();
+    let mmap = unsafe { memmap2::Mmap::map(&file).unwrap() };
+    drop(file);
+
+    let fbuffer = FileBuffer::try_from(mmap).unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+    fbuffer.prefetch(0, fbuffer.len());
+    assert!(fbuffer.resident_len(0, fbuffer.len()) <= fbuffer.len());
+    fbuffer.advise_file(0, fbuffer.len(), Advice::WillNeed).unwrap();
+
+    let file = fs::File::open("src/lib.rs").unwrap();
+    let mmap = unsafe { memmap2::MmapOptions::new().offset(1).map(&file).unwrap() };
+    let err = FileBuffer::try_from(mmap).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let file = fs::File::open("src/empty_file_for_testing.rs").unwrap();
+    let mmap = unsafe { memmap2::Mmap::map(&file).unwrap() };
+    assert_eq!(FileBuffer::try_from(mmap).unwrap().len(), 0);
+}
+
+#[cfg(feature = "memmap2")]
+#[test]
+fn leak_keeps_an_adopted_mapping_alive() {
+    let file = fs::File::open("src/lib.rs").unwrap();
+    let mmap = unsafe { memmap2::Mmap::map(&file).unwrap() };
+    drop(file);
+
+    let expected = fs::read("src/lib.rs").unwrap();
+    let leaked = FileBuffer::try_from(mmap).unwrap().leak();
+    assert_eq!(leaked, &expected[..]);
+}
+
+#[test]
+fn open_sequential_maps_the_file() {
+    let fbuffer = FileBuffer::open_sequential("src/lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+    assert_eq!(fbuffer.path(), Some(Path::new("src/lib.rs")));
+
+    let empty = FileBuffer::open_sequential("src/empty_file_for_testing.rs").unwrap();
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn open_with_options_uses_the_given_options() {
+    let mut opts = fs::OpenOptions::new();
+    opts.read(true);
+    let fbuffer = FileBuffer::open_with_options("src/lib.rs", &opts).unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+    assert_eq!(fbuffer.path(), Some(Path::new("src/lib.rs")));
+
+    // Read access is added when it is missing.
+    let opts = fs::OpenOptions::new();
+    let fbuffer = FileBuffer::open_with_options("src/lib.rs", &opts).unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    // The options are respected, so a file that does not exist is not created.
+    let mut opts = fs::OpenOptions::new();
+    opts.read(true).create(false);
+    assert!(FileBuffer::open_with_options("does_not_exist.txt", &opts).is_err());
+}
+
+#[test]
+fn sliding_returns_overlapping_windows() {
+    let fbuffer = OpenOptions::new().track_stats(true).open("src/lib.rs").unwrap();
+
+    let windows: Vec<&[u8]> = fbuffer.sliding(64, 48).collect();
+    let expected: Vec<&[u8]> = fbuffer.windows(64).step_by(48).collect();
+    assert_eq!(windows, expected);
+
+    // The windows advance by much less than a chunk, but prefetching advances by chunks.
+    let prefetch_calls = fbuffer.stats().unwrap().prefetch_calls;
+    assert!(prefetch_calls <= fbuffer.len() / fbuffer.chunk_len_hint() + 1);
+
+    // Windows with gaps between them, and windows that do not fit.
+    let windows: Vec<&[u8]> = fbuffer.sliding(2, 1000).collect();
+    assert_eq!(windows.len(), (fbuffer.len() - 2) / 1000 + 1);
+    assert_eq!(windows[1], &fbuffer[1000..1002]);
+    assert_eq!(fbuffer.sliding(fbuffer.len() + 1, 1).count(), 0);
+    assert_eq!(fbuffer.sliding(fbuffer.len(), 1).count(), 1);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn sliding_restores_the_advice_from_open_time() {
+    // The kernel lists the access pattern advice of a mapping in the `VmFlags` of its entry in
+    // `/proc/self/smaps`: `sr` for sequential and `rr` for random access.
+    fn vm_flags(fbuffer: &FileBuffer) -> (bool, bool) {
+        let smaps = fs::read_to_string("/proc/self/smaps").unwrap();
+        let address = fbuffer.as_ptr() as usize;
+        let mut in_mapping = false;
+        for line in smaps.lines() {
+            let range = line.split_whitespace().next().unwrap_or("");
+            let mut bounds = range.splitn(2, '-').map(|x| usize::from_str_radix(x, 16));
+            if let (Some(Ok(start)), Some(Ok(end))) = (bounds.next(), bounds.next()) {
+                in_mapping = start <= address && address < end;
+            } else if in_mapping && line.starts_with("VmFlags:") {
+                let flags: Vec<&str> = line.split_whitespace().collect();
+                return (flags.contains(&"sr"), flags.contains(&"rr"));
+            }
+        }
+        panic!("the mapping is not listed in /proc/self/smaps");
+    }
+    let sequential = (true, false);
+    let random = (false, true);
+    let normal = (false, false);
+
+    let fbuffer = OpenOptions::new().advise(Advice::Random).open("src/lib.rs").unwrap();
+    assert_eq!(vm_flags(&fbuffer), random);
+
+    // The advice is only restored when the last iterator is dropped.
+    let first = fbuffer.sliding(64, 64);
+    let second = fbuffer.sliding(64, 64);
+    assert_eq!(vm_flags(&fbuffer), sequential);
+    drop(first);
+    assert_eq!(vm_flags(&fbuffer), sequential);
+    drop(second);
+    assert_eq!(vm_flags(&fbuffer), random);
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    assert_eq!(fbuffer.sliding(64, 64).count(), fbuffer.len() / 64);
+    assert_eq!(vm_flags(&fbuffer), normal);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_chunks_covers_the_buffer_in_page_multiples() {
+    use rayon::prelude::*;
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let page_size = fbuffer.page_size();
+    let chunks: Vec<&[u8]> = fbuffer.par_chunks(1).collect();
+    assert_eq!(chunks.concat(), &fbuffer[..]);
+    assert!(chunks[..chunks.len() - 1].iter().all(|chunk| chunk.len() == page_size));
+
+    let total: usize = fbuffer.par_chunks(page_size * 2).map(|chunk| chunk.len()).sum();
+    assert_eq!(total, fbuffer.len());
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert_eq!(empty.par_chunks(page_size).count(), 0);
+}
+
+#[test]
+fn offset_of_finds_slices_within_the_buffer() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    assert_eq!(fbuffer.offset_of(&fbuffer[..]), Some(0));
+    assert_eq!(fbuffer.offset_of(&fbuffer[3..13]), Some(3));
+    assert_eq!(fbuffer.offset_of(&fbuffer[len - 1..]), Some(len - 1));
+    assert_eq!(fbuffer.offset_of(&fbuffer[len..]), Some(len));
+}
+
+#[test]
+fn offset_of_rejects_slices_outside_the_buffer() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let other = FileBuffer::open("src/lib.rs").unwrap();
+    let local = [0u8; 16];
+
+    assert_eq!(fbuffer.offset_of(&other[3..13]), None);
+    assert_eq!(fbuffer.offset_of(&local[..]), None);
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert_eq!(empty.offset_of(&fbuffer[..]), None);
+}
+
+#[test]
+fn copy_to_copies_ranges_within_the_buffer() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    let mut dst = [0u8; 10];
+    fbuffer.copy_to(3, &mut dst).unwrap();
+    assert_eq!(&dst, b"Filebuffer");
+
+    // A range that ends exactly at the end of the buffer fits.
+    fbuffer.copy_to(len - 10, &mut dst).unwrap();
+    assert_eq!(&dst[..], &fbuffer[len - 10..]);
+
+    // Copying into an empty slice is fine, even at the very end.
+    fbuffer.copy_to(len, &mut []).unwrap();
+}
+
+#[test]
+fn read_at_returns_short_reads_at_the_end() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    let mut buf = [0u8; 10];
+    assert_eq!(fbuffer.read_at(&mut buf, 3).unwrap(), 10);
+    assert_eq!(&buf, b"Filebuffer");
+
+    assert_eq!(fbuffer.read_at(&mut buf, len as u64 - 4).unwrap(), 4);
+    assert_eq!(&buf[..4], &fbuffer[len - 4..]);
+    assert_eq!(fbuffer.read_at(&mut buf, len as u64).unwrap(), 0);
+    assert_eq!(fbuffer.read_at(&mut buf, u64::MAX).unwrap(), 0);
+    assert_eq!(fbuffer.read_at(&mut [], 0).unwrap(), 0);
+}
+
+#[test]
+fn read_exact_at_rejects_ranges_past_the_end() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len() as u64;
+
+    let mut buf = [0u8; 10];
+    fbuffer.read_exact_at(&mut buf, 3).unwrap();
+    assert_eq!(&buf, b"Filebuffer");
+    fbuffer.read_exact_at(&mut buf, len - 10).unwrap();
+    fbuffer.read_exact_at(&mut [], len).unwrap();
+
+    let err = fbuffer.read_exact_at(&mut buf, len - 9).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    let err = fbuffer.read_exact_at(&mut buf, u64::MAX).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+#[allow(clippy::legacy_numeric_constants)]
+fn copy_to_rejects_ranges_past_the_end() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    let mut dst = [0u8; 10];
+    let err = fbuffer.copy_to(len - 9, &mut dst).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(fbuffer.copy_to(len + 1, &mut []).is_err());
+    assert!(fbuffer.copy_to(usize::max_value(), &mut dst).is_err());
+}
+
+/// Writes a file of `len` bytes to the temporary directory, where byte `i` is `i % 251`.
+#[cfg(test)]
+fn write_temp_file(name: &str, len: usize) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("filebuffer-{}-{}", name, std::process::id()));
+    let bytes: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+#[test]
+fn prefetch_dir_skips_subdirectories_unless_recursive() {
+    let dir = std::env::temp_dir().join(format!("filebuffer-prefetch-dir-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a"), b"first file").unwrap();
+    fs::write(dir.join("empty"), b"").unwrap();
+    fs::write(dir.join("sub").join("b"), b"second file").unwrap();
+
+    prefetch_dir(&dir).unwrap();
+    prefetch_dir_recursive(&dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let err = prefetch_dir(&dir).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn open_with_hint_maps_the_file() {
+    for &advice in &[Advice::Normal, Advice::Sequential, Advice::Random, Advice::WillNeed] {
+        let fbuffer = FileBuffer::open_with_hint("src/lib.rs", advice).unwrap();
+        assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+    }
+
+    let empty = FileBuffer::open_with_hint("src/empty_file_for_testing.rs", Advice::Sequential);
+    assert_eq!(empty.unwrap().len(), 0);
+}
+
+#[test]
+fn open_range_maps_a_window() {
+    let granularity = get_allocation_granularity();
+    let path = write_temp_file("open-range", granularity * 3);
+
+    let head = FileBuffer::open_range(&path, 0, 10).unwrap();
+    let window = FileBuffer::open_range(&path, granularity as u64, granularity).unwrap();
+    let tail = OpenOptions::new()
+        .range(granularity as u64 * 2, granularity)
+        .advise(Advice::Sequential)
+        .open(&path)
+        .unwrap();
+    let empty = FileBuffer::open_range(&path, granularity as u64 * 3, 0).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(&head[..], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9][..]);
+    assert_eq!(window.len(), granularity);
+    assert_eq!(window[0], (granularity % 251) as u8);
+    assert_eq!(tail[tail.len() - 1], ((granularity * 3 - 1) % 251) as u8);
+    assert!(empty.is_empty());
+}
+
+#[test]
+#[allow(clippy::legacy_numeric_constants)]
+fn open_range_rejects_invalid_ranges() {
+    let granularity = get_allocation_granularity();
+    let path = write_temp_file("open-range-invalid", granularity * 2);
+
+    let past_end = FileBuffer::open_range(&path, granularity as u64, granularity + 1);
+    let unaligned = FileBuffer::open_range(&path, 1, 10);
+    let overflow = FileBuffer::open_range(&path, u64::max_value(), 1);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(past_end.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(unaligned.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(overflow.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn try_open_range_maps_aligned_and_unaligned_offsets() {
+    let granularity = get_allocation_granularity();
+    let len = granularity * 3;
+    let path = write_temp_file("try-open-range", len);
+    let contents = fs::read(&path).unwrap();
+
+    let offsets = [0, 1, granularity - 1, granularity, granularity + 7, len - 1];
+    for &offset in &offsets {
+        let length = cmp::min(granularity + 100, len - offset);
+        let fbuffer = FileBuffer::try_open_range(&path, offset as u64, length).unwrap().unwrap();
+        assert_eq!(&fbuffer[..], &contents[offset..offset + length]);
+
+        // The methods that work with pages must account for the unaligned start.
+        fbuffer.prefetch(0, length);
+        fbuffer.prefetch_ranges(&[(0, 1), (length - 1, 1)]);
+        fbuffer.prefetch_sync(0, length).unwrap();
+        fbuffer.advise_file(0, length, Advice::WillNeed).unwrap();
+        fbuffer.prefault_parallel(2);
+        assert_eq!(fbuffer.resident_len(0, length), length);
+        assert_eq!(fbuffer.guarded_slice(0, length).unwrap(), &contents[offset..offset + length]);
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn constructors_name_the_path_in_errors() {
+    let path = "src/does-not-exist.rs";
+    let check = |err: io::Error| {
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains(path), "{}", err);
+    };
+
+    check(FileBuffer::open(path).unwrap_err());
+    check(FileBuffer::open_sequential(path).unwrap_err());
+    check(FileBuffer::try_open_range(path, 0, 1).unwrap_err());
+    check(FileBuffer::open_if_newer(path, SystemTime::UNIX_EPOCH).unwrap_err());
+    check(FileBuffer::open_with_options(path, &fs::OpenOptions::new()).unwrap_err());
+    check(FileBuffer::open_with_len(path, 1).unwrap_err());
+    check(FileBuffer::open_cow_patched(path, &[]).unwrap_err());
+    check(FileBufferMut::open_cow(path).unwrap_err());
+    check(GrowableFileBuffer::open(path).unwrap_err());
+    check(FileBufferCache::new().open(path).unwrap_err());
+    check(FileBufferPool::new(1).open(path).unwrap_err());
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    check(FileBuffer::open_direct(path).unwrap_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn open_rejects_directories() {
+    let err = FileBuffer::open("src").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(err.to_string(), "failed to open src: cannot memory-map a directory");
+}
+
+#[cfg(unix)]
+#[test]
+fn open_reads_pipes_into_memory() {
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let reader = unsafe { fs::File::from_raw_fd(fds[0]) };
+    let mut writer = unsafe { fs::File::from_raw_fd(fds[1]) };
+
+    // More than fits in the pipe buffer, so reading must keep up with the writer.
+    let expected: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+    let bytes = expected.clone();
+    let writer_thread = std::thread::spawn(move || io::Write::write_all(&mut writer, &bytes));
+
+    let fbuffer = FileBuffer::from_file(reader, &OpenOptions::new()).unwrap();
+    writer_thread.join().unwrap().unwrap();
+    assert_eq!(&fbuffer[..], &expected[..]);
+    assert_eq!(fbuffer.resident_len(0, fbuffer.len()), fbuffer.len());
+    fbuffer.advise(0, fbuffer.len(), Advice::DontNeed).unwrap();
+    assert_eq!(&fbuffer[..], &expected[..]);
+    assert!(!fbuffer.is_stale().unwrap());
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let reader = unsafe { fs::File::from_raw_fd(fds[0]) };
+    drop(unsafe { fs::File::from_raw_fd(fds[1]) });
+    let empty = FileBuffer::from_file(reader, &OpenOptions::new()).unwrap();
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn open_if_newer_compares_the_modification_time() {
+    let path = write_temp_file("open-if-newer", 100);
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+
+    let before = FileBuffer::open_if_newer(&path, modified - Duration::from_secs(1)).unwrap();
+    let at = FileBuffer::open_if_newer(&path, modified).unwrap();
+    let after = FileBuffer::open_if_newer(&path, modified + Duration::from_secs(3600)).unwrap();
+
+    let fbuffer = before.unwrap();
+    assert_eq!(fbuffer.len(), 100);
+    assert_eq!(fbuffer[99], 99);
+    assert!(at.is_none());
+    assert!(after.is_none());
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn is_stale_detects_files_that_grew() {
+    let path = write_temp_file("stale", 10);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    assert!(!fbuffer.is_stale().unwrap());
+
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    io::Write::write_all(&mut file, &[10, 11]).unwrap();
+    drop(file);
+
+    // The buffer still exposes the bytes that were there when it was mapped.
+    assert!(fbuffer.is_stale().unwrap());
+    assert_eq!(&fbuffer[..], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9][..]);
+
+    let reloaded = FileBuffer::open(&path).unwrap();
+    assert!(!reloaded.is_stale().unwrap());
+    assert_eq!(reloaded.len(), 12);
+    assert!(!FileBuffer::empty().is_stale().unwrap());
+
+    drop((fbuffer, reloaded));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn open_reads_small_files_into_memory() {
+    let path = write_temp_file("read-small", 1000);
+    let expected: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+
+    let fbuffer = OpenOptions::new().read_threshold(1000).open(&path).unwrap();
+    assert!(is_heap_backed(&fbuffer.platform_data));
+    assert_eq!(fbuffer.buffer as usize % fbuffer.page_size(), 0);
+    assert_eq!(&fbuffer[..], &expected[..]);
+    assert_eq!(fbuffer.resident_len(0, 1000), 1000);
+
+    // Dropping pages would zero them, because the heap allocation is not backed by the file.
+    fbuffer.advise(0, 1000, Advice::DontNeed).unwrap();
+    assert_eq!(&fbuffer[..], &expected[..]);
+
+    let leaked = fbuffer.leak();
+    assert_eq!(leaked, &expected[..]);
+
+    let mapped = OpenOptions::new().read_threshold(999).open(&path).unwrap();
+    assert!(!is_heap_backed(&mapped.platform_data));
+    assert_eq!(&mapped[..], &expected[..]);
+
+    let ranged = OpenOptions::new().read_threshold(1000).range(0, 10).open(&path).unwrap();
+    assert!(!is_heap_backed(&ranged.platform_data));
+
+    drop((mapped, ranged));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn open_region_aligned_returns_the_aligned_region() {
+    let granularity = get_allocation_granularity();
+    let path = write_temp_file("region-aligned", granularity * 3);
+
+    let offset = granularity + 100;
+    let (fbuffer, in_view) = FileBuffer::open_region_aligned(&path, offset as u64, 50).unwrap();
+    assert_eq!(in_view, 100);
+    assert_eq!(fbuffer.len(), 150);
+    assert_eq!(fbuffer.as_ptr() as usize % fbuffer.page_size(), 0);
+    let expected: Vec<u8> = (granularity..offset + 50).map(|i| (i % 251) as u8).collect();
+    assert_eq!(&fbuffer[..], &expected[..]);
+
+    let (aligned, in_view) = FileBuffer::open_region_aligned(&path, granularity as u64, 0).unwrap();
+    assert_eq!(in_view, 0);
+    assert!(aligned.is_empty());
+
+    let past_end = FileBuffer::open_region_aligned(&path, offset as u64, granularity * 2);
+    assert_eq!(past_end.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    drop((fbuffer, aligned));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_len_is_the_length_of_the_entire_file() {
+    let granularity = get_allocation_granularity();
+    let path = write_temp_file("file-len", granularity * 2 + 10);
+
+    let whole = FileBuffer::open(&path).unwrap();
+    let range = FileBuffer::open_range(&path, granularity as u64, granularity).unwrap();
+    let window = FileBuffer::try_open_range(&path, 5, 10).unwrap().unwrap();
+    let prefix = FileBuffer::open_with_len(&path, 10).unwrap();
+
+    for fbuffer in &[&whole, &range, &window, &prefix] {
+        assert_eq!(fbuffer.file_len(), granularity as u64 * 2 + 10);
+    }
+    assert_eq!(whole.len() as u64, whole.file_len());
+    assert_eq!(whole.len_u64(), whole.file_len());
+    assert_eq!(range.len_u64(), granularity as u64);
+    assert_eq!(window.len_u64(), 10);
+    assert_eq!(FileBuffer::empty().file_len(), 0);
+    assert_eq!(FileBuffer::empty().len_u64(), 0);
+
+    drop((whole, range, window, prefix));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn try_open_range_handles_the_end_of_the_file() {
+    let granularity = get_allocation_granularity();
+    let len = granularity + 10;
+    let path = write_temp_file("try-open-range-end", len);
+
+    let at_end = FileBuffer::try_open_range(&path, len as u64, 0).unwrap().unwrap();
+    let past_end = FileBuffer::try_open_range(&path, len as u64 + 1, 0).unwrap();
+    let too_long = FileBuffer::try_open_range(&path, 5, len).unwrap_err();
+    let last_byte = FileBuffer::try_open_range(&path, len as u64 - 1, 1).unwrap().unwrap();
+
+    assert!(at_end.is_empty());
+    assert!(past_end.is_none());
+    assert_eq!(too_long.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(&last_byte[..], &[((len - 1) % 251) as u8]);
+
+    drop(at_end);
+    drop(last_byte);
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn try_open_range_reports_data_ranges_relative_to_the_buffer() {
+    let path = write_temp_file("try-open-range-data", 8192);
+    let fbuffer = FileBuffer::try_open_range(&path, 100, 5000).unwrap().unwrap();
+    assert_eq!(fbuffer.data_ranges().unwrap(), vec![0..5000]);
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn path_returns_the_path_that_was_opened() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    assert_eq!(fbuffer.path(), Some(Path::new("src/lib.rs")));
+    assert!(format!("{:?}", fbuffer).contains("src/lib.rs"));
+
+    let window = FileBuffer::try_open_range("src/lib.rs", 10, 10).unwrap().unwrap();
+    assert_eq!(window.path(), Some(Path::new("src/lib.rs")));
+
+    assert_eq!(FileBuffer::empty().path(), None);
+}
+
+#[test]
+fn reload_picks_up_changes_to_the_file() {
+    let path = write_temp_file("reload", 100);
+    let mut fbuffer = FileBuffer::open(&path).unwrap();
+    assert_eq!(fbuffer.len(), 100);
+
+    // Rewrite the file in place, so the buffer still refers to the same file.
+    fs::write(&path, b"a file that is shorter now").unwrap();
+    fbuffer.reload().unwrap();
+    assert_eq!(&fbuffer[..], b"a file that is shorter now");
+
+    fs::write(&path, b"").unwrap();
+    fbuffer.reload().unwrap();
+    assert!(fbuffer.is_empty());
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn reload_keeps_referring_to_a_deleted_file() {
+    let path = write_temp_file("reload-deleted", 100);
+    let mut fbuffer = FileBuffer::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    fs::write(&path, b"a different file").unwrap();
+
+    fbuffer.reload().unwrap();
+    assert_eq!(fbuffer.len(), 100);
+    assert_eq!(fbuffer[99], 99);
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reload_fails_without_a_file() {
+    let mut fbuffer = FileBuffer::empty();
+    assert_eq!(fbuffer.reload().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn resident_pages_reports_touched_pages() {
+    let page_size = get_page_size();
+    let mut fbuffer_mut = FileBufferMut::anonymous(page_size * 8).unwrap();
+
+    // Pages of an anonymous mapping only become resident when they are written to.
+    for page in (0..8).step_by(2) {
+        fbuffer_mut[page * page_size] = 1;
+    }
+    let fbuffer = fbuffer_mut.into_readonly().unwrap();
+
+    let all_pages: Vec<usize> = (0..8).map(|page| page * page_size).collect();
+    let expected = if cfg!(any(windows, feature = "no-residency")) {
+        all_pages.clone()
+    } else {
+        all_pages.iter().cloned().step_by(2).collect()
+    };
+    assert_eq!(fbuffer.resident_pages(0, fbuffer.len()), expected);
+    assert_eq!(fbuffer.resident_pages(page_size * 4 + 1, 1), vec![page_size * 4]);
+    assert_eq!(fbuffer.resident_pages(0, 0), Vec::<usize>::new());
+}
+
+#[test]
+fn advise_accepts_all_advice() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    let all_advice = [
+        Advice::Normal,
+        Advice::Sequential,
+        Advice::Random,
+        Advice::WillNeed,
+        Advice::DontNeed,
+        Advice::Cold,
+        Advice::PageOut,
+        Advice::Free,
+    ];
+
+    for &advice in &all_advice {
+        fbuffer.advise(0, len, advice).unwrap();
+        fbuffer.advise(3, 10, advice).unwrap();
+        fbuffer.advise(len, 0, advice).unwrap();
+        fbuffer.advise_file(0, len, advice).unwrap();
+    }
+
+    // The contents do not change, even after the pages were dropped.
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    for &advice in &all_advice {
+        empty.advise(0, 0, advice).unwrap();
+    }
+}
+
+#[test]
+fn advise_file_accepts_all_advice() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    fbuffer.prefetch_background(0, len).unwrap();
+    fbuffer.advise_file(3, 10, Advice::Sequential).unwrap();
+    fbuffer.advise_file(0, len, Advice::Random).unwrap();
+    fbuffer.advise_file(len / 2, len / 2, Advice::DontNeed).unwrap();
+    fbuffer.advise_file(0, len, Advice::Normal).unwrap();
+    fbuffer.advise_file(len, 0, Advice::WillNeed).unwrap();
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    empty.prefetch_background(0, 0).unwrap();
+}
+
+#[test]
+fn advise_normal_resets_access_pattern_advice() {
+    let fbuffer = OpenOptions::new().advise(Advice::Sequential).open("src/lib.rs").unwrap();
+    fbuffer.advise_file(0, fbuffer.len(), Advice::Random).unwrap();
+    fbuffer.advise_normal().unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    FileBuffer::open("src/empty_file_for_testing.rs").unwrap().advise_normal().unwrap();
+    FileBuffer::empty().advise_normal().unwrap();
+}
+
+#[cfg(windows)]
+#[test]
+fn mapped_region_info_reports_a_read_only_view() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let info = fbuffer.mapped_region_info().unwrap();
+    assert_eq!(info.state, winapi::um::winnt::MEM_COMMIT);
+    assert_eq!(info.protect, winapi::um::winnt::PAGE_READONLY);
+    assert!(info.region_size >= fbuffer.len());
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(empty.mapped_region_info().is_err());
+}
+
+#[cfg(windows)]
+#[test]
+fn open_named_shares_the_mapping_object() {
+    let name = format!("filebuffer-test-{}", std::process::id());
+    let first = FileBuffer::open_named("src/lib.rs", &name).unwrap();
+
+    // While the first mapping is alive, the name refers to its mapping object, so the second
+    // view shows the contents of lib.rs, even though it opens a different (and smaller) file.
+    let second = FileBuffer::open_named("src/options.rs", &name).unwrap();
+    assert_eq!(&first[3..13], &b"Filebuffer"[..]);
+    assert_eq!(&second[..], &first[..second.len()]);
+
+    let invalid = FileBuffer::open_named("src/lib.rs", "nul\0name");
+    assert_eq!(invalid.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn split_at_byte_frames_records() {
+    let path = write_temp_file("split-at-byte", 0);
+    fs::write(&path, b"foo\n\nbar\nbaz").unwrap();
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let mut records = Vec::new();
+    let mut from = 0;
+    while let Some((record, next)) = fbuffer.split_at_byte(from, b'\n') {
+        records.push(record);
+        from = next;
+    }
+
+    // The last record is not terminated by a delimiter, so it extends to the end of the buffer.
+    assert_eq!(records, [&b"foo"[..], &b""[..], &b"bar"[..], &b"baz"[..]]);
+    assert_eq!(from, fbuffer.len());
+    assert_eq!(fbuffer.split_at_byte(fbuffer.len() + 1, b'\n'), None);
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert_eq!(empty.split_at_byte(0, b'\n'), None);
+}
+
+#[test]
+fn open_with_len_maps_exactly_len_bytes() {
+    let fbuffer = FileBuffer::open_with_len("src/lib.rs", 13).unwrap();
+    assert_eq!(&fbuffer[..], &b"// Filebuffer"[..]);
+
+    let file_len = fs::metadata("src/lib.rs").unwrap().len() as usize;
+    let whole = FileBuffer::open_with_len("src/lib.rs", file_len).unwrap();
+    assert_eq!(whole.len(), file_len);
+
+    let empty = FileBuffer::open_with_len("src/lib.rs", 0).unwrap();
+    assert!(empty.is_empty());
+
+    let err = FileBuffer::open_with_len("src/lib.rs", file_len + 1).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn as_slice_of_reinterprets_the_buffer() {
+    let path = write_temp_file("as-slice-of", 0);
+    let words = [1u32, 2, 0xdeadbeef];
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_ne_bytes().to_vec()).collect();
+    fs::write(&path, &bytes).unwrap();
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    let truncated = FileBuffer::open_with_len(&path, 11).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(unsafe { fbuffer.as_slice_of::<u32>() }.unwrap(), &[1, 2, 0xdeadbeef][..]);
+    assert_eq!(unsafe { fbuffer.as_slice_of::<u8>() }.unwrap(), &fbuffer[..]);
+
+    let err = unsafe { truncated.as_slice_of::<u32>() }.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(unsafe { empty.as_slice_of::<u64>() }.unwrap().is_empty());
+}
+
+#[test]
+fn prefetch_ranges_accepts_scattered_ranges() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    fbuffer.prefetch_ranges(&[(len - 10, 10), (0, 1), (len / 2, 0), (3, 100)]);
+    fbuffer.prefetch_ranges(&[]);
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    empty.prefetch_ranges(&[(0, 0)]);
+}
+
+#[test]
+fn contains_ptr_checks_bounds() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    let start = fbuffer.as_ptr();
+
+    assert!(fbuffer.contains_ptr(start));
+    assert!(fbuffer.contains_ptr(fbuffer[len - 1..].as_ptr()));
+    assert!(!fbuffer.contains_ptr(start.wrapping_add(len)));
+    assert!(!fbuffer.contains_ptr(start.wrapping_sub(1)));
+    assert!(!fbuffer.contains_ptr(ptr::null()));
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(!empty.contains_ptr(empty.as_ptr()));
+}
+
+#[test]
+fn reslice_returns_none_out_of_range() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    assert_eq!(fbuffer.reslice(3..13), Some(&b"Filebuffer"[..]));
+    assert_eq!(fbuffer.reslice(len - 1..len), Some(&fbuffer[len - 1..]));
+    assert_eq!(fbuffer.reslice(len..len), Some(&[][..]));
+    assert_eq!(fbuffer.reslice(len..len + 1), None);
+    assert_eq!(fbuffer.reslice(len + 1..len + 1), None);
+    #[allow(clippy::reversed_empty_ranges)]
+    let reversed = fbuffer.reslice(13..3);
+    assert_eq!(reversed, None);
+}
+
+#[test]
+fn as_chunks_splits_into_arrays_and_remainder() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    let (chunks, remainder) = fbuffer.as_chunks::<10>();
+    assert_eq!(chunks.len(), len / 10);
+    assert_eq!(remainder, &fbuffer[len - len % 10..]);
+    assert_eq!(&chunks[0][3..], b"Filebuf");
+    assert!(chunks.iter().flatten().eq(fbuffer[..len - len % 10].iter()));
+
+    let (chunks, remainder) = fbuffer.as_chunks::<1>();
+    assert_eq!(chunks.len(), len);
+    assert!(remainder.is_empty());
+
+    let empty = FileBuffer::empty();
+    let (chunks, remainder) = empty.as_chunks::<4>();
+    assert!(chunks.is_empty() && remainder.is_empty());
+}
+
+#[test]
+fn reslice_accepts_any_range() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    assert_eq!(fbuffer.reslice(3..=12), Some(&b"Filebuffer"[..]));
+    assert_eq!(fbuffer.reslice(..13), Some(&fbuffer[..13]));
+    assert_eq!(fbuffer.reslice(len - 1..), Some(&fbuffer[len - 1..]));
+    assert_eq!(fbuffer.reslice(..), Some(&fbuffer[..]));
+    assert_eq!(fbuffer.reslice(..=len), None);
+    assert_eq!(fbuffer.reslice(..=usize::MAX), None);
+    assert_eq!(fbuffer.reslice((Bound::Excluded(usize::MAX), Bound::Unbounded)), None);
+}
+
+#[test]
+fn from_borrows_the_buffer_as_a_slice() {
+    fn count_newlines<'a, T: Into<&'a [u8]>>(bytes: T) -> usize {
+        bytes.into().iter().filter(|&&b| b == b'\n').count()
+    }
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let bytes: &[u8] = (&fbuffer).into();
+    assert_eq!(bytes, &fbuffer[..]);
+    assert_eq!(count_newlines(&fbuffer), count_newlines(&fbuffer[..]));
+}
+
+#[test]
+fn resident_len_batched_agrees_with_resident_len() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    fbuffer.prefetch(0, len);
+
+    // Whatever is resident, the batch size must not affect the outcome. On a quiet system the
+    // pages do not get evicted between these calls.
+    let expected = fbuffer.resident_len(0, len);
+    assert_eq!(fbuffer.resident_len_batched::<1>(0, len), expected);
+    assert_eq!(fbuffer.resident_len_batched::<3>(0, len), expected);
+    assert_eq!(fbuffer.resident_len_batched::<1024>(0, len), expected);
+}
+
+#[test]
+fn resident_len_with_scratch_agrees_with_resident_len() {
+    let page_size = get_page_size();
+    let path = write_temp_file("resident-scratch", page_size * 100 + 10);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    let len = fbuffer.len();
+    fbuffer.prefetch(0, len);
+
+    // The same scratch buffer can be reused across calls, whatever it contains.
+    let expected = fbuffer.resident_len(0, len);
+    let mut scratch = vec![true; 64];
+    assert_eq!(fbuffer.resident_len_with_scratch(0, len, &mut scratch), expected);
+    assert_eq!(fbuffer.resident_len_with_scratch(0, len, &mut scratch), expected);
+    assert_eq!(fbuffer.resident_len_with_scratch(0, len, &mut scratch[..1]), expected);
+    assert_eq!(fbuffer.resident_len_with_scratch(0, len, &mut [false; 7]), expected);
+    assert_eq!(fbuffer.resident_len_with_scratch(0, len, &mut vec![false; 1000]), expected);
+
+    let offset = page_size * 3 + 5;
+    let expected = fbuffer.resident_len(offset, len - offset);
+    assert_eq!(fbuffer.resident_len_with_scratch(offset, len - offset, &mut scratch), expected);
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn resident_bitmap_agrees_with_resident_len() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    let page_size = fbuffer.page_size();
+    fbuffer.prefetch(0, len);
+
+    let num_pages = (len + page_size - 1) / page_size;
+    let bitmap = fbuffer.resident_bitmap();
+    assert_eq!(bitmap.len(), (num_pages + 63) / 64);
+
+    // On a quiet system the pages do not get evicted between these calls.
+    let is_resident = |page: usize| bitmap[page / 64] & (1 << (page % 64)) != 0;
+    let resident_pages = (0..num_pages).take_while(|&page| is_resident(page)).count();
+    assert_eq!(cmp::min(resident_pages * page_size, len), fbuffer.resident_len(0, len));
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(empty.resident_bitmap().is_empty());
+}
+
+#[test]
+fn open_cow_patched_patches_a_private_copy() {
+    let path = write_temp_file("cow-patched", 10_000);
+    let patches: &[(usize, &[u8])] = &[(0, b"abc"), (9_998, b"yz"), (1, b"B")];
+    let patched = FileBuffer::open_cow_patched(&path, patches).unwrap();
+    let original = FileBuffer::open(&path).unwrap();
+    let out_of_range = FileBuffer::open_cow_patched(&path, &[(9_999, &b"yz"[..])]);
+    let overflow = FileBuffer::open_cow_patched(&path, &[(usize::MAX, &b"z"[..])]);
+
+    assert_eq!(&patched[..4], b"aBc\x03");
+    assert_eq!(&patched[9_997..], &[(9_997 % 251) as u8, b'y', b'z'][..]);
+    assert_eq!(&patched[3..9_998], &original[3..9_998]);
+
+    // The patches are not visible through other mappings of the file.
+    assert_eq!(&original[..3], &[0, 1, 2][..]);
+    assert_eq!(&original[9_998..], &[(9_998 % 251) as u8, (9_999 % 251) as u8][..]);
+
+    assert_eq!(out_of_range.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(overflow.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    drop((patched, original));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn open_timeout_opens_files_in_time() {
+    let fbuffer = FileBuffer::open_timeout("src/lib.rs", Duration::from_secs(60)).unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    let not_found = FileBuffer::open_timeout("does-not-exist", Duration::from_secs(60));
+    assert_eq!(not_found.unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[cfg(unix)]
+#[test]
+fn open_timeout_gives_up_on_blocking_opens() {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    // Opening a fifo for reading blocks until a writer opens it, like an unresponsive mount.
+    let path = std::env::temp_dir().join(format!("filebuffer-fifo-{}", std::process::id()));
+    let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+    let timed_out = FileBuffer::open_timeout(&path, Duration::from_millis(50));
+    assert_eq!(timed_out.unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+    // Open the fifo for writing to unblock the abandoned worker.
+    let _writer = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn data_ranges_cover_all_data() {
+    // Write 64 KiB of data at the start and end of the file, and leave a hole in between.
+    let path = write_temp_file("data-ranges", 1 << 16);
+    {
+        use std::io::{Seek, SeekFrom, Write};
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(1 << 24)).unwrap();
+        file.write_all(&[1; 1 << 16]).unwrap();
+    }
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    let ranges = fbuffer.data_ranges().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    // Whether the hole is reported depends on the filesystem, but all data must be covered.
+    let len = fbuffer.len();
+    assert!(ranges.windows(2).all(|w| w[0].end < w[1].start));
+    assert!(ranges.iter().all(|r| r.start < r.end && r.end <= len));
+    assert_eq!(ranges.first().unwrap().start, 0);
+    assert_eq!(ranges.last().unwrap().end, len);
+    assert!(ranges.iter().any(|r| r.contains(&0) && r.contains(&((1 << 16) - 1))));
+    assert!(ranges.iter().any(|r| r.contains(&(1 << 24)) && r.contains(&(len - 1))));
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert_eq!(empty.data_ranges().unwrap(), Vec::<Range<usize>>::new());
+}
+
+#[cfg(unix)]
+#[test]
+fn raw_fd_exposes_the_mapped_file() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    assert_eq!(unsafe { libc::fstat(fbuffer.raw_fd().unwrap(), &mut stat) }, 0);
+    assert_eq!(stat.st_size as usize, fbuffer.len());
+
+    // Buffers without a file have no descriptor, rather than panicking.
+    assert_eq!(FileBuffer::empty().raw_fd(), None);
+    assert_eq!(FileBuffer::default().raw_fd(), None);
+}
+
+#[test]
+fn prefix_and_suffix_clamp_to_the_buffer() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    assert_eq!(fbuffer.prefix(13), &fbuffer[..13]);
+    assert_eq!(fbuffer.suffix(13), &fbuffer[len - 13..]);
+    assert_eq!(fbuffer.prefix(0), &[][..]);
+    assert_eq!(fbuffer.suffix(0), &[][..]);
+    assert_eq!(fbuffer.prefix(len + 1), &fbuffer[..]);
+    assert_eq!(fbuffer.suffix(usize::MAX), &fbuffer[..]);
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(empty.prefix(10).is_empty());
+    assert!(empty.suffix(10).is_empty());
+}
+
+#[test]
+fn guarded_slice_detects_truncation() {
+    let path = write_temp_file("guarded-slice", 10_000);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    assert_eq!(fbuffer.guarded_slice(9_000, 1_000).unwrap(), &fbuffer[9_000..]);
+
+    let out_of_range = fbuffer.guarded_slice(9_000, 1_001);
+    assert_eq!(out_of_range.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    let overflow = fbuffer.guarded_slice(usize::MAX, 1);
+    assert_eq!(overflow.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    // Windows does not allow truncating a mapped file.
+    if fs::OpenOptions::new().write(true).open(&path).and_then(|f| f.set_len(5_000)).is_ok() {
+        let truncated = fbuffer.guarded_slice(9_000, 1_000);
+        assert_eq!(truncated.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(fbuffer.guarded_slice(0, 5_000).unwrap(), &fbuffer[..5_000]);
+    }
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn next_nonresident_agrees_with_resident_len() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    fbuffer.prefetch(0, len);
+
+    // On a quiet system the pages do not get evicted between these calls.
+    for &offset in &[0, 1, len / 2, len - 1] {
+        let resident_len = fbuffer.resident_len(offset, len - offset);
+        let all_resident = resident_len == len - offset;
+        let expected = if all_resident { None } else { Some(offset + resident_len) };
+        assert_eq!(fbuffer.next_nonresident(offset), expected);
+    }
+    assert_eq!(fbuffer.next_nonresident(len), None);
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert_eq!(empty.next_nonresident(0), None);
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn open_direct_reads_the_entire_file() {
+    for &path in &["src/lib.rs", "src/empty_file_for_testing.rs"] {
+        match FileBuffer::open_direct(path) {
+            Ok(fbuffer) => {
+                assert_eq!(&fbuffer[..], &FileBuffer::open(path).unwrap()[..]);
+                // The file is read into memory, so this must not read it again with `O_DIRECT`.
+                fbuffer.prefetch_sync(0, fbuffer.len()).unwrap();
+                if fbuffer.len() > 13 { fbuffer.prefetch_sync(3, 10).unwrap(); }
+            }
+            // The filesystem does not support direct I/O, the kernel reports `EINVAL`.
+            Err(ref err) if err.kind() == io::ErrorKind::InvalidInput => {}
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+#[test]
+fn open_locked_makes_the_entire_file_resident() {
+    for &path in &["src/lib.rs", "src/empty_file_for_testing.rs"] {
+        match FileBuffer::open_locked(path) {
+            Ok(fbuffer) => {
+                let len = fbuffer.len();
+                assert_eq!(fbuffer.resident_len(0, len), len);
+                assert_eq!(&fbuffer[..], &FileBuffer::open(path).unwrap()[..]);
+            }
+            // The file does not fit in the limit on locked memory.
+            #[cfg(unix)]
+            Err(ref err) if err.raw_os_error() == Some(libc::ENOMEM) => {}
+            #[cfg(unix)]
+            Err(ref err) if err.raw_os_error() == Some(libc::EPERM) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+#[test]
+fn prefetch_if_needed_skips_resident_ranges() {
+    let fbuffer = OpenOptions::new().track_stats(true).open("src/lib.rs").unwrap();
+
+    // Touching the first page makes it resident, so there is nothing left to prefetch.
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+    let observes_residency = !cfg!(any(windows, feature = "no-residency"));
+    assert_eq!(fbuffer.prefetch_if_needed(0, 10), !observes_residency);
+    let expected_calls = if observes_residency { 0 } else { 1 };
+    assert_eq!(fbuffer.stats().unwrap().prefetch_calls, expected_calls);
+
+    // Whether the rest is resident depends on the page cache, but the result must be consistent.
+    let len = fbuffer.len();
+    let was_resident = fbuffer.resident_len(0, len) == len;
+    assert_eq!(fbuffer.prefetch_if_needed(0, len), !(observes_residency && was_resident));
+}
+
+#[test]
+fn slice_unchecked_agrees_with_indexing() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
 
-#[cfg(all(unix))]
-use unix::get_resident;
+    unsafe {
+        assert_eq!(fbuffer.slice_unchecked(3, 10), &b"Filebuffer"[..]);
+        assert_eq!(fbuffer.slice_unchecked(0, len), &fbuffer[..]);
+        assert_eq!(fbuffer.slice_unchecked(len - 7, 7), &fbuffer[len - 7..]);
+        assert!(fbuffer.slice_unchecked(len, 0).is_empty());
+        assert!(FileBuffer::empty().slice_unchecked(0, 0).is_empty());
+    }
+}
 
-#[cfg(windows)]
-use windows::{PlatformData, get_resident, get_page_size, map_file, unmap_file, prefetch};
+#[test]
+fn prefetch_ends_prefetches_the_first_and_last_page() {
+    let fbuffer = OpenOptions::new().track_stats(true).open("src/lib.rs").unwrap();
+    let page_size = fbuffer.page_size();
+    assert!(fbuffer.len() > page_size * 2);
 
-/// A memory-mapped file.
-///
-/// # Safety
-///
-/// **On Unix-ish platforms, external modifications to the file made after the file buffer was
-/// opened can show up in this file buffer.** In particular, if a file is truncated after opening,
-/// accessing the removed part causes undefined behavior. On Windows it is possible to prevent this
-/// by opening the file in exclusive mode, but that functionality is not available in stable Rust
-/// currently. (Filebuffer will be updated after stabilization.)
-///
-/// It is recommended to ensure that other applications do not write to the file when it is mapped,
-/// possibly by marking the file read-only. (Though even this is no guarantee.)
-#[derive(Debug)]
-pub struct FileBuffer {
-    page_size: usize,
-    buffer: *const u8,
-    length: usize,
+    fbuffer.prefetch_ends();
+    let stats = fbuffer.stats().unwrap();
+    assert_eq!(stats.prefetch_calls, 1);
+    assert_eq!(stats.prefetch_bytes, page_size * 2);
 
-    #[allow(dead_code)] // This field is not dead, it might have an effectful destructor.
-    platform_data: PlatformData,
+    // A buffer that fits in a single page gets that page prefetched once.
+    let path = write_temp_file("prefetch-ends", 100);
+    let small = OpenOptions::new().track_stats(true).open(&path).unwrap();
+    small.prefetch_ends();
+    assert_eq!(small.stats().unwrap().prefetch_bytes, page_size);
+
+    FileBuffer::open("src/empty_file_for_testing.rs").unwrap().prefetch_ends();
+    drop(small);
+    fs::remove_file(&path).unwrap();
 }
 
-/// Rounds `size` up to the nearest multiple of `power_of_two`.
-fn round_up_to(size: usize, power_of_two: usize) -> usize {
-    (size + (power_of_two - 1)) & !(power_of_two - 1)
+#[test]
+fn try_into_string_copies_valid_utf8() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let expected = fs::read_to_string("src/lib.rs").unwrap();
+    assert_eq!(fbuffer.try_into_string().unwrap(), expected);
+    assert_eq!(FileBuffer::empty().try_into_string().unwrap(), "");
+
+    let path = write_temp_file("into-string", 300);
+    let (fbuffer, err) = FileBuffer::open(&path).unwrap().try_into_string().unwrap_err();
+    assert_eq!(err.valid_up_to(), 128);
+    assert_eq!(fbuffer.len(), 300);
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
 }
 
 #[test]
-fn verify_round_up_to() {
-    assert_eq!(1024, round_up_to(23, 1024));
-    assert_eq!(1024, round_up_to(1024, 1024));
-    assert_eq!(2048, round_up_to(1025, 1024));
+fn stats_count_advice() {
+    let fbuffer = OpenOptions::new().track_stats(true).open("src/lib.rs").unwrap();
+    let page_size = fbuffer.page_size();
+    assert_eq!(fbuffer.stats().unwrap().prefetch_calls, 0);
+
+    fbuffer.prefetch(1, 10);
+    fbuffer.prefetch_ranges(&[(0, 10), (page_size + 1, 10)]);
+    let _ = fbuffer.advise_pageout(0, 10);
+    let _ = fbuffer.prefetch_background(0, 10);
+
+    let stats = fbuffer.stats().unwrap();
+    assert_eq!(stats.prefetch_calls, 3);
+    assert_eq!(stats.prefetch_bytes, page_size * 3 + 10);
+    assert_eq!(stats.evict_calls, 1);
+    assert_eq!(stats.major_faults.is_some(), cfg!(unix));
+
+    let untracked = FileBuffer::open("src/lib.rs").unwrap();
+    untracked.prefetch(0, 10);
+    assert_eq!(untracked.stats(), None);
 }
 
-/// Rounds `size` down to the nearest multiple of `power_of_two`.
-fn round_down_to(size: usize, power_of_two: usize) -> usize {
-    size & !(power_of_two - 1)
+#[test]
+fn ct_eq_compares_contents_and_length() {
+    let path = write_temp_file("ct-eq", 5);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    assert!(fbuffer.ct_eq(&[0, 1, 2, 3, 4]));
+    assert!(!fbuffer.ct_eq(&[0, 1, 2, 3, 5]));
+    assert!(!fbuffer.ct_eq(&[1, 1, 2, 3, 4]));
+    assert!(!fbuffer.ct_eq(&[0, 1, 2, 3]));
+    assert!(!fbuffer.ct_eq(&[0, 1, 2, 3, 4, 0]));
+    assert!(!fbuffer.ct_eq(&[]));
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(empty.ct_eq(&[]));
+    assert!(!empty.ct_eq(&[0]));
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
 }
 
 #[test]
-fn verify_round_down_to() {
-    assert_eq!(0, round_down_to(23, 1024));
-    assert_eq!(1024, round_down_to(1024, 1024));
-    assert_eq!(1024, round_down_to(1025, 1024));
+fn into_readonly_keeps_address_and_contents() {
+    let mut scratch = FileBufferMut::anonymous(10_000).unwrap();
+    scratch[9_999] = 42;
+    let ptr = scratch.as_ptr();
+    let fbuffer = scratch.into_readonly().unwrap();
+    assert_eq!(fbuffer.as_ptr(), ptr);
+    assert_eq!(fbuffer.len(), 10_000);
+    assert_eq!(fbuffer[9_999], 42);
+
+    let patched = FileBuffer::open_cow_patched("src/lib.rs", &[(3, b"filebuffer")]).unwrap();
+    let fbuffer = patched.into_readonly().unwrap();
+    assert_eq!(&fbuffer[3..13], &b"filebuffer"[..]);
+
+    let empty = FileBufferMut::anonymous(0).unwrap().into_readonly().unwrap();
+    assert!(empty.is_empty());
 }
 
-impl FileBuffer {
-    /// Maps the file at `path` into memory.
-    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileBuffer> {
-        // Open the `fs::File` so we get all of std's error handling for free, then use it to
-        // extract the file descriptor. The file is closed again when `map_file` returns on
-        // Unix-ish platforms, but `mmap` only requires the descriptor to be open for the `mmap`
-        // call, so this is fine. On Windows, the file must be kept open for the lifetime of the
-        // mapping, so `map_file` moves the file into the platform data.
-        let mut open_opts = fs::OpenOptions::new();
-        open_opts.read(true);
-
-        // TODO: On Windows, set `share_mode()` to read-only. This requires the
-        // `open_options_ext` feature that is currently unstable, but it is
-        // required to ensure that a different process does not suddenly modify
-        // the contents of the file. See also Rust issue 27720.
-
-        let file = open_opts.open(path)?;
-        let (buffer, length, platform_data) = map_file(file)?;
-        let fbuffer = FileBuffer {
-            page_size: get_page_size(),
-            buffer: buffer,
-            length: length,
-            platform_data: platform_data
-        };
-        Ok(fbuffer)
+#[test]
+fn get_or_copy_borrows_from_the_buffer() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    match fbuffer.get_or_copy(3, 10) {
+        Some(Cow::Borrowed(bytes)) => assert_eq!(bytes, b"Filebuffer"),
+        other => panic!("expected borrowed bytes, got {:?}", other),
     }
 
-    /// Returns the number of bytes resident in physical memory, starting from `offset`.
-    ///
-    /// The slice `[offset..offset + resident_len]` can be accessed without causing page faults or
-    /// disk access. Note that this is only a snapshot, and the kernel might decide to evict pages
-    /// or make them resident at any time.
-    ///
-    /// The returned resident length is at most `length`.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the specified range lies outside of the buffer.
-    ///
-    /// # Remarks
-    ///
-    /// Windows does not expose a mechanism to query which pages are resident in physical
-    /// memory. Therefore this function optimistically claims that the entire range is resident
-    /// on Windows.
-    pub fn resident_len(&self, offset: usize, length: usize) -> usize {
-        // The specified offset and length must lie within the buffer.
-        assert!(offset + length <= self.length);
+    assert_eq!(fbuffer.get_or_copy(len, 0), Some(Cow::Borrowed(&[][..])));
+    assert_eq!(fbuffer.get_or_copy(len - 1, 2), None);
+    assert_eq!(fbuffer.get_or_copy(1, usize::MAX), None);
+}
 
-        // This is a no-op for empty files.
-        if self.buffer == ptr::null() { return 0; }
+#[test]
+fn prewarm_maps_files_in_order() {
+    let paths = ["src/lib.rs", "src/empty_file_for_testing.rs", "src/options.rs"];
+    let fbuffers = FileBuffer::prewarm(&paths).unwrap();
+    assert_eq!(fbuffers.len(), 3);
+    assert_eq!(&fbuffers[0][3..13], &b"Filebuffer"[..]);
+    assert!(fbuffers[1].is_empty());
+    assert_eq!(fbuffers[2].len(), fs::metadata("src/options.rs").unwrap().len() as usize);
 
-        let aligned_offset = round_down_to(offset, self.page_size);
-        let aligned_length = round_up_to(length + (offset - aligned_offset), self.page_size);
-        let num_pages = aligned_length / self.page_size;
+    assert!(FileBuffer::prewarm(&["src/lib.rs", "src/does_not_exist.rs"]).is_err());
+}
 
-        // There is a tradeoff here: to store residency information, we need an array of booleans.
-        // The requested range can potentially be very large and it is only known at runtime. We
-        // could allocate a vector here, but that requires a heap allocation just to get residency
-        // information (which might in turn cause a page fault). Instead, check at most 32 pages at
-        // once. This means more syscalls for large ranges, but it saves us the heap allocation,
-        // and for ranges up to 32 pages (128 KiB typically) there is only one syscall.
-        let mut residency = [false; 32];
-        let mut pages_checked = 0;
-        let mut pages_resident = 0;
-
-        while pages_checked < num_pages {
-            let pages_to_check = cmp::min(32, num_pages - pages_checked);
-            let check_offset = (aligned_offset + pages_checked * self.page_size) as isize;
-            let check_buffer = unsafe { self.buffer.offset(check_offset) };
-            let check_length = pages_to_check * self.page_size;
-            get_resident(check_buffer, check_length, &mut residency);
-
-            // Count the number of resident pages.
-            match residency[..pages_to_check].iter().position(|resident| !resident) {
-                Some(non_resident) => {
-                    // The index of the non-resident page is the number of resident pages.
-                    pages_resident += non_resident;
-                    break;
-                }
-                None => {
-                    pages_resident += pages_to_check;
-                    pages_checked += pages_to_check;
-                }
-            }
-        }
+#[test]
+fn open_all_fails_on_the_first_error_and_names_the_path() {
+    let paths = ["src/lib.rs", "src/empty_file_for_testing.rs"];
+    let fbuffers = FileBuffer::open_all(&paths).unwrap();
+    assert_eq!(fbuffers.len(), 2);
+    assert_eq!(&fbuffers[0][3..13], &b"Filebuffer"[..]);
+    assert!(fbuffers[1].is_empty());
 
-        let resident_length = pages_resident * self.page_size + aligned_offset - offset;
+    let err = FileBuffer::open_all(&["src/lib.rs", "src/does-not-exist.rs"]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(err.to_string().contains("src/does-not-exist.rs"));
+}
 
-        // Never return more than the requested length. The resident length might be larger than
-        // the length of the buffer, because it is rounded up to the page size.
-        cmp::min(length, resident_length)
-    }
+#[test]
+fn open_each_opens_every_file() {
+    let results = FileBuffer::open_each(&["src/does-not-exist.rs", "src/lib.rs"]);
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].as_ref().unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(&results[1].as_ref().unwrap()[3..13], &b"Filebuffer"[..]);
+}
 
-    /// Returns the system page size.
-    ///
-    /// When the kernel makes the file resident in physical memory, it does so with page
-    /// granularity. (In practice this happens in larger chunks, but still in multiples of
-    /// the page size.) Therefore, when processing the file in chunks, this is a good chunk
-    /// length.
-    pub fn chunk_len_hint(&self) -> usize {
-        self.page_size
-    }
+#[test]
+fn vec_from_fbuffer_copies_contents() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let expected = fbuffer.to_vec();
+    let bytes: Vec<u8> = fbuffer.into();
+    assert_eq!(bytes, expected);
 
-    /// Advises the kernel to make a slice of the file resident in physical memory.
-    ///
-    /// This method does not block, meaning that when the function returns, the slice is not
-    /// necessarily resident. After this function returns, the kernel may read the requested slice
-    /// from disk and make it resident. Note that this is only an advice, the kernel need not honor
-    /// it.
-    ///
-    /// To check whether the slice is resident at a later time, use `resident_len()`.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the specified range lies outside of the buffer.
-    pub fn prefetch(&self, offset: usize, length: usize) {
-        // TODO: This function should use `collections::range::RangeArgument` once stabilized.
-        // The specified offset and length must lie within the buffer.
-        assert!(offset + length <= self.length);
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(Vec::from(empty).is_empty());
+}
 
-        // This is a no-op for empty files.
-        if self.buffer == ptr::null() { return; }
+#[test]
+fn weak_handle_outlives_cache_eviction() {
+    use std::sync::Weak;
+    use std::thread;
 
-        let aligned_offset = round_down_to(offset, self.page_size);
-        let aligned_length = round_up_to(length + (offset - aligned_offset), self.page_size);
+    // The cache holds a strong and a weak reference, and a borrower holds a strong reference.
+    let cached = FileBuffer::open_arc("src/lib.rs").unwrap();
+    let weak: Weak<FileBuffer> = Arc::downgrade(&cached);
+    let borrowed = cached.clone();
 
-        let buffer = unsafe { self.buffer.offset(aligned_offset as isize) };
-        prefetch(buffer, aligned_length);
-    }
+    // Evict the buffer from the cache. The borrower still keeps the buffer mapped.
+    drop(cached);
+    let borrower = thread::spawn(move || {
+        assert_eq!(&borrowed[3..13], &b"Filebuffer"[..]);
+    });
+    borrower.join().unwrap();
 
-    /// Leaks the file buffer as a byte slice.
-    ///
-    /// This prevents the buffer from being unmapped, keeping the file mapped until the program
-    /// ends. This is not as bad as it sounds, because the kernel is free to evict pages from
-    /// physical memory in case of memory pressure. Because the file is mapped read-only, it can
-    /// always be read from disk again.
-    ///
-    /// If the file buffer is going to be open for the entire duration of the program anyway, this
-    /// method can avoid some lifetime issues. Still, it is good practice to close the file buffer
-    /// if possible. This method should be a last resort.
-    pub fn leak(mut self) -> &'static [u8] {
-        let buffer = if self.buffer == ptr::null() {
-            &[]
-        } else {
-            unsafe { slice::from_raw_parts(self.buffer, self.length) }
-        };
+    // Now the last strong reference is gone, so the buffer was unmapped.
+    assert!(weak.upgrade().is_none());
+}
 
-        // Prevent `drop()` from freeing the buffer.
-        self.buffer = ptr::null();
-        self.length = 0;
+#[test]
+fn dump_formats_like_xxd() {
+    let path = write_temp_file("dump", 18);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
 
-        buffer
-    }
+    let expected = "\
+        00000000: 0001 0203 0405 0607 0809 0a0b 0c0d 0e0f  ................\n\
+        00000010: 1011                                     ..\n";
+    assert_eq!(fbuffer.dump(0, 18), expected);
+    assert_eq!(fbuffer.dump(17, 1), "00000011: 11                                       .\n");
+    assert_eq!(fbuffer.dump(18, 0), "");
 }
 
-// There is no possibility of data races when passing `&FileBuffer` across threads,
-// because the buffer is read-only. `&FileBuffer` has no interior mutability.
-unsafe impl Sync for FileBuffer {}
+#[test]
+fn advise_cold_and_pageout_keep_contents() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
 
-// It is safe to move a `FileBuffer` into a different thread.
-unsafe impl Send for FileBuffer {}
+    fbuffer.advise_cold(3, 10).unwrap();
+    fbuffer.advise_pageout(0, len).unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
 
-impl Drop for FileBuffer {
-    fn drop(&mut self) {
-        if self.buffer != ptr::null() { unmap_file(self.buffer, self.length); }
-    }
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    empty.advise_cold(0, 0).unwrap();
+    empty.advise_pageout(0, 0).unwrap();
 }
 
-impl Deref for FileBuffer {
-    type Target = [u8];
+#[test]
+fn content_hash_detects_modification() {
+    let path = write_temp_file("content-hash", 0);
+    fs::write(&path, b"foobar").unwrap();
+    let fbuffer = FileBuffer::open(&path).unwrap();
 
-    fn deref(&self) -> &[u8] {
-        if self.buffer == ptr::null() {
-            &[]
-        } else {
-            unsafe { slice::from_raw_parts(self.buffer, self.length) }
-        }
+    // This is the reference value for FNV-1a 64 from the FNV test suite.
+    let before = fbuffer.content_hash();
+    assert_eq!(before, 0x85944171f73967e8);
+
+    // A write through a different file handle shows up in the mapping on Unix-ish platforms.
+    #[cfg(unix)]
+    {
+        use std::io::{Seek, SeekFrom, Write};
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(3)).unwrap();
+        file.write_all(b"baz").unwrap();
+        assert_ne!(fbuffer.content_hash(), before);
     }
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert_eq!(empty.content_hash(), 0xcbf29ce484222325);
 }
 
-impl AsRef<[u8]> for FileBuffer {
-    fn as_ref(&self) -> &[u8] {
-        self.deref()
+#[test]
+fn content_equals_path_compares_contents() {
+    let len = get_page_size() * 3 + 10;
+    let path = write_temp_file("equals-a", len);
+    let same = write_temp_file("equals-b", len);
+    let shorter = write_temp_file("equals-c", len - 1);
+    let fbuffer = FileBuffer::open(&path).unwrap();
+
+    assert!(fbuffer.content_equals_path(&path).unwrap());
+    assert!(fbuffer.content_equals_path(&same).unwrap());
+    assert!(!fbuffer.content_equals_path(&shorter).unwrap());
+
+    let mut contents = fs::read(&same).unwrap();
+    contents[len - 1] ^= 1;
+    fs::write(&same, &contents).unwrap();
+    assert!(!fbuffer.content_equals_path(&same).unwrap());
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(empty.content_equals_path("src/empty_file_for_testing.rs").unwrap());
+    assert!(fbuffer.content_equals_path("does-not-exist").is_err());
+
+    drop(fbuffer);
+    for path in &[path, same, shorter] {
+        fs::remove_file(path).unwrap();
     }
 }
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
 #[test]
-fn open_file() {
-    let fbuffer = FileBuffer::open("src/lib.rs");
-    assert!(fbuffer.is_ok());
+fn huge_page_backed_finds_the_mapping() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+
+    // Whether huge pages are used depends on the system configuration, but the mapping must exist.
+    fbuffer.huge_page_backed(0).unwrap();
+    fbuffer.huge_page_backed(len - 1).unwrap();
 }
 
 #[test]
-fn make_resident() {
-    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+fn try_from_converts_to_str() {
+    use std::convert::TryInto;
 
-    // Touch the first page to make it resident.
-    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let text: &str = (&fbuffer).try_into().unwrap();
+    assert!(text.starts_with("// Filebuffer"));
 
-    // Now at least that part should be resident.
-    assert_eq!(fbuffer.resident_len(3, 10), 10);
+    let path = write_temp_file("try-from-str", 0);
+    fs::write(&path, b"caf\xe9").unwrap();
+    let latin1 = FileBuffer::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(<&str>::try_from(&latin1).is_err());
 }
 
 #[test]
-fn prefetch_is_not_harmful() {
-    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+fn try_from_converts_to_cstr() {
+    let mut cases = Vec::new();
+    for (i, contents) in [&b"hello\0"[..], b"hello", b"hel\0lo\0", b"\0"].iter().enumerate() {
+        let path = write_temp_file(&format!("try-from-cstr-{}", i), 0);
+        fs::write(&path, contents).unwrap();
+        cases.push(FileBuffer::open(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
 
-    // It is impossible to test that this actually works without root access to instruct the kernel
-    // to drop its caches, but at least we can verify that calling `prefetch` is not harmful.
-    fbuffer.prefetch(0, fbuffer.len());
+    assert_eq!(<&CStr>::try_from(&cases[0]).unwrap().to_bytes(), b"hello");
+    assert!(<&CStr>::try_from(&cases[1]).is_err(), "missing nul must be rejected");
+    assert!(<&CStr>::try_from(&cases[2]).is_err(), "interior nul must be rejected");
+    assert_eq!(<&CStr>::try_from(&cases[3]).unwrap().to_bytes(), b"");
 
-    // Reading from the file should still work as normal.
-    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert!(<&CStr>::try_from(&empty).is_err());
 }
 
 #[test]
@@ -315,6 +5285,50 @@ fn drop_after_leak() {
     assert_eq!(&bytes[3..13], &b"Filebuffer"[..]);
 }
 
+#[test]
+fn leak_ref_keeps_other_references_usable() {
+    let fbuffer = Arc::new(FileBuffer::open("src/lib.rs").unwrap());
+    let weak = Arc::downgrade(&fbuffer);
+    let bytes = fbuffer.clone().leak_ref();
+    assert_eq!(&bytes[3..13], &b"Filebuffer"[..]);
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    // The leaked reference keeps the buffer alive after the last other reference is dropped.
+    drop(fbuffer);
+    assert!(weak.upgrade().is_some());
+    assert_eq!(&bytes[3..13], &b"Filebuffer"[..]);
+}
+
+#[test]
+fn debug_prints_summary_rather_than_contents() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len();
+    let page_size = get_page_size();
+
+    // Reading the file populates the page cache, so on a quiet system every page is resident.
+    fbuffer.prefetch_sync(0, len).unwrap();
+    let observes_residency = !cfg!(any(windows, feature = "no-residency"));
+    let resident = if observes_residency {
+        format!("{} pages", (len + page_size - 1) / page_size)
+    } else {
+        "unknown".to_string()
+    };
+    let expected = format!(
+        "FileBuffer {{ path: Some(\"src/lib.rs\"), len: {}, page_size: {}, resident: {} }}",
+        len,
+        page_size,
+        resident
+    );
+    assert_eq!(format!("{:?}", fbuffer), expected);
+
+    let empty = format!("{:?}", FileBuffer::empty());
+    let expected = if observes_residency { "resident: 0 pages }" } else { "resident: unknown }" };
+    assert!(empty.ends_with(expected), "{}", empty);
+
+    let fbuffer_mut = FileBufferMut::anonymous(4096).unwrap();
+    assert_eq!(format!("{:?}", fbuffer_mut), "FileBufferMut { len: 4096 }");
+}
+
 #[test]
 fn fbuffer_can_be_moved_into_thread() {
     use std::thread;
@@ -351,6 +5365,13 @@ fn empty_file_prefetch_is_fine() {
 }
 
 #[test]
+fn empty_file_prefetch_checked_advises_nothing() {
+    let fbuffer = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    assert_eq!(fbuffer.prefetch_checked(0, 0).unwrap(), 0);
+}
+
+#[test]
+#[allow(clippy::bool_assert_comparison)]
 fn empty_file_deref_is_fine() {
     let fbuffer = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
     assert_eq!(fbuffer.iter().any(|_| true), false);
@@ -362,6 +5383,72 @@ fn empty_file_has_zero_resident_len() {
     assert_eq!(fbuffer.resident_len(0, 0), 0);
 }
 
+#[test]
+fn anonymous_buffer_is_zeroed_and_writable() {
+    let mut fbuffer = FileBufferMut::anonymous(10_000).unwrap();
+    assert_eq!(fbuffer.len(), 10_000);
+    assert!(fbuffer.iter().all(|&x| x == 0));
+
+    fbuffer[9_999] = 42;
+    fbuffer[..10].copy_from_slice(b"Filebuffer");
+    assert_eq!(&fbuffer[..10], &b"Filebuffer"[..]);
+    assert_eq!(fbuffer[9_999], 42);
+}
+
+#[test]
+fn as_mut_slice_can_be_filled_by_a_reader() {
+    use std::io::Read;
+
+    let mut fbuffer = FileBufferMut::anonymous(4096).unwrap();
+    assert!(fbuffer.as_mut_slice().iter().all(|&b| b == 0));
+
+    let mut file = fs::File::open("src/lib.rs").unwrap();
+    file.read_exact(&mut fbuffer.as_mut_slice()[..45]).unwrap();
+    assert_eq!(&fbuffer[3..45], &b"Filebuffer -- Fast and simple file reading"[..]);
+
+    let mut cow = FileBufferMut::open_cow("src/lib.rs").unwrap();
+    assert_eq!(&cow.as_mut_slice()[3..13], &b"Filebuffer"[..]);
+}
+
+#[test]
+fn anonymous_buffer_is_page_aligned() {
+    let fbuffer = FileBufferMut::anonymous(1).unwrap();
+    assert_eq!(fbuffer.as_ptr() as usize % get_page_size(), 0);
+}
+
+#[test]
+fn advise_free_preserves_file_contents() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+    fbuffer.advise_free(0, fbuffer.len()).unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+}
+
+#[test]
+fn anonymous_advise_free_only_frees_whole_pages() {
+    let page_size = get_page_size();
+    let mut fbuffer = FileBufferMut::anonymous(page_size * 3).unwrap();
+    for x in fbuffer.iter_mut() {
+        *x = 42;
+    }
+
+    // Only the last two pages lie entirely within the range.
+    fbuffer.advise_free(page_size - 1, page_size * 2 + 1).unwrap();
+    assert!(fbuffer[..page_size].iter().all(|&x| x == 42));
+    assert!(fbuffer[page_size..].iter().all(|&x| x == 42 || x == 0));
+
+    // Writing after freeing works as normal.
+    fbuffer[page_size * 2] = 7;
+    assert_eq!(fbuffer[page_size * 2], 7);
+}
+
+#[test]
+fn empty_anonymous_buffer_is_fine() {
+    let mut fbuffer = FileBufferMut::anonymous(0).unwrap();
+    assert_eq!(fbuffer.len(), 0);
+    assert!(fbuffer.as_mut().is_empty());
+}
+
 #[test]
 fn page_size_at_least_4096() {
     // There is no reason why the page size cannot be smaller, it is just that in practice there
@@ -369,3 +5456,15 @@ fn page_size_at_least_4096() {
     // a plausible value.
     assert!(get_page_size() >= 4096);
 }
+
+#[test]
+fn chunk_len_hint_is_a_multiple_of_the_page_size() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    assert_eq!(fbuffer.page_size(), get_page_size());
+    assert!(fbuffer.chunk_len_hint() >= fbuffer.page_size());
+    assert_eq!(fbuffer.chunk_len_hint() % fbuffer.page_size(), 0);
+
+    // Without a file there is no device to ask, so the hint falls back to 128 KiB.
+    let empty = FileBuffer::empty();
+    assert_eq!(empty.chunk_len_hint(), round_up_to(128 * 1024, empty.page_size()));
+}
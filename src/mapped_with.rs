@@ -0,0 +1,96 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This mod contains a holder for a buffer together with a value that borrows from it.
+
+use std::fmt;
+
+use stable_deref_trait::StableDeref;
+use yoke::{Yoke, Yokeable};
+
+use super::FileBuffer;
+
+// The slice that a buffer dereferences to lives in the mapping, or in the heap allocation for
+// small files, and not in the `FileBuffer` itself. Moving the buffer does not move the slice.
+unsafe impl StableDeref for FileBuffer {}
+
+/// A buffer bundled with a value that borrows from its contents.
+///
+/// Returned by `FileBuffer::map_with()`. A value that borrows from a buffer, such as the parsed
+/// index of a file, cannot normally be stored next to the buffer in the same struct. This holder
+/// owns both, so it can be stored and moved around freely, and the value stays zero-copy.
+///
+/// The type of the value is written with `'static` in place of the lifetime of the borrow, for
+/// example `MappedWith<(&'static str, &'static str)>`. It must implement `yoke::Yokeable`, which
+/// the `yoke` crate implements for references, `Cow`, and options, tuples, and arrays of those,
+/// and which can be derived for structs with `#[derive(Yokeable)]`. The `'static` is only a
+/// placeholder: `get()` returns the value with a lifetime bound to the holder, so borrows cannot
+/// outlive the buffer.
+///
+/// This type is only available with the `mapped-with` feature enabled.
+pub struct MappedWith<Y: for<'a> Yokeable<'a>> {
+    yoke: Yoke<Y, FileBuffer>,
+}
+
+impl<Y: for<'a> Yokeable<'a>> MappedWith<Y> {
+    pub(crate) fn new<F>(fbuffer: FileBuffer, f: F) -> MappedWith<Y>
+    where
+        F: for<'de> FnOnce(&'de [u8]) -> <Y as Yokeable<'de>>::Output,
+    {
+        MappedWith {
+            yoke: Yoke::attach_to_cart(fbuffer, f),
+        }
+    }
+
+    /// Returns the value that was derived from the buffer.
+    pub fn get<'a>(&'a self) -> &'a <Y as Yokeable<'a>>::Output {
+        self.yoke.get()
+    }
+
+    /// Returns the buffer that the value borrows from.
+    pub fn file_buffer(&self) -> &FileBuffer {
+        self.yoke.backing_cart()
+    }
+
+    /// Drops the value, and returns the buffer.
+    pub fn into_file_buffer(self) -> FileBuffer {
+        self.yoke.into_backing_cart()
+    }
+}
+
+impl<Y: for<'a> Yokeable<'a>> fmt::Debug for MappedWith<Y> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MappedWith")
+            .field("fbuffer", self.file_buffer())
+            .finish()
+    }
+}
+
+#[test]
+fn map_with_keeps_borrowed_values_with_the_buffer() {
+    use std::str;
+
+    fn first_and_last_line(bytes: &[u8]) -> (&str, &str) {
+        let text = str::from_utf8(bytes).unwrap();
+        (text.lines().next().unwrap(), text.lines().last().unwrap())
+    }
+
+    type Lines = (&'static str, &'static str);
+    let mapped = FileBuffer::map_with::<Lines, _, _>("src/lib.rs", first_and_last_line).unwrap();
+    let header = FileBuffer::map_with::<&'static [u8], _, _>("src/lib.rs", |b| &b[3..13]);
+    assert_eq!(*header.unwrap().get(), &b"Filebuffer"[..]);
+
+    // The holder can be moved, the value still points into the buffer.
+    let moved = Box::new(mapped);
+    let (first, last) = *moved.get();
+    assert_eq!(first, "// Filebuffer -- Fast and simple file reading");
+    assert_eq!(first.as_ptr(), moved.file_buffer().as_ptr());
+    assert_eq!(last, "}");
+
+    let fbuffer = moved.into_file_buffer();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+}
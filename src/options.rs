@@ -0,0 +1,524 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This mod contains the options builder that configures how a file is opened and mapped.
+
+use std::io;
+use std::path::Path;
+
+use super::{Advice, FileBuffer, error_naming_path, open_path};
+
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+#[cfg(unix)]
+use super::open_path_at;
+
+/// What to do when a file that is about to be mapped can be modified by others.
+///
+/// When a different process modifies a mapped file, the buffer changes under the feet of the
+/// program, see the safety notes on `FileBuffer`. Tools that are sensitive to this can refuse to
+/// map files that are not read-only, or make them read-only. See `OpenOptions::writable_policy()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum WritablePolicy {
+    /// Map the file regardless of its permissions. This is the default.
+    Allow,
+    /// Refuse to map a file that is writable by others, with an error of kind `PermissionDenied`.
+    ///
+    /// On Unix-ish platforms, these are files that are writable by the group or by other users,
+    /// according to their mode bits. On Windows, these are files that do not have the
+    /// `FILE_ATTRIBUTE_READONLY` attribute.
+    Refuse,
+    /// Make the file read-only before mapping it.
+    ///
+    /// On Unix-ish platforms, this clears all write bits from the mode of the file with `fchmod`,
+    /// which requires ownership of the file. On Windows, this sets `FILE_ATTRIBUTE_READONLY`.
+    /// If the permissions cannot be changed, opening fails with the error from the system.
+    MakeReadonly,
+}
+
+/// Options that configure how a file is opened and mapped.
+///
+/// This is a builder in the spirit of `std::fs::OpenOptions`. `FileBuffer::open(path)` is
+/// equivalent to `OpenOptions::new().open(path)`.
+///
+/// # Examples
+///
+/// ```
+/// use filebuffer::OpenOptions;
+/// let fbuffer = OpenOptions::new().follow_symlinks(false).open("src/lib.rs").unwrap();
+/// assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    pub(crate) follow_symlinks: bool,
+    #[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(dead_code))]
+    pub(crate) no_atime: bool,
+    #[cfg_attr(windows, allow(dead_code))]
+    pub(crate) no_cache: bool,
+    pub(crate) range: Option<(u64, usize)>,
+    pub(crate) known_len: Option<u64>,
+    pub(crate) advice: Option<Advice>,
+    pub(crate) forbid_empty: bool,
+    pub(crate) lock_shared: bool,
+    pub(crate) writable_policy: WritablePolicy,
+    pub(crate) track_stats: bool,
+    #[cfg_attr(windows, allow(dead_code))]
+    pub(crate) no_reserve: bool,
+    pub(crate) read_threshold: usize,
+    #[cfg(unix)]
+    pub(crate) mmap_flags: i32,
+    #[cfg(windows)]
+    pub(crate) mapping_name: Option<String>,
+    #[cfg(windows)]
+    pub(crate) large_pages: bool,
+}
+
+impl OpenOptions {
+    /// Creates options with the defaults that `FileBuffer::open()` uses.
+    pub fn new() -> OpenOptions {
+        OpenOptions {
+            follow_symlinks: true,
+            no_atime: false,
+            no_cache: false,
+            range: None,
+            known_len: None,
+            advice: None,
+            forbid_empty: false,
+            lock_shared: false,
+            writable_policy: WritablePolicy::Allow,
+            track_stats: false,
+            no_reserve: false,
+            read_threshold: 0,
+            #[cfg(unix)]
+            mmap_flags: 0,
+            #[cfg(windows)]
+            mapping_name: None,
+            #[cfg(windows)]
+            large_pages: false,
+        }
+    }
+
+    /// Sets whether to follow a symlink at the final component of the path.
+    ///
+    /// By default symlinks are followed, like `fs::File::open()` does. When set to `false`,
+    /// opening a path that is a symlink fails on Unix-ish platforms, because the file is opened
+    /// with `O_NOFOLLOW`. This is useful when scanning untrusted directory trees, where a symlink
+    /// could point out of the tree, or to a device such as `/dev/zero`. Note that symlinks in
+    /// the leading components of the path are still followed.
+    ///
+    /// On Windows, the reparse point itself is opened instead of its target, by passing
+    /// `FILE_FLAG_OPEN_REPARSE_POINT`.
+    pub fn follow_symlinks(&mut self, follow: bool) -> &mut OpenOptions {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Sets whether to avoid updating the access time of the file.
+    ///
+    /// Reading from a mapped file normally updates its access time, which causes a metadata write
+    /// for every file read. When set to `true`, the file is opened with `O_NOATIME` on Linux, which
+    /// avoids that. The kernel permits this flag only for files owned by the caller. For other
+    /// files, the file is opened without it, so this option never causes `open()` to fail.
+    ///
+    /// This option is Linux-specific, on other platforms it has no effect.
+    pub fn no_atime(&mut self, no_atime: bool) -> &mut OpenOptions {
+        self.no_atime = no_atime;
+        self
+    }
+
+    /// Sets whether to keep the data of the file out of the cache after it is read.
+    ///
+    /// When set to `true`, the file is marked with `fcntl(F_NOCACHE)` on macOS and iOS before it
+    /// is mapped, which is their counterpart of `O_DIRECT`. This is useful for batch tools that
+    /// read huge files once, because it keeps those files from evicting everything else from the
+    /// unified buffer cache. This changes only how the kernel caches the file, not the contents
+    /// of the buffer, although reading it may be slower, because pages are read from disk again.
+    ///
+    /// This option is specific to macOS and iOS, on other platforms it has no effect. See
+    /// `FileBuffer::open_direct()` for a way to bypass the page cache on Linux.
+    pub fn no_cache(&mut self, no_cache: bool) -> &mut OpenOptions {
+        self.no_cache = no_cache;
+        self
+    }
+
+    /// Maps only `length` bytes of the file, starting at `offset`, instead of the entire file.
+    ///
+    /// This is useful to access a window into a file that is too large to map in its entirety.
+    /// The offset must be a multiple of the page size on Unix-ish platforms, and a multiple of the
+    /// allocation granularity (typically 64 KiB) on Windows. Opening fails with `InvalidInput` if
+    /// the offset is not aligned, or if the range does not lie within the file.
+    ///
+    /// Because a window is usually accessed randomly, the mapping is advised as `Advice::Random`,
+    /// unless different advice is set with `advise()`.
+    pub fn range(&mut self, offset: u64, length: usize) -> &mut OpenOptions {
+        self.range = Some((offset, length));
+        self
+    }
+
+    /// Uses `len` as the length of the file, rather than asking the filesystem for it.
+    ///
+    /// Finding the length of the file takes an `fstat` call on Unix-ish platforms, and a
+    /// `GetFileInformationByHandle` call on Windows. Opening a file otherwise takes an `open`, an
+    /// `fstatfs` (on Linux and Android), and an `mmap` call, so for batch workloads that map many
+    /// small files, skipping the length query saves one in four system calls per file. This is
+    /// worth it when the length is known already, for example from the metadata of a
+    /// `fs::DirEntry` found while walking a directory.
+    ///
+    /// In release builds the length is trusted: it is not checked against the file, and the
+    /// file is not checked to be a regular file. If the file is shorter than `len`, accessing the
+    /// part of the buffer past the end of the file raises `SIGBUS` on Unix-ish platforms, as it
+    /// would if the file was truncated after it was mapped. On Windows, mapping more than the
+    /// length of the file fails instead. In debug builds the length of the file is queried
+    /// anyway, so opening a directory or a character device fails as it does without a known
+    /// length, and if `len` exceeds the length of the file, opening fails with an error of kind
+    /// `InvalidInput`.
+    pub fn known_len(&mut self, len: u64) -> &mut OpenOptions {
+        self.known_len = Some(len);
+        self
+    }
+
+    /// Sets the access pattern advice to apply to the mapping after it is created.
+    ///
+    /// By default no advice is given when mapping an entire file, and `Advice::Random` is given
+    /// when mapping a range. Failure to apply the advice does not cause `open()` to fail.
+    pub fn advise(&mut self, advice: Advice) -> &mut OpenOptions {
+        self.advice = Some(advice);
+        self
+    }
+
+    /// Sets whether opening an empty file is an error.
+    ///
+    /// By default, an empty file results in an empty buffer. When set to `true`, opening a file
+    /// with a length of 0 fails with an error of kind `UnexpectedEof` instead. This is useful when
+    /// the file is expected to have contents, for example a downloaded file that might have been
+    /// truncated, so that the problem surfaces at open time rather than later in a parser.
+    pub fn forbid_empty(&mut self, forbid: bool) -> &mut OpenOptions {
+        self.forbid_empty = forbid;
+        self
+    }
+
+    /// Sets whether to hold a shared advisory lock on the file for the lifetime of the buffer.
+    ///
+    /// When set to `true`, the file is locked with `flock(LOCK_SH)` on Unix-ish platforms, and
+    /// with a shared `LockFileEx` on Windows. If a different process holds an exclusive lock on
+    /// the file, `open()` waits until that lock is released. The lock is released when the buffer
+    /// is dropped.
+    ///
+    /// The lock signals to a cooperating writer that takes an exclusive lock that the file should
+    /// not be modified while it is mapped. On Unix-ish platforms the lock is advisory: it does not
+    /// stop processes that do not take the lock from modifying the file. On Windows, the lock is
+    /// mandatory for `ReadFile` and `WriteFile`, but it does not apply to other mappings.
+    pub fn lock_shared(&mut self, lock: bool) -> &mut OpenOptions {
+        self.lock_shared = lock;
+        self
+    }
+
+    /// Sets what to do when the file can be modified by others, see `WritablePolicy`.
+    ///
+    /// By default, files are mapped regardless of their permissions. The permissions are checked
+    /// on the opened file, so a file that is replaced after the check is not mapped by mistake.
+    /// Note that permissions do not stop the owner of the file, or a privileged user, from
+    /// modifying it; this only rules out modification by arbitrary other users.
+    pub fn writable_policy(&mut self, policy: WritablePolicy) -> &mut OpenOptions {
+        self.writable_policy = policy;
+        self
+    }
+
+    /// Sets whether to count the advice given for the buffer, for `FileBuffer::stats()`.
+    ///
+    /// This is meant for profiling, to verify that a prefetch strategy has the intended effect.
+    /// Tracking adds an atomic increment to every advisory method, so it is off by default.
+    pub fn track_stats(&mut self, track: bool) -> &mut OpenOptions {
+        self.track_stats = track;
+        self
+    }
+
+    /// Sets whether to map the file with `MAP_NORESERVE`, so no swap space is reserved for it.
+    ///
+    /// On systems with strict memory overcommit accounting, mapping a very large file can fail
+    /// when there is not enough swap space to back it, even though a read-only file mapping never
+    /// needs swap. When set to `true`, `MAP_NORESERVE` is passed to `mmap`, which allows mapping
+    /// files larger than the available swap space, for example huge sparse files of which only a
+    /// part will be touched.
+    ///
+    /// This option is specific to Unix-ish platforms, on Windows it has no effect.
+    pub fn no_reserve(&mut self, no_reserve: bool) -> &mut OpenOptions {
+        self.no_reserve = no_reserve;
+        self
+    }
+
+    /// Sets the size up to which files are read into memory rather than mapped.
+    ///
+    /// For small files, setting up and tearing down a mapping costs more than copying the file
+    /// with a single `read`, and every mapping takes up a virtual memory area. Files of at most
+    /// this many bytes are therefore read into a page-aligned heap allocation instead, which the
+    /// buffer owns. The buffer behaves the same, except that it is never affected by changes to
+    /// the file, and that advice which drops pages, such as `Advice::DontNeed`, has no effect on
+    /// it. Files are always mapped when `range()` is set, or when an option that only applies to
+    /// mappings is set, such as `mmap_flags()`.
+    ///
+    /// The default is 0, so files are always mapped. Measured with the `read_threshold` example,
+    /// reading a 4 KiB file takes about half the time of mapping it, and reading stays ahead up
+    /// to about 64 KiB, so a threshold of 16 KiB is a reasonable choice when the buffers do not
+    /// need to follow changes to the file.
+    pub fn read_threshold(&mut self, bytes: usize) -> &mut OpenOptions {
+        self.read_threshold = bytes;
+        self
+    }
+
+    /// Sets additional flags to pass to `mmap`, such as `libc::MAP_POPULATE` or
+    /// `libc::MAP_NORESERVE`.
+    ///
+    /// The flags are ORed into the `MAP_PRIVATE` that is always passed. This is an escape hatch
+    /// for advanced use; the flags are passed to the kernel unchecked. Flags that make no sense,
+    /// or that are not supported by the platform, cause `open()` to fail with the error returned
+    /// by `mmap`. Flags that change the nature of the mapping, such as `MAP_FIXED`, can break the
+    /// guarantees that `FileBuffer` relies on, so use this with care.
+    ///
+    /// This option is only available on Unix-ish platforms.
+    #[cfg(unix)]
+    pub fn mmap_flags(&mut self, flags: i32) -> &mut OpenOptions {
+        self.mmap_flags = flags;
+        self
+    }
+
+    /// Sets the name of the file mapping object, so cooperating processes can share it.
+    ///
+    /// By default the file mapping object is anonymous. When a name is set, the mapping object is
+    /// created with that name, or if a mapping object with that name exists already, the existing
+    /// object is opened instead, and the view is mapped from it. In the latter case the opened
+    /// file only determines the size of the view, the contents come from the existing mapping
+    /// object. The name is global to the session (or to the machine, with a `Global\` prefix),
+    /// and it is subject to the usual rules of the Windows object namespace. In particular, it
+    /// must not contain backslashes other than the namespace prefix, and it must not collide
+    /// with the name of a different kind of object, such as a mutex or an event.
+    ///
+    /// This option is only available on Windows.
+    #[cfg(windows)]
+    pub fn mapping_name(&mut self, name: &str) -> &mut OpenOptions {
+        self.mapping_name = Some(name.to_string());
+        self
+    }
+
+    /// Sets whether to try to map the file with large pages, to reduce TLB pressure.
+    ///
+    /// When set to `true`, the file mapping object is created with `SEC_LARGE_PAGES`. This
+    /// requires the `SeLockMemoryPrivilege` privilege, which must be granted to the user and
+    /// enabled in the token of the process, for example with `AdjustTokenPrivileges`. Windows
+    /// also does not support large pages for every kind of mapping; at the time of writing,
+    /// mappings of data files are not supported. When creating a large page mapping fails for any
+    /// reason, such as `ERROR_PRIVILEGE_NOT_HELD`, the file is mapped with regular pages instead,
+    /// so this option never causes `open()` to fail.
+    ///
+    /// This option is only available on Windows. See `mmap_flags()` for huge pages on Linux.
+    #[cfg(windows)]
+    pub fn large_pages(&mut self, large_pages: bool) -> &mut OpenOptions {
+        self.large_pages = large_pages;
+        self
+    }
+
+    /// Maps the file at `path` into memory with these options.
+    ///
+    /// The message of an error names the path, its kind is that of the underlying error.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<FileBuffer> {
+        // Open the `fs::File` so we get all of std's error handling for free, then use it to
+        // extract the file descriptor. On Unix-ish platforms `mmap` only requires the descriptor
+        // to be open for the `mmap` call, but we keep it open to be able to advise on the file.
+        // On Windows, the file must be kept open for the lifetime of the mapping. Either way,
+        // `map_file` moves the file into the platform data.
+        let path = path.as_ref();
+        open_path(path, self)
+            .and_then(|file| FileBuffer::from_file(file, self))
+            .map(|fbuffer| fbuffer.with_path(path))
+            .map_err(|err| error_naming_path(path, err))
+    }
+
+    /// Maps the file at `path`, relative to the directory `dir`, into memory with these options.
+    ///
+    /// The file is opened with `openat`, so `path` is resolved relative to `dir` rather than to
+    /// the current working directory, unless it is absolute. Opening files relative to a pinned
+    /// directory avoids races where a directory in the path is replaced while walking a tree, and
+    /// it fits capability-based sandboxes where only directory descriptors are available.
+    ///
+    /// This method is only available on Unix-ish platforms.
+    #[cfg(unix)]
+    pub fn open_at<D: AsRawFd, P: AsRef<Path>>(&self, dir: &D, path: P) -> io::Result<FileBuffer> {
+        let path = path.as_ref();
+        open_path_at(dir.as_raw_fd(), path, self)
+            .and_then(|file| FileBuffer::from_file(file, self))
+            .map(|fbuffer| fbuffer.with_path(path))
+            .map_err(|err| error_naming_path(path, err))
+    }
+
+    /// Returns whether an option is set that only has an effect on a mapping.
+    pub(crate) fn requires_mapping(&self) -> bool {
+        #[cfg(unix)]
+        { self.mmap_flags != 0 || self.no_reserve }
+        #[cfg(windows)]
+        { self.mapping_name.is_some() || self.large_pages }
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> OpenOptions {
+        OpenOptions::new()
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn follow_symlinks_false_refuses_symlinks() {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    let target = env::current_dir().unwrap().join("src/lib.rs");
+    let link = env::temp_dir().join(format!("filebuffer-symlink-{}", std::process::id()));
+    let _ = fs::remove_file(&link);
+    symlink(&target, &link).unwrap();
+
+    let followed = OpenOptions::new().open(&link);
+    let not_followed = OpenOptions::new().follow_symlinks(false).open(&link);
+    let regular = OpenOptions::new().follow_symlinks(false).open(&target);
+    fs::remove_file(&link).unwrap();
+
+    assert_eq!(&followed.unwrap()[3..13], &b"Filebuffer"[..]);
+    assert!(not_followed.is_err());
+    assert_eq!(&regular.unwrap()[3..13], &b"Filebuffer"[..]);
+}
+
+#[cfg(unix)]
+#[test]
+fn open_at_resolves_relative_to_dir() {
+    use std::fs;
+
+    let dir = fs::File::open("src").unwrap();
+    let fbuffer = FileBuffer::open_at(&dir, "lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    let not_found = OpenOptions::new().open_at(&dir, "src/lib.rs");
+    assert_eq!(not_found.unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn no_atime_opens_file() {
+    let fbuffer = OpenOptions::new().no_atime(true).open("src/lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+}
+
+#[cfg(unix)]
+#[test]
+fn lock_shared_takes_a_shared_lock() {
+    use std::os::unix::io::AsRawFd;
+
+    let fbuffer = OpenOptions::new().lock_shared(true).open("src/options.rs").unwrap();
+    let other = std::fs::File::open("src/options.rs").unwrap();
+
+    // While the buffer lives, a different open file can take a shared lock, but not an exclusive
+    // one.
+    let try_lock = |op| unsafe { libc::flock(other.as_raw_fd(), op | libc::LOCK_NB) };
+    assert_eq!(try_lock(libc::LOCK_EX), -1);
+    assert_eq!(try_lock(libc::LOCK_SH), 0);
+    assert_eq!(try_lock(libc::LOCK_UN), 0);
+
+    drop(fbuffer);
+    assert_eq!(try_lock(libc::LOCK_EX), 0);
+}
+
+#[cfg(windows)]
+#[test]
+fn large_pages_falls_back_to_regular_pages() {
+    let fbuffer = OpenOptions::new().large_pages(true).open("src/lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+}
+
+#[test]
+fn no_cache_opens_file() {
+    let fbuffer = OpenOptions::new().no_cache(true).open("src/lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+}
+
+#[test]
+fn no_reserve_opens_file() {
+    let fbuffer = OpenOptions::new().no_reserve(true).open("src/lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+}
+
+#[test]
+fn forbid_empty_rejects_empty_files() {
+    let empty = OpenOptions::new().forbid_empty(true).open("src/empty_file_for_testing.rs");
+    assert_eq!(empty.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+
+    let fbuffer = OpenOptions::new().forbid_empty(true).open("src/lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    let empty = OpenOptions::new().open("src/empty_file_for_testing.rs").unwrap();
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn known_len_maps_the_given_length() {
+    let len = std::fs::metadata("src/lib.rs").unwrap().len();
+    let fbuffer = OpenOptions::new().known_len(len).open("src/lib.rs").unwrap();
+    assert_eq!(fbuffer.len() as u64, len);
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    let prefix = OpenOptions::new().known_len(13).open("src/lib.rs").unwrap();
+    assert_eq!(&prefix[3..], &b"Filebuffer"[..]);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn known_len_is_checked_in_debug_builds() {
+    let len = std::fs::metadata("src/lib.rs").unwrap().len();
+    let err = OpenOptions::new().known_len(len + 1).open("src/lib.rs").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    // Directories are rejected as they are without a known length. On Windows, opening a
+    // directory as a file fails already.
+    assert!(OpenOptions::new().known_len(10).open("src").is_err());
+}
+
+#[test]
+fn writable_policy_refuses_or_fixes_writable_files() {
+    use std::fs;
+
+    let path = super::write_temp_file("writable-policy", 10);
+
+    // Make the file writable by everybody. On Windows, new files are not read-only anyway.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+    }
+
+    let err = FileBuffer::open_readonly_enforced(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(OpenOptions::new().writable_policy(WritablePolicy::Allow).open(&path).is_ok());
+
+    let fbuffer = OpenOptions::new()
+        .writable_policy(WritablePolicy::MakeReadonly)
+        .open(&path)
+        .unwrap();
+    assert_eq!(&fbuffer[..], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9][..]);
+    assert!(fs::metadata(&path).unwrap().permissions().readonly());
+    assert!(FileBuffer::open_readonly_enforced(&path).is_ok());
+
+    // Windows refuses to delete read-only files.
+    #[cfg(windows)]
+    #[allow(clippy::permissions_set_readonly_false)]
+    {
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(false);
+        fs::set_permissions(&path, permissions).unwrap();
+    }
+
+    drop(fbuffer);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,276 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This mod contains a pool that keeps recently used mappings alive between opens.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use super::{FileBuffer, OpenOptions, error_naming_path, get_file_id, open_path};
+
+/// A pool entry: the mapping, the identity, size, and modification time of the file when it was
+/// mapped, and when the entry was last used, for least recently used eviction.
+struct Entry {
+    fbuffer: Arc<FileBuffer>,
+    id: (u64, u64),
+    len: u64,
+    modified: Option<SystemTime>,
+    last_used: u64,
+}
+
+/// A pool that keeps the most recently used mappings alive, keyed by path.
+///
+/// Unlike `FileBufferCache`, which only shares a mapping while it is in use elsewhere, a pool
+/// holds on to up to `capacity` buffers after they have been dropped by their users. This saves
+/// the `mmap` and `munmap` calls when the same small set of files is mapped, read, and dropped
+/// over and over again, for example once per request in a server. When the pool is full, the
+/// least recently used buffer is evicted. An evicted buffer is unmapped as soon as the last `Arc`
+/// to it is dropped, right away if the pool held the only one.
+///
+/// By default the pool opens the file on every `open()`, and checks its identity, size, and
+/// modification time. If the path now refers to a different file, for example because a new
+/// version was renamed over it, or if the file changed, the pool maps it again, so a stale
+/// mapping is not handed out. This costs an `open` and an `fstat` call per open, which is still
+/// much cheaper than mapping the file. When the files are known not to change, validation can be
+/// disabled with `set_validate()`.
+///
+/// Files are identified by the path they were opened through, so different paths to the same file
+/// result in different mappings. See `FileBufferCache` for a cache that identifies files by their
+/// inode instead.
+pub struct FileBufferPool {
+    entries: HashMap<PathBuf, Entry>,
+    capacity: usize,
+    validate: bool,
+    clock: u64,
+}
+
+impl FileBufferPool {
+    /// Creates an empty pool that holds on to at most `capacity` buffers.
+    pub fn new(capacity: usize) -> FileBufferPool {
+        FileBufferPool {
+            entries: HashMap::new(),
+            capacity,
+            validate: true,
+            clock: 0,
+        }
+    }
+
+    /// Sets whether to check that the file did not change before handing out a pooled buffer.
+    ///
+    /// Validation is enabled by default. When it is disabled, a pooled buffer is handed out
+    /// without touching the file system. A buffer mapped before the file was truncated can then
+    /// raise `SIGBUS` when accessed on Unix-ish platforms, so only disable validation when the
+    /// files do not change while the pool is in use.
+    pub fn set_validate(&mut self, validate: bool) {
+        self.validate = validate;
+    }
+
+    /// Returns the maximum number of buffers that the pool holds on to.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of buffers that the pool holds on to.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the pool holds no buffers.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drops all buffers held by the pool.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the pooled buffer for `path`, or maps the file at `path` and adds it to the pool.
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Arc<FileBuffer>> {
+        let path = path.as_ref();
+        self.clock += 1;
+
+        if !self.validate {
+            if let Some(entry) = self.entries.get_mut(path) {
+                entry.last_used = self.clock;
+                return Ok(entry.fbuffer.clone());
+            }
+        }
+
+        // Validate against the file that the path refers to now, through a descriptor, so the
+        // metadata that is compared is the metadata of the file that gets mapped on a miss.
+        let mut options = OpenOptions::new();
+        let file = open_path(path, &options).map_err(|err| error_naming_path(path, err))?;
+        let id = get_file_id(&file)?;
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+        let modified = metadata.modified().ok();
+
+        if let Some(entry) = self.entries.get_mut(path) {
+            if entry.id == id && entry.len == len && entry.modified == modified {
+                entry.last_used = self.clock;
+                return Ok(entry.fbuffer.clone());
+            }
+        }
+
+        // Drop a stale entry before mapping the file again, so the old mapping can go away.
+        self.entries.remove(path);
+
+        // This is the length that the entry records, so pass it on rather than letting mapping
+        // query it again. For files other than regular files, such as block devices, the metadata
+        // reports no meaningful length.
+        if metadata.is_file() {
+            options.known_len(len);
+        }
+
+        let fbuffer = FileBuffer::from_file(file, &options)
+            .map_err(|err| error_naming_path(path, err))?;
+        let fbuffer = Arc::new(fbuffer.with_path(path));
+
+        if self.capacity == 0 {
+            return Ok(fbuffer);
+        }
+
+        if self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+
+        let entry = Entry {
+            fbuffer: fbuffer.clone(),
+            id,
+            len,
+            modified,
+            last_used: self.clock,
+        };
+        self.entries.insert(path.to_path_buf(), entry);
+        Ok(fbuffer)
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let lru_path = self.entries
+            .iter()
+            .min_by_key(|&(_, entry)| entry.last_used)
+            .map(|(path, _)| path.clone());
+
+        if let Some(path) = lru_path {
+            self.entries.remove(&path);
+        }
+    }
+}
+
+impl fmt::Debug for FileBufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileBufferPool")
+            .field("len", &self.entries.len())
+            .field("capacity", &self.capacity)
+            .field("validate", &self.validate)
+            .finish()
+    }
+}
+
+#[test]
+fn open_reuses_pooled_buffers() {
+    let mut pool = FileBufferPool::new(2);
+    let a = pool.open("src/lib.rs").unwrap();
+    let a_ptr = a.as_ptr();
+    drop(a);
+
+    // The pool keeps the buffer alive after its user dropped it.
+    let b = pool.open("src/lib.rs").unwrap();
+    assert_eq!(b.as_ptr(), a_ptr);
+    assert_eq!(pool.len(), 1);
+}
+
+#[test]
+fn open_evicts_the_least_recently_used_buffer() {
+    let mut pool = FileBufferPool::new(2);
+    let lib = pool.open("src/lib.rs").unwrap();
+    let cache = pool.open("src/cache.rs").unwrap();
+    pool.open("src/lib.rs").unwrap();
+
+    // Mapping a third file evicts `cache.rs`, which was used least recently.
+    let pool_buffer = pool.open("src/pool.rs").unwrap();
+    assert_eq!(pool.len(), 2);
+    assert_eq!(Arc::strong_count(&cache), 1);
+    assert_eq!(Arc::strong_count(&lib), 2);
+    assert_eq!(Arc::strong_count(&pool_buffer), 2);
+    assert!(Arc::ptr_eq(&pool.open("src/lib.rs").unwrap(), &lib));
+
+    pool.clear();
+    assert!(pool.is_empty());
+    assert_eq!(Arc::strong_count(&lib), 1);
+}
+
+#[test]
+fn open_maps_changed_files_again() {
+    use std::fs;
+
+    let path = super::write_temp_file("pool", 10);
+    let mut pool = FileBufferPool::new(1);
+    let a = pool.open(&path).unwrap();
+
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    io::Write::write_all(&mut file, &[10]).unwrap();
+    drop(file);
+
+    // Without validation the stale buffer is handed out, with validation it is not.
+    pool.set_validate(false);
+    assert!(Arc::ptr_eq(&pool.open(&path).unwrap(), &a));
+    pool.set_validate(true);
+    let b = pool.open(&path).unwrap();
+    assert!(!Arc::ptr_eq(&a, &b));
+    assert_eq!(&b[..], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10][..]);
+    assert_eq!(Arc::strong_count(&a), 1);
+
+    drop((a, b, pool));
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn open_maps_a_file_renamed_over_the_path_again() {
+    use std::fs;
+    use std::os::unix::io::AsRawFd;
+
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("filebuffer-pool-rename-{}", std::process::id()));
+    let new_path = dir.join(format!("filebuffer-pool-rename-new-{}", std::process::id()));
+    fs::write(&path, b"old contents").unwrap();
+    fs::write(&new_path, b"new contents").unwrap();
+
+    // Give both files the same size and modification time, so only their identity differs.
+    for p in &[&path, &new_path] {
+        let file = fs::File::open(p).unwrap();
+        let times = [libc::timespec { tv_sec: 1_000_000_000, tv_nsec: 0 }; 2];
+        assert_eq!(unsafe { libc::futimens(file.as_raw_fd(), times.as_ptr()) }, 0);
+    }
+
+    let mut pool = FileBufferPool::new(1);
+    let old = pool.open(&path).unwrap();
+    assert_eq!(&old[..], &b"old contents"[..]);
+
+    fs::rename(&new_path, &path).unwrap();
+    let new = pool.open(&path).unwrap();
+    assert_eq!(&new[..], &b"new contents"[..]);
+    assert_eq!(Arc::strong_count(&old), 1);
+
+    drop((old, new, pool));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn open_with_zero_capacity_pools_nothing() {
+    let mut pool = FileBufferPool::new(0);
+    let a = pool.open("src/lib.rs").unwrap();
+    assert_eq!(&a[3..13], &b"Filebuffer"[..]);
+    assert!(pool.is_empty());
+    assert_eq!(Arc::strong_count(&a), 1);
+}
@@ -0,0 +1,110 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This mod contains a handle to track the progress of a prefetch.
+
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+
+use super::{FileBuffer, touch_pages};
+
+/// A handle to a prefetch that is in progress.
+///
+/// Returned by `FileBuffer::prefetch_async()`. The handle becomes ready when a background thread
+/// has touched every page of the prefetched range, which means that the pages were resident at
+/// that point. This is best-effort feedback: pages can be evicted again before they are read, and
+/// the touching itself faults in pages that the kernel did not read ahead.
+///
+/// Dropping the handle does not stop the background thread.
+pub struct PrefetchHandle {
+    done: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl PrefetchHandle {
+    /// Spawns a thread that touches `length` bytes of the buffer, starting at `offset`.
+    pub(crate) fn spawn(
+        fbuffer: &Arc<FileBuffer>,
+        offset: usize,
+        length: usize,
+    ) -> io::Result<PrefetchHandle> {
+        let done = Arc::new(AtomicBool::new(false));
+        let done_thread = done.clone();
+        let weak = Arc::downgrade(fbuffer);
+        let thread = thread::Builder::new()
+            .name("filebuffer-prefetch".to_string())
+            .spawn(move || {
+                touch_in_background(weak, offset, length);
+                done_thread.store(true, Ordering::Release);
+            })?;
+
+        let handle = PrefetchHandle {
+            done,
+            thread: Some(thread),
+        };
+        Ok(handle)
+    }
+
+    /// Returns whether every page of the range has been touched.
+    pub fn is_ready(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+
+    /// Blocks until every page of the range has been touched.
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            // The thread only panics if touching a page panics, which it does not.
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for PrefetchHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PrefetchHandle")
+            .field("is_ready", &self.is_ready())
+            .finish()
+    }
+}
+
+/// Touches every page of the range, one chunk at a time. Like `fault_in_background`, it holds only
+/// a weak reference to the buffer between chunks, and it stops when the buffer is dropped.
+fn touch_in_background(weak: Weak<FileBuffer>, offset: usize, length: usize) {
+    let end = offset + length;
+    let mut offset = offset;
+    while let Some(fbuffer) = weak.upgrade() {
+        if offset >= end { return; }
+        let chunk_len = cmp::min(fbuffer.chunk_len_hint(), end - offset);
+        touch_pages(fbuffer[offset..].as_ptr(), chunk_len, fbuffer.page_size());
+        offset += chunk_len;
+    }
+}
+
+#[test]
+fn prefetch_async_becomes_ready() {
+    let fbuffer = Arc::new(FileBuffer::open("src/lib.rs").unwrap());
+    let handle = fbuffer.prefetch_async(0, fbuffer.len()).unwrap();
+    handle.wait();
+
+    let handle = fbuffer.prefetch_async(10, 20).unwrap();
+    while !handle.is_ready() {
+        thread::yield_now();
+    }
+    assert!(format!("{:?}", handle).contains("is_ready: true"));
+}
+
+#[test]
+fn prefetch_async_stops_when_the_buffer_is_dropped() {
+    let fbuffer = Arc::new(FileBuffer::open("src/lib.rs").unwrap());
+    let handle = fbuffer.prefetch_async(0, fbuffer.len()).unwrap();
+    drop(fbuffer);
+    handle.wait();
+}
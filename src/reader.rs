@@ -0,0 +1,462 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This mod contains readers over a file buffer: a plain cursor, and a reader that only reads
+//! bytes that are resident in physical memory.
+
+use std::cmp;
+use std::convert::TryFrom;
+use std::io;
+
+use super::{FileBuffer, get_available_memory};
+
+/// A cursor over a file buffer that implements `Read`, `BufRead`, and `Seek`.
+///
+/// Returned by `FileBuffer::reader()`. Because the entire file is mapped already, the reader
+/// needs no buffer of its own: `fill_buf()` returns the rest of the mapping, and `consume()` only
+/// advances the position. This makes it a drop-in replacement for a `BufReader<File>`, without
+/// the copy into an intermediate buffer. Like any access to the mapping, reads fault in pages
+/// that are not resident, and block until they are. See `ResidentReader` for a reader that
+/// never blocks.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    fbuffer: &'a FileBuffer,
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(fbuffer: &'a FileBuffer) -> Reader<'a> {
+        Reader {
+            fbuffer,
+            position: 0,
+        }
+    }
+
+    /// Returns the offset into the buffer of the next byte to be read.
+    ///
+    /// This can lie past the end of the buffer after seeking there.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl<'a> io::Read for Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::io::BufRead;
+
+        let n = {
+            let remaining = self.fill_buf()?;
+            let n = cmp::min(remaining.len(), buf.len());
+            buf[..n].copy_from_slice(&remaining[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a> io::BufRead for Reader<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let start = cmp::min(self.position, self.fbuffer.len());
+        Ok(&self.fbuffer[start..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position = self.position.saturating_add(amt);
+    }
+}
+
+/// Seeking works like it does for `io::Cursor`: seeking past the end is allowed, and reads there
+/// return no bytes, but seeking before the start is an error of kind `InvalidInput`.
+impl<'a> io::Seek for Reader<'a> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            io::SeekFrom::Start(n) => Some(n),
+            io::SeekFrom::End(delta) => offset_by(self.fbuffer.len() as u64, delta),
+            io::SeekFrom::Current(delta) => offset_by(self.position as u64, delta),
+        };
+        match new_position.and_then(|n| usize::try_from(n).ok()) {
+            Some(n) => {
+                self.position = n;
+                Ok(n as u64)
+            }
+            None => {
+                let msg = "invalid seek to a negative or overflowing position";
+                Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+            }
+        }
+    }
+}
+
+/// Adds a signed offset to a position, or returns `None` if the result is negative or overflows.
+fn offset_by(position: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        position.checked_add(delta as u64)
+    } else {
+        position.checked_sub(delta.unsigned_abs())
+    }
+}
+
+/// A reader that never blocks on a page fault.
+///
+/// Returned by `FileBuffer::resident_reader()`. Unlike readers over in-memory data, this reader
+/// can return short reads: `read()` only returns bytes that are resident in physical memory, and
+/// it prefetches the rest of the requested range, so a later read can continue where this one
+/// stopped. If none of the requested bytes are resident, `read()` returns an error of kind
+/// `WouldBlock` rather than blocking. This allows an event loop to make progress on other work
+/// while the kernel reads in the file, and to come back later.
+///
+/// As for any reader, `Ok(0)` means that the end of the buffer has been reached (or that the
+/// buffer passed to `read()` was empty); it never means that no bytes are resident yet.
+///
+/// Besides the bytes requested, the reader prefetches a number of chunks of
+/// `FileBuffer::chunk_len_hint()` bytes ahead of the read position, 4 by default. See
+/// `set_readahead()`. The reader remembers how far it has prefetched, and it only advises the
+/// kernel about bytes past that point, so many small reads do not advise the same pages over and
+/// over again. Only when none of the requested bytes are resident does it advise them again, in
+/// case the kernel evicted them after they were prefetched.
+///
+/// The reader never prefetches more than a budget of bytes past the read position, so that on a
+/// system with little free memory, prefetching does not evict pages that were prefetched earlier
+/// but not read yet. The window only slides forward as the read position advances. By default the
+/// budget is a quarter of the physical memory that is available at the first read, or unlimited
+/// where that cannot be determined. See `set_prefetch_budget()`.
+///
+/// On Windows residency cannot be queried, so this reader behaves like a regular reader there.
+/// Because of the short reads and `WouldBlock` errors, helpers that expect a regular reader, such
+/// as `read_to_end()` or `lines()`, fail on a file that is not resident yet. Use `Reader` for
+/// those.
+#[derive(Debug)]
+pub struct ResidentReader<'a> {
+    fbuffer: &'a FileBuffer,
+    position: usize,
+    readahead: usize,
+    // The end of the range that has been prefetched so far.
+    prefetched_end: usize,
+    // The maximum number of bytes to prefetch past the read position. When it is not set, the
+    // default is determined at the first read, so creating a reader does no file I/O.
+    prefetch_budget: Option<usize>,
+}
+
+impl<'a> ResidentReader<'a> {
+    pub(crate) fn new(fbuffer: &'a FileBuffer) -> ResidentReader<'a> {
+        ResidentReader {
+            fbuffer,
+            position: 0,
+            readahead: 4,
+            prefetched_end: 0,
+            prefetch_budget: None,
+        }
+    }
+
+    /// Sets the number of chunks to prefetch ahead of the read position.
+    ///
+    /// A chunk is `FileBuffer::chunk_len_hint()` bytes. If the readahead is too shallow, reads
+    /// return short or fail with `WouldBlock` more often, because the kernel cannot keep up. If it
+    /// is too deep, memory and disk bandwidth are wasted on bytes that are not needed yet. The
+    /// right depth depends on the speed of the storage and of the consumer. With a readahead of
+    /// 0, only the bytes requested by `read()` are prefetched.
+    pub fn set_readahead(&mut self, chunks: usize) {
+        self.readahead = chunks;
+    }
+
+    /// Sets the maximum number of bytes to prefetch past the read position.
+    ///
+    /// This caps both the readahead window and the prefetch of the bytes requested by `read()`.
+    /// With a budget of 0, nothing is prefetched, and reads only return bytes that became
+    /// resident by other means. See the type-level documentation for the default.
+    pub fn set_prefetch_budget(&mut self, bytes: usize) {
+        self.prefetch_budget = Some(bytes);
+    }
+
+    /// Returns the offset into the buffer of the next byte to be read.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the resident bytes among the next `max_len` bytes at the read position, without
+    /// advancing it, and prefetches the rest and the readahead window after it.
+    fn resident_slice(&mut self, max_len: usize) -> io::Result<&'a [u8]> {
+        let remaining = self.fbuffer.len() - self.position;
+        let length = cmp::min(max_len, remaining);
+
+        // This covers both the end of the buffer, and an empty request.
+        if length == 0 {
+            return Ok(&[]);
+        }
+
+        let resident_len = self.fbuffer.resident_len(self.position, length);
+
+        // Ask the kernel to read in the part of the requested range that is not resident, so it
+        // is there next time, and the readahead window after what we return now. Skip what was
+        // prefetched before, unless nothing is resident, in which case it may have been evicted.
+        let readahead_len = self.readahead * self.fbuffer.chunk_len_hint();
+        let nonresident_start = self.position + resident_len;
+        let prefetch_start = if resident_len == 0 {
+            nonresident_start
+        } else {
+            cmp::max(nonresident_start, self.prefetched_end)
+        };
+        let prefetch_end = cmp::max(self.position + length, nonresident_start + readahead_len);
+        let prefetch_budget = *self.prefetch_budget.get_or_insert_with(default_prefetch_budget);
+        let budget_end = self.position.saturating_add(prefetch_budget);
+        let prefetch_end = cmp::min(cmp::min(prefetch_end, budget_end), self.fbuffer.len());
+        if prefetch_end > prefetch_start {
+            self.fbuffer.prefetch(prefetch_start, prefetch_end - prefetch_start);
+            self.prefetched_end = cmp::max(self.prefetched_end, prefetch_end);
+        }
+
+        if resident_len == 0 {
+            let msg = "no bytes are resident at the read position yet";
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, msg));
+        }
+
+        Ok(&self.fbuffer[self.position..self.position + resident_len])
+    }
+}
+
+/// Returns a quarter of the available physical memory, or no limit if it is unknown.
+fn default_prefetch_budget() -> usize {
+    match get_available_memory() {
+        Some(bytes) => cmp::min(bytes / 4, usize::MAX as u64) as usize,
+        None => usize::MAX,
+    }
+}
+
+impl<'a> io::Read for ResidentReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let resident = self.resident_slice(buf.len())?;
+        buf[..resident.len()].copy_from_slice(resident);
+        self.position += resident.len();
+        Ok(resident.len())
+    }
+}
+
+/// Reading through `BufRead` does not copy: `fill_buf()` returns a slice of the mapping.
+///
+/// Like `read()`, `fill_buf()` only returns resident bytes, and it fails with `WouldBlock` if none
+/// of the bytes at the read position are resident. It looks at most one chunk past the readahead
+/// window, so a single call does not scan or prefetch the entire remainder of a large buffer.
+impl<'a> io::BufRead for ResidentReader<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let max_len = (self.readahead + 1) * self.fbuffer.chunk_len_hint();
+        self.resident_slice(max_len)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position = cmp::min(self.position + amt, self.fbuffer.len());
+    }
+}
+
+#[test]
+fn reader_reads_the_entire_buffer() {
+    use std::io::{BufRead, Read};
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let mut contents = Vec::new();
+    fbuffer.reader().read_to_end(&mut contents).unwrap();
+    assert_eq!(&contents[..], &fbuffer[..]);
+
+    let lines = fbuffer.reader().lines().count();
+    assert_eq!(lines, fbuffer.iter().filter(|&&b| b == b'\n').count());
+
+    let empty = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    let mut reader = empty.reader();
+    assert!(reader.fill_buf().unwrap().is_empty());
+    assert_eq!(reader.read(&mut [0u8; 16]).unwrap(), 0);
+}
+
+#[test]
+fn reader_fill_buf_returns_the_rest_of_the_buffer() {
+    use std::io::BufRead;
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let mut reader = fbuffer.reader();
+    assert_eq!(reader.fill_buf().unwrap().as_ptr(), fbuffer.as_ptr());
+
+    reader.consume(3);
+    assert_eq!(&reader.fill_buf().unwrap()[..10], &b"Filebuffer"[..]);
+    assert_eq!(reader.fill_buf().unwrap().len(), fbuffer.len() - 3);
+
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line).unwrap();
+    assert_eq!(&line[..], &b"Filebuffer -- Fast and simple file reading\n"[..]);
+}
+
+#[test]
+fn reader_seeks_like_a_cursor() {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let len = fbuffer.len() as u64;
+    let mut reader = fbuffer.reader();
+    let mut buf = [0u8; 10];
+
+    assert_eq!(reader.seek(SeekFrom::Start(3)).unwrap(), 3);
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"Filebuffer");
+    assert_eq!(reader.seek(SeekFrom::Current(-10)).unwrap(), 3);
+    assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), len - 1);
+    assert_eq!(reader.read(&mut buf).unwrap(), 1);
+
+    // Seeking past the end is fine, but reads there return nothing.
+    assert_eq!(reader.seek(SeekFrom::End(5)).unwrap(), len + 5);
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+    let err = reader.seek(SeekFrom::Current(-(len as i64) - 6)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(reader.position() as u64, len + 5);
+}
+
+/// Reads from `reader` into `buf`, and yields to other threads while no bytes are resident.
+#[cfg(test)]
+fn read_resident(reader: &mut ResidentReader, buf: &mut [u8]) -> usize {
+    use std::io::Read;
+    use std::thread;
+
+    loop {
+        match reader.read(buf) {
+            Ok(n) => return n,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => thread::yield_now(),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+/// Reads `reader` until the end of the buffer, in reads of at most `buf.len()` bytes.
+#[cfg(test)]
+fn read_to_end_resident(reader: &mut ResidentReader, buf: &mut [u8]) -> Vec<u8> {
+    let mut contents = Vec::new();
+    loop {
+        match read_resident(reader, buf) {
+            0 => return contents,
+            n => contents.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+#[test]
+fn resident_reader_reads_the_entire_buffer() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let mut reader = fbuffer.resident_reader();
+    let contents = read_to_end_resident(&mut reader, &mut [0u8; 4096]);
+
+    assert_eq!(&contents[..], &fbuffer[..]);
+    assert_eq!(reader.position(), fbuffer.len());
+    assert_eq!(read_resident(&mut reader, &mut [0u8; 16]), 0);
+}
+
+#[test]
+fn resident_reader_reads_with_any_readahead() {
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+
+    for &readahead in &[0, 1, 64] {
+        let mut reader = fbuffer.resident_reader();
+        reader.set_readahead(readahead);
+        let contents = read_to_end_resident(&mut reader, &mut [0u8; 1000]);
+
+        assert_eq!(&contents[..], &fbuffer[..]);
+    }
+}
+
+#[test]
+fn resident_reader_implements_buf_read() {
+    use std::io::BufRead;
+    use std::thread;
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let mut reader = fbuffer.resident_reader();
+    let mut lines = 0;
+    let mut line = Vec::new();
+
+    loop {
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                assert!(line.ends_with(b"\n") || reader.position() == fbuffer.len());
+                lines += line.ends_with(b"\n") as usize;
+                line.clear();
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => thread::yield_now(),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    assert_eq!(lines, fbuffer.iter().filter(|&&b| b == b'\n').count());
+    assert_eq!(reader.position(), fbuffer.len());
+    assert!(reader.fill_buf().unwrap().is_empty());
+}
+
+#[test]
+fn resident_reader_reads_nothing_from_an_empty_buffer() {
+    use std::io::Read;
+
+    let fbuffer = FileBuffer::open("src/empty_file_for_testing.rs").unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(fbuffer.resident_reader().read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn resident_reader_does_not_prefetch_the_same_bytes_again() {
+    use super::OpenOptions;
+
+    let fbuffer = OpenOptions::new().track_stats(true).open("src/lib.rs").unwrap();
+    let mut reader = fbuffer.resident_reader();
+    let mut buf = [0u8; 64];
+    let mut reads = 0;
+
+    while read_resident(&mut reader, &mut buf) > 0 {
+        reads += 1;
+    }
+
+    // Every read advances by 64 bytes, but the prefetches advance by chunks, so the number of
+    // prefetches grows with the number of chunks rather than with the number of reads.
+    let stats = fbuffer.stats().unwrap();
+    let chunks = (fbuffer.len() + fbuffer.chunk_len_hint() - 1) / fbuffer.chunk_len_hint();
+    assert!(reads > 100);
+    assert!(stats.prefetch_calls <= chunks + 1, "{} prefetches", stats.prefetch_calls);
+}
+
+#[test]
+fn resident_reader_determines_the_default_budget_lazily() {
+    use std::io::Read;
+
+    let fbuffer = FileBuffer::open("src/lib.rs").unwrap();
+    let mut reader = fbuffer.resident_reader();
+    assert_eq!(reader.prefetch_budget, None);
+    let _ = reader.read(&mut [0u8; 16]);
+    assert!(reader.prefetch_budget.is_some());
+
+    let mut reader = fbuffer.resident_reader();
+    reader.set_prefetch_budget(0);
+    let _ = reader.read(&mut [0u8; 16]);
+    assert_eq!(reader.prefetch_budget, Some(0));
+}
+
+#[test]
+fn resident_reader_stays_within_the_prefetch_budget() {
+    use super::OpenOptions;
+
+    let fbuffer = OpenOptions::new().track_stats(true).open("src/lib.rs").unwrap();
+    let page_size = fbuffer.page_size();
+    let mut reader = fbuffer.resident_reader();
+    reader.set_readahead(64);
+    reader.set_prefetch_budget(page_size);
+    let mut buf = [0u8; 64];
+    let mut contents = Vec::new();
+
+    loop {
+        match read_resident(&mut reader, &mut buf) {
+            0 => break,
+            n => contents.extend_from_slice(&buf[..n]),
+        }
+        assert!(reader.prefetched_end <= reader.position() + page_size);
+    }
+
+    assert_eq!(&contents[..], &fbuffer[..]);
+    assert!(fbuffer.stats().unwrap().prefetch_calls > 1);
+}
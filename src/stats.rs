@@ -0,0 +1,84 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This mod contains the counters that track how a buffer is advised, for profiling.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::get_fault_counts;
+
+/// Statistics about the advice given for a buffer over its lifetime.
+///
+/// Returned by `FileBuffer::stats()` for buffers opened with `OpenOptions::track_stats(true)`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct BufferStats {
+    /// The number of calls that advised the kernel to make part of the buffer resident, such as
+    /// `prefetch()` and `prefetch_ranges()`.
+    pub prefetch_calls: usize,
+    /// The number of bytes advised by those calls, after rounding outwards to page boundaries.
+    pub prefetch_bytes: usize,
+    /// The number of calls that advised the kernel that part of the buffer is no longer needed,
+    /// such as `advise_free()` and `advise_pageout()`.
+    pub evict_calls: usize,
+    /// The number of major page faults (which required disk access) since the buffer was opened.
+    ///
+    /// This counts all page faults of the process, not only those that touched the buffer, so it
+    /// is only a good measure when the buffer is the main source of faults. It is `None` on
+    /// platforms where the fault count cannot be queried, including Windows.
+    pub major_faults: Option<u64>,
+    /// The number of minor page faults (which did not require disk access) since the buffer was
+    /// opened. The same caveats as for `major_faults` apply.
+    pub minor_faults: Option<u64>,
+}
+
+/// The counters behind `BufferStats`, updated through a shared reference.
+#[derive(Debug)]
+pub(crate) struct StatsCounters {
+    prefetch_calls: AtomicUsize,
+    prefetch_bytes: AtomicUsize,
+    evict_calls: AtomicUsize,
+    initial_faults: Option<(u64, u64)>,
+}
+
+impl StatsCounters {
+    pub(crate) fn new() -> StatsCounters {
+        StatsCounters {
+            prefetch_calls: AtomicUsize::new(0),
+            prefetch_bytes: AtomicUsize::new(0),
+            evict_calls: AtomicUsize::new(0),
+            initial_faults: get_fault_counts(),
+        }
+    }
+
+    pub(crate) fn record_prefetch(&self, bytes: usize) {
+        // The counters are independent, there is no ordering to enforce between them.
+        self.prefetch_calls.fetch_add(1, Ordering::Relaxed);
+        self.prefetch_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_evict(&self) {
+        self.evict_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> BufferStats {
+        let faults = match (self.initial_faults, get_fault_counts()) {
+            (Some((major0, minor0)), Some((major1, minor1))) => {
+                Some((major1.saturating_sub(major0), minor1.saturating_sub(minor0)))
+            }
+            _ => None,
+        };
+
+        BufferStats {
+            prefetch_calls: self.prefetch_calls.load(Ordering::Relaxed),
+            prefetch_bytes: self.prefetch_bytes.load(Ordering::Relaxed),
+            evict_calls: self.evict_calls.load(Ordering::Relaxed),
+            major_faults: faults.map(|(major, _)| major),
+            minor_faults: faults.map(|(_, minor)| minor),
+        }
+    }
+}
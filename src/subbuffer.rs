@@ -0,0 +1,142 @@
+// Filebuffer -- Fast and simple file reading
+// Copyright 2016 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This mod contains an owned view of part of a shared file buffer.
+
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use super::{Advice, FileBuffer};
+
+/// An owned view of a slice of a shared `FileBuffer`.
+///
+/// Returned by `FileBuffer::split_off_aligned()`. A sub-buffer holds a reference to the buffer it
+/// was split from, so it can outlive the original handle and be moved to a different thread. It
+/// dereferences to its slice of the buffer, and the methods that advise the kernel take offsets
+/// relative to the start of the sub-buffer.
+#[derive(Clone)]
+pub struct SubBuffer {
+    fbuffer: Arc<FileBuffer>,
+    offset: usize,
+    length: usize,
+}
+
+impl SubBuffer {
+    pub(crate) fn new(fbuffer: Arc<FileBuffer>, offset: usize, length: usize) -> SubBuffer {
+        debug_assert!(offset + length <= fbuffer.len());
+        SubBuffer {
+            fbuffer,
+            offset,
+            length,
+        }
+    }
+
+    /// Returns the offset of the sub-buffer in the buffer that it was split from.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the buffer that the sub-buffer was split from.
+    pub fn file_buffer(&self) -> &Arc<FileBuffer> {
+        &self.fbuffer
+    }
+
+    /// Advises the kernel to make a slice of the sub-buffer resident, see `FileBuffer::prefetch()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the sub-buffer.
+    pub fn prefetch(&self, offset: usize, length: usize) {
+        assert!(offset + length <= self.length);
+        self.fbuffer.prefetch(self.offset + offset, length)
+    }
+
+    /// Advises the kernel about a slice of the sub-buffer, see `FileBuffer::advise()`.
+    ///
+    /// Advice applies to whole pages. When the sub-buffer starts or ends at a page boundary, as
+    /// the sub-buffers returned by `FileBuffer::split_off_aligned()` do, advice for one sub-buffer
+    /// does not affect the pages of the other.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the sub-buffer.
+    pub fn advise(&self, offset: usize, length: usize, advice: Advice) -> io::Result<()> {
+        assert!(offset + length <= self.length);
+        self.fbuffer.advise(self.offset + offset, length, advice)
+    }
+
+    /// Returns the number of bytes resident in physical memory, starting from `offset`, see
+    /// `FileBuffer::resident_len()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the specified range lies outside of the sub-buffer.
+    pub fn resident_len(&self, offset: usize, length: usize) -> usize {
+        assert!(offset + length <= self.length);
+        self.fbuffer.resident_len(self.offset + offset, length)
+    }
+}
+
+impl Deref for SubBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.fbuffer[self.offset..self.offset + self.length]
+    }
+}
+
+impl AsRef<[u8]> for SubBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.deref()
+    }
+}
+
+impl fmt::Debug for SubBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SubBuffer")
+            .field("offset", &self.offset)
+            .field("len", &self.length)
+            .finish()
+    }
+}
+
+#[test]
+fn split_off_aligned_splits_at_a_page_boundary() {
+    let fbuffer = Arc::new(FileBuffer::open("src/lib.rs").unwrap());
+    let page_size = fbuffer.page_size();
+
+    let (front, back) = fbuffer.split_off_aligned(10);
+    assert_eq!(front.offset(), 0);
+    assert_eq!(front.len(), page_size);
+    assert_eq!(back.offset(), page_size);
+    assert_eq!(back.len(), fbuffer.len() - page_size);
+    assert_eq!(&front[3..13], &b"Filebuffer"[..]);
+    assert_eq!(&back[..], &fbuffer[page_size..]);
+
+    // The sub-buffers keep the buffer alive.
+    drop(fbuffer);
+    back.prefetch(0, back.len());
+    back.advise(0, back.len(), Advice::DontNeed).unwrap();
+    assert_eq!(front.resident_len(0, 13), 13);
+    assert_eq!(&front[3..13], &b"Filebuffer"[..]);
+}
+
+#[test]
+fn split_off_aligned_clamps_to_the_buffer() {
+    let fbuffer = Arc::new(FileBuffer::open("src/lib.rs").unwrap());
+    let len = fbuffer.len();
+
+    let (front, back) = fbuffer.split_off_aligned(len);
+    assert_eq!(front.len(), len);
+    assert!(back.is_empty());
+
+    let (front, back) = fbuffer.split_off_aligned(0);
+    assert!(front.is_empty());
+    assert_eq!(back.len(), len);
+}
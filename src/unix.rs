@@ -8,42 +8,732 @@
 //! This mod contains the platform-specific implementations of functions based on the libc crate
 //! that is available on Unix-ish platforms.
 
+use std::cmp;
 use std::fs;
 use std::io;
 use std::mem;
-use std::os::unix::io::AsRawFd;
+use std::ops::Range;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
 use std::ptr;
+#[cfg(target_os = "linux")]
+use std::sync::Mutex;
+
+use super::{Advice, HeapPages, OpenOptions, WritablePolicy};
 
 extern crate libc;
 
+// These are not defined by the version of libc that we depend on. The values are the same for
+// all architectures, see `include/uapi/asm-generic/mman-common.h` in the kernel source.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const MADV_COLD: i32 = 20;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const MADV_PAGEOUT: i32 = 21;
+
+// Prefetches at least this long also submit `readahead` calls, see `prefetch_deep`.
+#[cfg(target_os = "linux")]
+const DEEP_PREFETCH_MIN_LEN: usize = 8 * 1024 * 1024;
+
+// Not defined by libc for musl. See `include/uapi/linux/magic.h` in the kernel source.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const TMPFS_MAGIC: i64 = 0x0102_1994;
+
 #[derive(Debug)]
-pub struct PlatformData;
+pub struct PlatformData {
+    // The file is kept open so that advice can be given on the file descriptor, and `offset` is
+    // the offset into the file at which the mapping starts. Anonymous mappings have no file.
+    file: Option<fs::File>,
+    #[cfg_attr(
+        not(any(target_os = "android", target_os = "freebsd", target_os = "linux")),
+        allow(dead_code)
+    )]
+    offset: u64,
+    // The length of the mapping in bytes, so `unmap_file` can check that it unmaps what was mapped.
+    length: usize,
+    // Whether the file lives on tmpfs, so its pages are in memory rather than on disk.
+    memory_backed: bool,
+    // A mapping that was created by `memmap2` is owned by its `Mmap`, and it is unmapped when
+    // that is dropped, rather than by `unmap_file`.
+    #[cfg(feature = "memmap2")]
+    mmap: Option<memmap2::Mmap>,
+    // The contents of a small file that was read into memory rather than mapped, see `read_file`.
+    // The buffer points into it, and it is freed when the platform data is dropped.
+    heap: Option<HeapPages>,
+    // The readahead window and the number of request slots of the device that holds the file,
+    // resolved on first use by `get_queue_params`, because it reads several files in `/sys`.
+    #[cfg(target_os = "linux")]
+    queue_params: Mutex<Option<(usize, usize)>>,
+}
+
+/// Returns the file descriptor of the mapped file, or `None` for mappings without a file.
+pub fn get_raw_fd(platform_data: &PlatformData) -> Option<RawFd> {
+    platform_data.file.as_ref().map(|file| file.as_raw_fd())
+}
+
+/// Returns a new handle to the mapped file, or an error for mappings that are not backed by a
+/// file.
+pub fn clone_file(platform_data: &PlatformData) -> io::Result<fs::File> {
+    match platform_data.file {
+        Some(ref file) => file.try_clone(),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is not backed by a file")),
+    }
+}
+
+/// Opens the file at `path` for reading, applying the Unix-specific flags for `options`.
+pub fn open_path(path: &Path, options: &OpenOptions) -> io::Result<fs::File> {
+    trace_event!(DEBUG, path = %path.display(), "opening file");
+    let file = open_with_options(options, |flags| open_with_flags(path, flags))?;
+    if options.no_cache { set_no_cache(&file)?; }
+    if options.lock_shared { lock_shared(&file)?; }
+    apply_writable_policy(&file, options.writable_policy)?;
+    Ok(file)
+}
+
+/// Opens the file at `path` relative to the directory `dir` for reading, like `open_path`.
+pub fn open_path_at(dir: RawFd, path: &Path, options: &OpenOptions) -> io::Result<fs::File> {
+    trace_event!(DEBUG, dir, path = %path.display(), "opening file");
+    let file = open_with_options(options, |flags| open_at_with_flags(dir, path, flags))?;
+    if options.no_cache { set_no_cache(&file)?; }
+    if options.lock_shared { lock_shared(&file)?; }
+    apply_writable_policy(&file, options.writable_policy)?;
+    Ok(file)
+}
+
+/// Checks that `file` is not writable by the group or other users, or clears its write bits, as
+/// `policy` prescribes.
+fn apply_writable_policy(file: &fs::File, policy: WritablePolicy) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if policy == WritablePolicy::Allow { return Ok(()); }
+
+    let mut permissions = file.metadata()?.permissions();
+    let mode = permissions.mode();
+
+    match policy {
+        WritablePolicy::Refuse if mode & 0o022 != 0 => {
+            let msg = "file is writable by other users";
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, msg))
+        }
+        WritablePolicy::MakeReadonly if mode & 0o222 != 0 => {
+            // This is an `fchmod` on the opened file, so it cannot affect a different file.
+            permissions.set_mode(mode & !0o222);
+            file.set_permissions(permissions)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Tells the kernel not to keep the data of `file` in the unified buffer cache after it is read,
+/// with `F_NOCACHE`. This is only supported on macOS and iOS, elsewhere it is a no-op.
+fn set_no_cache(file: &fs::File) -> io::Result<()> {
+    #[cfg(any(target_os = "ios", target_os = "macos"))]
+    {
+        let fd = file.as_raw_fd();
+        let result = retry_on_eintr(-1, || unsafe { libc::fcntl(fd, libc::F_NOCACHE, 1) });
+        if result == -1 { return Err(io::Error::last_os_error()); }
+    }
+
+    #[cfg(not(any(target_os = "ios", target_os = "macos")))]
+    {
+        let _ = file;
+    }
+
+    Ok(())
+}
+
+/// Takes a shared advisory lock on `file` with `flock`, waiting for an exclusive lock held by a
+/// different process to be released. The lock is released when the file is closed.
+fn lock_shared(file: &fs::File) -> io::Result<()> {
+    // Waiting for the lock can be interrupted by a signal, in which case we wait again.
+    let result = retry_on_eintr(-1, || unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH) });
+    if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+}
+
+/// Calls `f` until it does not fail with `EINTR`, and returns its result.
+///
+/// The standard library retries system calls that are interrupted by a signal, but the calls that
+/// we make directly through `libc` do not. In a process that receives many signals, for example
+/// because a profiler is attached, a stray signal would otherwise surface as a spurious error.
+/// `failed` is the value with which the call indicates failure, after which `errno` is inspected.
+fn retry_on_eintr<T: Copy + PartialEq, F: FnMut() -> T>(failed: T, mut f: F) -> T {
+    loop {
+        let result = f();
+        if result != failed || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return result;
+        }
+    }
+}
+
+/// Calls `madvise` on the range of `length` bytes at `buffer`, retrying when interrupted by a
+/// signal. Returns 0 on success, and -1 with `errno` set on failure, like `madvise` itself.
+fn madvise(buffer: *const u8, length: usize, advice: i32) -> i32 {
+    retry_on_eintr(-1, || unsafe { libc::madvise(buffer as *mut libc::c_void, length, advice) })
+}
+
+/// Determines the flags for `options`, and opens the file with `open`.
+fn open_with_options<F>(options: &OpenOptions, open: F) -> io::Result<fs::File>
+where
+    F: Fn(i32) -> io::Result<fs::File>,
+{
+    let mut flags = 0;
+
+    if !options.follow_symlinks {
+        flags |= libc::O_NOFOLLOW;
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        if options.no_atime {
+            match open(flags | libc::O_NOATIME) {
+                // `O_NOATIME` is only permitted for the owner of the file. For other files, fall
+                // back to opening without it.
+                Err(ref err) if err.raw_os_error() == Some(libc::EPERM) => {}
+                result => return result,
+            }
+        }
+    }
+
+    open(flags)
+}
+
+fn open_with_flags(path: &Path, flags: i32) -> io::Result<fs::File> {
+    let mut open_opts = fs::OpenOptions::new();
+    open_opts.read(true);
+    open_opts.custom_flags(flags);
+    open_opts.open(path)
+}
+
+fn open_at_with_flags(dir: RawFd, path: &Path, flags: i32) -> io::Result<fs::File> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::FromRawFd;
 
-pub fn map_file(file: fs::File) -> io::Result<(*const u8, usize, PlatformData)> {
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => {
+            let msg = "path contains an interior null byte";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+    };
+
+    // Like `fs::File::open`, open the file with `O_CLOEXEC`, so it does not leak into child
+    // processes.
+    let fd = retry_on_eintr(-1, || unsafe {
+        libc::openat(dir, path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC | flags)
+    });
+
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { fs::File::from_raw_fd(fd) })
+    }
+}
+
+/// Returns the number of bytes that can be mapped from `file`.
+///
+/// For regular files this is the length from the metadata. On Linux, the metadata of a block
+/// device reports a length of 0, so for block devices the size is determined by seeking to the
+/// end, which reports the same size as the `BLKGETSIZE64` ioctl. Character devices do not have a
+/// size, so they are rejected. Directories can be opened for reading, but not mapped, so they are
+/// rejected as well, rather than failing later in `mmap` with an obscure error.
+pub fn get_file_len(file: &fs::File) -> io::Result<u64> {
+    let metadata = file.metadata()?;
+
+    if metadata.is_dir() {
+        let msg = "cannot memory-map a directory";
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::io::Seek;
+        use std::os::unix::fs::FileTypeExt;
+
+        let file_type = metadata.file_type();
+
+        if file_type.is_block_device() {
+            let mut file = file;
+            return file.seek(io::SeekFrom::End(0));
+        }
+
+        if file_type.is_char_device() {
+            let msg = "cannot map a character device";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+    }
+
+    Ok(metadata.len())
+}
+
+/// Returns how many bytes at the start of the mapping are still backed by the file, which is less
+/// than the length of the mapping if the file was truncated after it was mapped.
+pub fn get_backed_len(platform_data: &PlatformData) -> io::Result<usize> {
+    let file_len = match platform_data.file {
+        Some(ref file) => get_file_len(file)?,
+        None => return Ok(platform_data.length),
+    };
+    let backed_len = file_len.saturating_sub(platform_data.offset);
+    Ok(cmp::min(backed_len, platform_data.length as u64) as usize)
+}
+
+/// Returns the current length of the mapped file, or `None` if the mapping is not backed by a file.
+pub fn get_current_file_len(platform_data: &PlatformData) -> io::Result<Option<u64>> {
+    match platform_data.file {
+        Some(ref file) => get_file_len(file).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Reads `length` bytes of the mapped file, starting at `offset` relative to the start of the
+/// mapping, with `pread`, and discards them. When this returns, the bytes are in the page cache.
+pub fn read_file_range(
+    platform_data: &PlatformData,
+    offset: usize,
+    length: usize,
+) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    let file = match platform_data.file {
+        Some(ref file) => file,
+        None => return Ok(()),
+    };
+
+    let mut buf = [0u8; 64 * 1024];
+    let mut file_offset = platform_data.offset + offset as u64;
+    let mut remaining = length;
+    while remaining > 0 {
+        let n = cmp::min(remaining, buf.len());
+        match file.read_at(&mut buf[..n], file_offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file was truncated")),
+            Ok(n_read) => {
+                file_offset += n_read as u64;
+                remaining -= n_read;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the number of major and minor page faults of the process so far, or `None` if the
+/// counts cannot be queried.
+pub fn get_fault_counts() -> Option<(u64, u64)> {
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    let result = unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    if result == 0 {
+        Some((usage.ru_majflt as u64, usage.ru_minflt as u64))
+    } else {
+        None
+    }
+}
+
+/// Returns the device and inode number of `file`, which together identify the file.
+pub fn get_file_id(file: &fs::File) -> io::Result<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = file.metadata()?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+/// Maps `length` bytes of `file`, starting at `offset`, which must be page-aligned. The range must
+/// lie within the file.
+#[allow(clippy::legacy_numeric_constants)]
+pub fn map_file(
+    file: fs::File,
+    offset: u64,
+    length: usize,
+    options: &OpenOptions,
+) -> io::Result<(*const u8, PlatformData)> {
     let fd = file.as_raw_fd();
-    let length = file.metadata()?.len();
+    let memory_backed = is_on_tmpfs(&file);
+
+    let platform_data = PlatformData {
+        file: Some(file),
+        offset,
+        length,
+        memory_backed,
+        #[cfg(feature = "memmap2")]
+        mmap: None,
+        heap: None,
+        #[cfg(target_os = "linux")]
+        queue_params: Mutex::new(None),
+    };
 
-    if length > usize::max_value() as u64 {
+    // Don't try to map anything if the range is empty.
+    if length == 0 {
+        return Ok((ptr::null(), platform_data));
+    }
+
+    // A slice can be at most `isize::MAX` bytes long, a larger mapping could not be dereferenced.
+    if length > isize::MAX as usize {
         return Err(io::Error::new(io::ErrorKind::Other, "file is larger than address space"));
     }
 
+    // On 32-bit platforms without large file support, `off_t` cannot address the entire file.
+    if offset > libc::off_t::max_value() as u64 {
+        return Err(io::Error::new(io::ErrorKind::Other, "offset is larger than off_t"));
+    }
+
+    let mut flags = libc::MAP_PRIVATE | options.mmap_flags;
+    if options.no_reserve {
+        flags |= libc::MAP_NORESERVE;
+    }
+
+    let result = retry_on_eintr(libc::MAP_FAILED, || unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            length,
+            libc::PROT_READ,
+            flags,
+            fd,
+            offset as libc::off_t
+        )
+    });
+
+    if result == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Memory on tmpfs can be backed by transparent huge pages, depending on the `huge=` mount
+    // option, or `shmem_enabled` in sysfs. The advice only matters when it is set to `advise`, and
+    // like all advice it is only a hint, so failure is ignored.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        if memory_backed {
+            madvise(result as *const u8, length, libc::MADV_HUGEPAGE);
+        }
+    }
+
+    Ok((result as *const u8, platform_data))
+}
+
+/// Returns whether `file` lives on tmpfs, such as `/dev/shm`, whose pages live in memory rather
+/// than on disk. This is only detected on Linux and Android; elsewhere it returns false.
+fn is_on_tmpfs(file: &fs::File) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let mut stat: libc::statfs = unsafe { mem::zeroed() };
+        let result = retry_on_eintr(-1, || unsafe { libc::fstatfs(file.as_raw_fd(), &mut stat) });
+        result == 0 && stat.f_type as i64 == TMPFS_MAGIC
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = file;
+        false
+    }
+}
+
+/// Returns whether the mapped file lives in memory, on tmpfs, so that its pages are resident
+/// unless they were swapped out. See `is_on_tmpfs`.
+pub fn is_memory_backed(platform_data: &PlatformData) -> bool {
+    platform_data.memory_backed
+}
+
+/// Resizes the mapping of `file` at `buffer` to `new_length` bytes, and records the new length in
+/// `platform_data`. Neither the old nor the new length may be zero. Returns the new address of the
+/// mapping, which may have moved.
+///
+/// On Linux this uses `mremap`, which can often extend the mapping in place. Elsewhere the file is
+/// mapped again and the old mapping is unmapped.
+pub fn remap_file(
+    buffer: *const u8,
+    new_length: usize,
+    file: &fs::File,
+    platform_data: &mut PlatformData,
+    options: &OpenOptions,
+) -> io::Result<*const u8> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let _ = (file, options);
+        let result = retry_on_eintr(libc::MAP_FAILED, || unsafe {
+            libc::mremap(
+                buffer as *mut libc::c_void,
+                platform_data.length,
+                new_length,
+                libc::MREMAP_MAYMOVE
+            )
+        });
+
+        if result == libc::MAP_FAILED {
+            Err(io::Error::last_os_error())
+        } else {
+            platform_data.length = new_length;
+            Ok(result as *const u8)
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let (new_buffer, _new_platform_data) =
+            map_file(file.try_clone()?, 0, new_length, options)?;
+        unmap_file(buffer, platform_data.length, platform_data);
+        platform_data.length = new_length;
+        Ok(new_buffer)
+    }
+}
+
+/// Maps the first `length` bytes of `file` into memory as a private, writable copy-on-write
+/// mapping. Writes to the mapping are never written back to the file.
+pub fn map_file_cow(file: fs::File, length: usize) -> io::Result<(*mut u8, PlatformData)> {
+    let fd = file.as_raw_fd();
+
+    let platform_data = PlatformData {
+        file: Some(file),
+        offset: 0,
+        length,
+        memory_backed: false,
+        #[cfg(feature = "memmap2")]
+        mmap: None,
+        heap: None,
+        #[cfg(target_os = "linux")]
+        queue_params: Mutex::new(None),
+    };
+
     // Don't try to map anything if the file is empty.
     if length == 0 {
-        return Ok((ptr::null(), 0, PlatformData));
+        return Ok((ptr::null_mut(), platform_data));
     }
 
-    let result = unsafe {
-        libc::mmap(ptr::null_mut(), length as usize, libc::PROT_READ, libc::MAP_PRIVATE, fd, 0)
+    // `MAP_PRIVATE` makes writes go to private copies of the pages, rather than to the file.
+    let result = retry_on_eintr(libc::MAP_FAILED, || unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            length,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE,
+            fd,
+            0
+        )
+    });
+
+    if result == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok((result as *mut u8, platform_data))
+    }
+}
+
+/// Maps `length` bytes of zeroed, writable memory that is not backed by a file.
+pub fn map_anonymous(length: usize) -> io::Result<(*mut u8, PlatformData)> {
+    let platform_data = PlatformData {
+        file: None,
+        offset: 0,
+        length,
+        memory_backed: false,
+        #[cfg(feature = "memmap2")]
+        mmap: None,
+        heap: None,
+        #[cfg(target_os = "linux")]
+        queue_params: Mutex::new(None),
     };
 
+    // Don't try to map anything if the length is zero, `mmap` rejects empty mappings.
+    if length == 0 {
+        return Ok((ptr::null_mut(), platform_data));
+    }
+
+    let result = retry_on_eintr(libc::MAP_FAILED, || unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            length,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANON,
+            -1,
+            0
+        )
+    });
+
     if result == libc::MAP_FAILED {
         Err(io::Error::last_os_error())
     } else {
-        Ok((result as *const u8, length as usize, PlatformData))
+        Ok((result as *mut u8, platform_data))
+    }
+}
+
+/// Flushes the writable mapping of `length` bytes at `buffer` to its file, if it has one, and then
+/// makes the mapping read-only.
+pub fn protect_readonly(
+    buffer: *mut u8,
+    length: usize,
+    platform_data: &PlatformData,
+) -> io::Result<()> {
+    if platform_data.file.is_some() {
+        let result = retry_on_eintr(-1, || unsafe {
+            libc::msync(buffer as *mut libc::c_void, length, libc::MS_SYNC)
+        });
+        if result != 0 { return Err(io::Error::last_os_error()); }
+    }
+
+    let result = retry_on_eintr(-1, || unsafe {
+        libc::mprotect(buffer as *mut libc::c_void, length, libc::PROT_READ)
+    });
+    if result != 0 { return Err(io::Error::last_os_error()); }
+
+    Ok(())
+}
+
+/// Locks the `length` bytes at `buffer` into physical memory with `mlock`.
+///
+/// The lock is released when the memory is unmapped or freed. Locking more than `RLIMIT_MEMLOCK`
+/// allows fails with `ENOMEM`, or with `EPERM` if the limit is zero.
+pub fn lock_memory(buffer: *const u8, length: usize) -> io::Result<()> {
+    let result = unsafe { libc::mlock(buffer as *const libc::c_void, length) };
+    if result != 0 { return Err(io::Error::last_os_error()); }
+
+    Ok(())
+}
+
+/// Reads the entire file at `path` into anonymous memory with `O_DIRECT`, bypassing the page
+/// cache. Returns the buffer, the length of the file, and the platform data that owns the buffer.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn read_direct(path: &Path) -> io::Result<(*const u8, usize, PlatformData)> {
+    use std::io::Read;
+
+    let mut file = open_with_flags(path, libc::O_DIRECT)?;
+    let file_len = get_file_len(&file)?;
+
+    if file_len > isize::MAX as u64 {
+        return Err(io::Error::new(io::ErrorKind::Other, "file is larger than address space"));
+    }
+
+    // Don't allocate anything for an empty file, like `map_file` does not map anything.
+    let length = file_len as usize;
+    if length == 0 {
+        let (buffer, mut platform_data) = map_anonymous(0)?;
+        platform_data.file = Some(file);
+        return Ok((buffer, 0, platform_data));
+    }
+
+    // With `O_DIRECT`, the address of the buffer, the file offset, and the length of every read
+    // must be aligned to the logical block size of the device, typically 512 bytes or 4 KiB. A
+    // page-aligned allocation that is a whole number of pages satisfies this.
+    let page_size = get_page_size();
+    let aligned_length = (length + page_size - 1) & !(page_size - 1);
+    let mut heap = HeapPages::new(length, page_size);
+    let buffer = heap.as_mut_ptr();
+
+    let mut bytes_read = 0;
+    while bytes_read < length {
+        let dst = unsafe {
+            std::slice::from_raw_parts_mut(buffer.add(bytes_read), aligned_length - bytes_read)
+        };
+        match file.read(dst) {
+            // Reads only return short at the end of the file. If the file shrunk in the meantime,
+            // the remainder of the buffer stays zeroed.
+            Ok(0) => break,
+            Ok(n) => bytes_read += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
     }
+
+    let (buffer, platform_data) = adopt_heap(Some(file), heap);
+    Ok((buffer, length, platform_data))
 }
 
-pub fn unmap_file(buffer: *const u8, length: usize) {
+/// Takes ownership of a mapping that was created by `memmap2`. Returns the address of the mapping,
+/// and platform data that keeps it alive. The mapping must not be empty.
+#[cfg(feature = "memmap2")]
+pub fn adopt_mmap(mmap: memmap2::Mmap) -> (*const u8, PlatformData) {
+    let buffer = mmap.as_ptr();
+    let platform_data = PlatformData {
+        file: None,
+        offset: 0,
+        length: mmap.len(),
+        memory_backed: false,
+        mmap: Some(mmap),
+        heap: None,
+        #[cfg(target_os = "linux")]
+        queue_params: Mutex::new(None),
+    };
+    (buffer, platform_data)
+}
+
+/// Reads the first `length` bytes of `file` into a heap allocation, rather than mapping them.
+/// The file is kept open, so advice on the file descriptor still works. `length` must not be 0.
+pub(crate) fn read_file(file: fs::File, length: usize) -> io::Result<(*const u8, PlatformData)> {
+    use std::os::unix::fs::FileExt;
+
+    let mut heap = HeapPages::new(length, get_page_size());
+
+    // Read at an explicit offset, because the file position may be shared with a different
+    // descriptor, for example one that was duplicated by `clone_file`.
+    file.read_exact_at(heap.as_mut_slice(), 0)?;
+
+    Ok(adopt_heap(Some(file), heap))
+}
+
+/// Takes ownership of a heap allocation that holds the contents of `file`, as if it were mapped.
+/// Heap memory is resident, so the buffer counts as memory-backed.
+pub(crate) fn adopt_heap(file: Option<fs::File>, heap: HeapPages) -> (*const u8, PlatformData) {
+    let buffer = heap.as_ptr();
+    let platform_data = PlatformData {
+        file,
+        offset: 0,
+        length: heap.len(),
+        memory_backed: true,
+        #[cfg(feature = "memmap2")]
+        mmap: None,
+        heap: Some(heap),
+        #[cfg(target_os = "linux")]
+        queue_params: Mutex::new(None),
+    };
+    (buffer, platform_data)
+}
+
+/// Returns whether `file` is a stream that cannot be mapped, such as a pipe, a socket, or a
+/// terminal. Such files have no length, and they can only be read from start to end.
+pub fn is_stream(file: &fs::File) -> io::Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let file_type = file.metadata()?.file_type();
+    if file_type.is_fifo() || file_type.is_socket() {
+        return Ok(true);
+    }
+
+    // Some character devices, such as `/dev/zero`, can be mapped. Terminals cannot, and unlike
+    // those devices they cannot seek either.
+    if file_type.is_char_device() {
+        let result = unsafe { libc::lseek(file.as_raw_fd(), 0, libc::SEEK_CUR) };
+        let errno = io::Error::last_os_error().raw_os_error();
+        return Ok(result < 0 && errno == Some(libc::ESPIPE));
+    }
+
+    Ok(false)
+}
+
+/// Returns whether the buffer was read into a heap allocation by `read_file`, rather than mapped.
+pub(crate) fn is_heap_backed(platform_data: &PlatformData) -> bool {
+    platform_data.heap.is_some()
+}
+
+/// Leaks the memory that the platform data owns, if any, so it stays valid after the platform
+/// data is dropped. Mappings that `unmap_file` would unmap are not affected.
+pub(crate) fn leak_owned_memory(platform_data: &mut PlatformData) {
+    mem::forget(platform_data.heap.take());
+
+    #[cfg(feature = "memmap2")]
+    {
+        mem::forget(platform_data.mmap.take());
+    }
+}
+
+/// Unmaps the mapping of `length` bytes at `buffer`, which must be the length that was mapped.
+pub fn unmap_file(buffer: *const u8, length: usize, platform_data: &PlatformData) {
+    debug_assert_eq!(length, platform_data.length, "unmapped length differs from mapped length");
+
+    // A buffer that was read rather than mapped is freed when the platform data is dropped.
+    if platform_data.heap.is_some() { return; }
+
+    // The `Mmap` unmaps its mapping when the platform data is dropped.
+    #[cfg(feature = "memmap2")]
+    {
+        if platform_data.mmap.is_some() { return; }
+    }
+
     let result = unsafe { libc::munmap(buffer as *mut libc::c_void, length) };
 
     // `munmap` only fails due to incorrect usage, which is a program error, not a runtime failure.
@@ -53,37 +743,603 @@ pub fn unmap_file(buffer: *const u8, length: usize) {
 /// Writes whether the pages in the range starting at `buffer` with a length of `length` bytes
 /// are resident in physical memory into `residency`. The size of `residency` must be at least
 /// `length / page_size`. Both `buffer` and `length` must be a multiple of the page size.
+///
+/// Where the kernel does not implement `mincore`, all pages are reported as resident, like on
+/// Windows. Pages that are not mapped at all are reported as not resident, and so are all pages
+/// after them.
+#[cfg(not(feature = "no-residency"))]
 pub fn get_resident(buffer: *const u8, length: usize, residency: &mut [bool]) {
+    if query_resident(buffer, length, residency) {
+        return;
+    }
+
+    // Part of the range is not mapped. A buffer should never be partially unmapped, but some
+    // kernels report pages past the end of a file that was truncated after it was mapped this
+    // way. Residency is only a hint, so rather than failing, find the first page that is not
+    // mapped by querying the pages one by one, and report it and everything after it as not
+    // resident.
+    let page_size = get_page_size();
+    let mut mapped = true;
+    for (i, is_resident) in residency.iter_mut().take(length / page_size).enumerate() {
+        let mut page = [false];
+        let page_start = unsafe { buffer.add(i * page_size) };
+        mapped = mapped && query_resident(page_start, page_size, &mut page);
+        *is_resident = mapped && page[0];
+    }
+}
+
+/// Queries residency with `mincore` like `get_resident`, but returns false rather than filling
+/// `residency` if part of the range is not mapped.
+#[cfg(not(feature = "no-residency"))]
+fn query_resident(buffer: *const u8, length: usize, residency: &mut [bool]) -> bool {
     use std::thread;
 
-    let result = unsafe {
-        // Note: the libc on BSD descendants uses a signed char for residency_char while
-        // glibc uses an unsigned one, which is why we use an type-inferred cast here.
-        let residency_char = residency.as_mut_ptr() as *mut _;
-        assert_eq!(1, mem::size_of_val(&*residency_char));
-        libc::mincore(buffer as *mut libc::c_void, length, residency_char)
-    };
+    loop {
+        let result = unsafe {
+            // Note: the libc on BSD descendants uses a signed char for residency_char while
+            // glibc uses an unsigned one, which is why we use an type-inferred cast here.
+            let residency_char = residency.as_mut_ptr() as *mut _;
+            assert_eq!(1, mem::size_of_val(&*residency_char));
+            libc::mincore(buffer as *mut libc::c_void, length, residency_char)
+        };
+
+        if result == 0 {
+            return true;
+        }
 
-    // Any error code except EAGAIN indicates a programming error.
-    assert!(result == libc::EAGAIN || result == 0);
+        let error = io::Error::last_os_error();
+        match error.raw_os_error() {
+            // In the rare occasion that the kernel is busy, yield so we don't spam the kernel
+            // with `mincore` calls, then try again.
+            Some(libc::EAGAIN) => thread::yield_now(),
+            // The call was interrupted by a signal, try again right away.
+            Some(libc::EINTR) => {}
+            // Part of the range is not mapped.
+            Some(libc::ENOMEM) => return false,
+            // Some kernels and sandboxes (such as gVisor, or seccomp filters on Android) do not
+            // implement `mincore`. Residency is only a hint, so degrade gracefully and pretend
+            // that everything is resident.
+            Some(libc::ENOSYS) | Some(libc::ENOTSUP) => {
+                for x in residency {
+                    *x = true;
+                }
+                return true;
+            }
+            // Any other error code indicates a programming error.
+            _ => panic!("mincore failed: {}", error),
+        }
+    }
+}
 
-    // In the rare occasion that the kernel is busy, yield so we don't spam the kernel with
-    // `mincore` calls, then try again.
-    if result == libc::EAGAIN {
-        thread::yield_now();
-        get_resident(buffer, length, residency)
+/// With the `no-residency` feature, `mincore` is not used, and all pages are reported as
+/// resident, like on Windows.
+#[cfg(feature = "no-residency")]
+pub fn get_resident(_buffer: *const u8, _length: usize, residency: &mut [bool]) {
+    for x in residency {
+        *x = true;
     }
 }
 
 /// Requests the kernel to make the specified range of bytes resident in physical memory. `buffer`
 /// must be page-aligned.
-pub fn prefetch(buffer: *const u8, length: usize) {
-    let result = unsafe {
-        libc::madvise(buffer as *mut libc::c_void, length, libc::MADV_WILLNEED)
+pub fn prefetch(buffer: *const u8, length: usize) -> io::Result<()> {
+    use std::thread;
+
+    // Retry a few times if the kernel is busy, but do not keep trying forever; the advice is not
+    // worth blocking for.
+    let mut attempts_left = 3;
+
+    loop {
+        let result = madvise(buffer, length, libc::MADV_WILLNEED);
+
+        if result == 0 {
+            return Ok(());
+        }
+
+        let error = io::Error::last_os_error();
+        match error.raw_os_error() {
+            // In the rare occasion that the kernel is busy, yield so we don't spam the kernel
+            // with `madvise` calls, then try again.
+            Some(libc::EAGAIN) if attempts_left > 1 => {
+                attempts_left -= 1;
+                thread::yield_now();
+            }
+            // These indicate memory pressure, or that paging in the range would exceed the
+            // resident set limit. They are runtime conditions, not programming errors.
+            Some(libc::EAGAIN) | Some(libc::ENOMEM) | Some(libc::EIO) => return Err(error),
+            // Any other error code, such as `EINVAL` or `EBADF`, indicates a programming error.
+            _ => panic!("madvise failed: {}", error),
+        }
+    }
+}
+
+/// Requests the kernel to read `length` bytes of the mapped file into the page cache with
+/// `readahead`, starting at `offset` relative to `buffer`, the start of the mapping. Where
+/// `readahead` is not available, and for mappings that have no file, this calls `prefetch` on the
+/// mapping instead. `offset` must be page-aligned.
+pub fn readahead(
+    buffer: *const u8,
+    platform_data: &PlatformData,
+    offset: usize,
+    length: usize,
+) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(ref file) = platform_data.file {
+            let file_offset = platform_data.offset + offset as u64;
+            let result = retry_on_eintr(-1, || unsafe {
+                libc::readahead(file.as_raw_fd(), file_offset as libc::off64_t, length)
+            });
+            return if result == 0 { Ok(()) } else { Err(io::Error::last_os_error()) };
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = platform_data;
+    }
+
+    prefetch(unsafe { buffer.add(offset) }, length)
+}
+
+/// Requests the kernel to make `length` bytes of the mapping resident, starting at `offset`
+/// relative to `buffer`, the start of the mapping. `offset` must be page-aligned.
+///
+/// This calls `prefetch` on the range. A single `MADV_WILLNEED` may not submit enough reads to
+/// keep the command queue of a fast device full, so on Linux, for large ranges of mapped files,
+/// this also submits `readahead` calls for the start of the range, one per readahead window of
+/// the device, up to one per request slot in the device queue. See `get_queue_params`. Buffers
+/// that were read into memory have nothing to read ahead, so for those this is just `prefetch`.
+pub fn prefetch_deep(
+    buffer: *const u8,
+    platform_data: &PlatformData,
+    offset: usize,
+    length: usize,
+) -> io::Result<()> {
+    // Pages of anonymous memory that were marked reusable with `MADV_FREE_REUSABLE` must be marked
+    // as in use again with `MADV_FREE_REUSE`, otherwise macOS keeps accounting them as reclaimable.
+    // This only affects accounting, so the result is ignored. See `advise_free`.
+    #[cfg(any(target_os = "ios", target_os = "macos"))]
+    {
+        if is_anonymous(platform_data) {
+            madvise(unsafe { buffer.add(offset) }, length, libc::MADV_FREE_REUSE);
+        }
+    }
+
+    let result = prefetch(unsafe { buffer.add(offset) }, length);
+
+    #[cfg(target_os = "linux")]
+    {
+        if platform_data.heap.is_none() && length >= DEEP_PREFETCH_MIN_LEN {
+            if let Some((segment_len, max_segments)) = get_cached_queue_params(platform_data) {
+                let mut segment_offset = offset;
+                let end = offset + length;
+                for _ in 0..max_segments {
+                    if segment_offset >= end { break; }
+                    let len = cmp::min(segment_len, end - segment_offset);
+                    // The reads are only advice on top of the `madvise` above, so errors are
+                    // ignored, and the first one stops submitting more.
+                    if readahead(buffer, platform_data, segment_offset, len).is_err() { break; }
+                    segment_offset += len;
+                }
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = platform_data;
+    }
+
+    result
+}
+
+/// Returns the queue parameters of the device that holds the file of `platform_data`, or `None` if
+/// it has no file. See `get_queue_params`. They are resolved on the first call, and cached after.
+#[cfg(target_os = "linux")]
+fn get_cached_queue_params(platform_data: &PlatformData) -> Option<(usize, usize)> {
+    let file = platform_data.file.as_ref()?;
+    let mut params = platform_data.queue_params.lock().unwrap_or_else(|err| err.into_inner());
+    Some(*params.get_or_insert_with(|| get_queue_params(file)))
+}
+
+/// Returns the readahead window in bytes of the device that holds the mapped file, or `None` where
+/// it is not known, which is everywhere but on Linux. Buffers that were read into memory are not
+/// read ahead, so for those this returns `None` too.
+pub fn get_readahead_len(platform_data: &PlatformData) -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        if platform_data.heap.is_some() { return None; }
+        get_cached_queue_params(platform_data).map(|(window, _)| window)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = platform_data;
+        None
+    }
+}
+
+/// Returns the readahead window in bytes, and the number of request slots, of the block device
+/// that holds `file`, as reported by `read_ahead_kb` and `nr_requests` in `/sys`. For files on a
+/// partition, these are the parameters of the disk. Where they cannot be determined, for example
+/// for files on network or virtual filesystems, this returns the kernel defaults: a window of
+/// 128 KiB, and 64 requests.
+#[cfg(target_os = "linux")]
+fn get_queue_params(file: &fs::File) -> (usize, usize) {
+    use std::os::unix::fs::MetadataExt;
+
+    fn read_sysfs_usize(path: &str) -> Option<usize> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    let (major, minor) = match file.metadata() {
+        Ok(metadata) => split_dev(metadata.dev()),
+        Err(..) => (0, 0),
+    };
+
+    // Virtual filesystems, such as tmpfs, have device number 0:N, which is no block device.
+    let read_param = |name: &str| -> Option<usize> {
+        if major == 0 { return None; }
+        let device = format!("/sys/dev/block/{}:{}", major, minor);
+        read_sysfs_usize(&format!("{}/queue/{}", device, name))
+            .or_else(|| read_sysfs_usize(&format!("{}/../queue/{}", device, name)))
     };
 
-    // Any returned error code indicates a programming error, not a runtime error.
-    assert_eq!(0, result);
+    let read_ahead_kb = read_param("read_ahead_kb").filter(|&kb| kb > 0).unwrap_or(128);
+    let nr_requests = read_param("nr_requests").filter(|&n| n > 0).unwrap_or(64);
+    (read_ahead_kb * 1024, nr_requests)
+}
+
+/// Splits a device number into its major and minor number, like the `major` and `minor` macros
+/// of glibc do.
+#[cfg(target_os = "linux")]
+fn split_dev(dev: u64) -> (u64, u64) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    (major, minor)
+}
+
+/// Requests the kernel to make the specified ranges resident in physical memory. Every range is
+/// an `(offset, length)` pair relative to `buffer`. Both `buffer` and the offsets must be
+/// page-aligned.
+pub fn prefetch_ranges(buffer: *const u8, ranges: &[(usize, usize)]) -> io::Result<()> {
+    // There is no vectored `madvise`, so advise one range at a time. Keep going when one range
+    // fails; the advice for the other ranges might still be taken.
+    let mut result = Ok(());
+    for &(offset, length) in ranges {
+        let range_result = prefetch(unsafe { buffer.add(offset) }, length);
+        if result.is_ok() {
+            result = range_result;
+        }
+    }
+    result
+}
+
+/// Returns whether the platform data belongs to anonymous memory from `map_anonymous`, rather than
+/// to a mapping of a file or to a heap allocation.
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+fn is_anonymous(platform_data: &PlatformData) -> bool {
+    #[cfg(feature = "memmap2")]
+    {
+        if platform_data.mmap.is_some() { return false; }
+    }
+    platform_data.file.is_none() && platform_data.heap.is_none()
+}
+
+/// Tells the kernel that the pages in the specified range are no longer needed. Where supported,
+/// this uses `MADV_FREE`, which lets the kernel reclaim the pages lazily. Otherwise it uses
+/// `MADV_DONTNEED`, which drops them immediately. `buffer` must be page-aligned.
+pub fn advise_free(
+    buffer: *const u8,
+    platform_data: &PlatformData,
+    length: usize,
+) -> io::Result<()> {
+    // On macOS, `MADV_FREE_REUSABLE` is preferable to `MADV_FREE` for anonymous memory: it also
+    // marks the pages as reusable, so they no longer count towards the footprint of the process in
+    // the memory pressure system and in Activity Monitor. It does not apply to mappings of a file,
+    // so for those it is not tried at all.
+    #[cfg(any(target_os = "ios", target_os = "macos"))]
+    {
+        if is_anonymous(platform_data) {
+            let result = madvise(buffer, length, libc::MADV_FREE_REUSABLE);
+
+            if result == 0 {
+                return Ok(());
+            }
+
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::EINVAL) {
+                return Err(error);
+            }
+        }
+    }
+
+    #[cfg(not(any(target_os = "ios", target_os = "macos")))]
+    {
+        let _ = platform_data;
+    }
+
+    #[cfg(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd",
+    ))]
+    {
+        let result = madvise(buffer, length, libc::MADV_FREE);
+
+        if result == 0 {
+            return Ok(());
+        }
+
+        // Linux rejects `MADV_FREE` with `EINVAL` for file-backed mappings, and kernels before 4.5
+        // do not know it at all. In those cases, fall back to `MADV_DONTNEED`.
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EINVAL) {
+            return Err(error);
+        }
+    }
+
+    let result = madvise(buffer, length, libc::MADV_DONTNEED);
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Tells the kernel that the pages in the specified range are unlikely to be accessed soon, so
+/// they should be reclaimed before other pages. This uses `MADV_COLD` on Linux 5.4 and later, and
+/// does nothing elsewhere. `buffer` must be page-aligned.
+pub fn advise_cold(buffer: *const u8, length: usize) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let result = madvise(buffer, length, MADV_COLD);
+
+        // Kernels before 5.4 do not know `MADV_COLD` and fail with `EINVAL`. The advice is only
+        // a hint, so ignore it there.
+        if result != 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::EINVAL) {
+                return Err(error);
+            }
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = (buffer, length);
+    }
+
+    Ok(())
+}
+
+/// Tells the kernel to reclaim the pages in the specified range right away. This uses
+/// `MADV_PAGEOUT` on Linux 5.4 and later. Elsewhere, it falls back to `MADV_DONTNEED`. `buffer`
+/// must be page-aligned.
+pub fn advise_pageout(buffer: *const u8, length: usize) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let result = madvise(buffer, length, MADV_PAGEOUT);
+
+        if result == 0 {
+            return Ok(());
+        }
+
+        // Kernels before 5.4 do not know `MADV_PAGEOUT` and fail with `EINVAL`.
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EINVAL) {
+            return Err(error);
+        }
+    }
+
+    let result = madvise(buffer, length, libc::MADV_DONTNEED);
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Applies the access pattern `advice` to the specified range. `buffer` must be page-aligned.
+pub fn advise(
+    buffer: *const u8,
+    platform_data: &PlatformData,
+    length: usize,
+    advice: Advice,
+) -> io::Result<()> {
+    let flag = match advice {
+        Advice::Normal => libc::MADV_NORMAL,
+        Advice::Sequential => libc::MADV_SEQUENTIAL,
+        Advice::Random => libc::MADV_RANDOM,
+        Advice::WillNeed => libc::MADV_WILLNEED,
+        Advice::DontNeed => libc::MADV_DONTNEED,
+        Advice::Cold => return advise_cold(buffer, length),
+        Advice::PageOut => return advise_pageout(buffer, length),
+        Advice::Free => return advise_free(buffer, platform_data, length),
+    };
+
+    let result = madvise(buffer, length, flag);
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Returns the ranges of the mapping that are backed by data in the file, rather than by holes,
+/// relative to the start of the mapping. The file is walked with `lseek` with `SEEK_DATA` and
+/// `SEEK_HOLE`. On platforms that lack these, the entire mapping is reported as data.
+pub fn get_data_ranges(platform_data: &PlatformData) -> io::Result<Vec<Range<usize>>> {
+    let length = platform_data.length;
+
+    // Without information about holes, the entire mapping is data.
+    let all_data = || std::iter::once(0..length).filter(|r| !r.is_empty()).collect();
+
+    #[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+    {
+        let file = match platform_data.file {
+            Some(ref file) => file,
+            None => return Ok(all_data()),
+        };
+
+        let start = platform_data.offset;
+        let end = start + length as u64;
+        let mut ranges = Vec::new();
+        let mut position = start;
+
+        // The data and holes alternate, so seek to the next data, then to the hole after it,
+        // until the end of the mapping. Seeking past the last data fails with `ENXIO`.
+        while position < end {
+            let data = unsafe {
+                libc::lseek(file.as_raw_fd(), position as libc::off_t, libc::SEEK_DATA)
+            };
+            if data < 0 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() == Some(libc::ENXIO) { break; }
+                return Err(err);
+            }
+            if data as u64 >= end { break; }
+
+            let hole = unsafe { libc::lseek(file.as_raw_fd(), data, libc::SEEK_HOLE) };
+            if hole < 0 { return Err(io::Error::last_os_error()); }
+
+            // There is an implicit hole at the end of the file, so `hole` is always past `data`.
+            let hole = std::cmp::min(hole as u64, end);
+            ranges.push((data as u64 - start) as usize..(hole - start) as usize);
+            position = hole;
+        }
+
+        Ok(ranges)
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "freebsd", target_os = "linux")))]
+    {
+        Ok(all_data())
+    }
+}
+
+/// Applies the access pattern `advice` to `length` bytes of the mapped file, starting at `offset`
+/// relative to the start of the mapping. This advises on the file with `posix_fadvise`, rather
+/// than on the mapping. On platforms that lack `posix_fadvise`, this is a no-op.
+pub fn advise_file(
+    platform_data: &PlatformData,
+    offset: usize,
+    length: usize,
+    advice: Advice,
+) -> io::Result<()> {
+    match platform_data.file {
+        Some(ref file) => advise_fd(file, platform_data.offset + offset as u64, length, advice),
+        None => Ok(()),
+    }
+}
+
+/// Applies the access pattern `advice` to `length` bytes of `file`, starting at `offset`, with
+/// `posix_fadvise`. A length of 0 extends to the end of the file. Unlike `advise_file`, this does
+/// not need a mapping, so it can be called before the file is mapped. On platforms that lack
+/// `posix_fadvise`, this is a no-op.
+pub fn advise_fd(file: &fs::File, offset: u64, length: usize, advice: Advice) -> io::Result<()> {
+    #[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+    {
+        let flag = match advice {
+            Advice::Normal => libc::POSIX_FADV_NORMAL,
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::Random => libc::POSIX_FADV_RANDOM,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+            // There is no file equivalent of `MADV_COLD`, and dropping the pages from the page
+            // cache is the closest to reclaiming or freeing them.
+            Advice::Cold => return Ok(()),
+            Advice::PageOut | Advice::Free => libc::POSIX_FADV_DONTNEED,
+        };
+
+        // Unlike most calls, `posix_fadvise` returns the error number rather than setting `errno`,
+        // so we cannot use `retry_on_eintr` here.
+        let result = loop {
+            let result = unsafe {
+                libc::posix_fadvise(
+                    file.as_raw_fd(),
+                    offset as libc::off_t,
+                    length as libc::off_t,
+                    flag
+                )
+            };
+            if result != libc::EINTR { break result; }
+        };
+
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(result))
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "freebsd", target_os = "linux")))]
+    {
+        let _ = (file, offset, length, advice);
+        Ok(())
+    }
+}
+
+/// Returns whether the mapping that contains `address` is backed by transparent huge pages, as
+/// reported by `/proc/self/smaps`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn is_huge_page_backed(address: usize) -> io::Result<bool> {
+    let smaps = fs::read_to_string("/proc/self/smaps")?;
+    match parse_smaps_huge_page_backed(&smaps, address) {
+        Some(is_backed) => Ok(is_backed),
+        None => {
+            let msg = "mapping not found in /proc/self/smaps";
+            Err(io::Error::new(io::ErrorKind::NotFound, msg))
+        }
+    }
+}
+
+/// Finds the mapping that contains `address` in the contents of an `smaps` file, and returns
+/// whether it has any huge pages, anonymous or file-backed. Returns `None` if no mapping contains
+/// the address.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_smaps_huge_page_backed(smaps: &str, address: usize) -> Option<bool> {
+    let mut in_mapping = false;
+    let mut found = false;
+    let mut huge_kb = 0;
+
+    for line in smaps.lines() {
+        // A mapping starts with a header line such as `7f0c4a000000-7f0c4a021000 r--p ...`. The
+        // lines that follow it, until the next header, have the form `Name:  value kB`.
+        let first_field = line.split_whitespace().next().unwrap_or("");
+        let range = first_field.find('-').and_then(|i| {
+            let start = usize::from_str_radix(&first_field[..i], 16).ok()?;
+            let end = usize::from_str_radix(&first_field[i + 1..], 16).ok()?;
+            Some((start, end))
+        });
+
+        match range {
+            Some((start, end)) => {
+                if found { break; }
+                in_mapping = start <= address && address < end;
+                found = in_mapping;
+            }
+            None if in_mapping => {
+                let mut fields = line.split_whitespace();
+                let name = fields.next();
+                if name == Some("AnonHugePages:") || name == Some("FilePmdMapped:") {
+                    huge_kb += fields.next().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+                }
+            }
+            None => {}
+        }
+    }
+
+    if found { Some(huge_kb > 0) } else { None }
 }
 
 pub fn get_page_size() -> usize {
@@ -95,3 +1351,186 @@ pub fn get_page_size() -> usize {
 
     page_size
 }
+
+/// Returns the number of processors that are online, or 1 if that cannot be determined.
+pub fn get_cpu_count() -> usize {
+    let count = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if count < 1 { 1 } else { count as usize }
+}
+
+/// Returns the number of bytes of physical memory that are available without swapping, or `None`
+/// if that cannot be determined. On Linux and Android this is `MemAvailable` from `/proc/meminfo`,
+/// which includes the page cache that can be reclaimed. Elsewhere it is `None`.
+pub fn get_available_memory() -> Option<u64> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+        let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+        let kib = line["MemAvailable:".len()..].trim().trim_end_matches("kB").trim();
+        kib.parse::<u64>().ok().map(|kib| kib * 1024)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        None
+    }
+}
+
+/// Returns the alignment that the offset passed to `map_file` must have. On Unix-ish platforms
+/// this is the page size.
+pub fn get_allocation_granularity() -> usize {
+    get_page_size()
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn mmap_flags_are_passed_to_mmap() {
+    let fbuffer = OpenOptions::new().mmap_flags(libc::MAP_POPULATE).open("src/lib.rs").unwrap();
+    assert_eq!(&fbuffer[3..13], &b"Filebuffer"[..]);
+
+    // `MAP_HUGETLB` is only valid for anonymous mappings and files on hugetlbfs.
+    assert!(OpenOptions::new().mmap_flags(libc::MAP_HUGETLB).open("src/lib.rs").is_err());
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn parse_smaps_finds_huge_pages_of_mapping() {
+    let smaps = "\
+        7f0000000000-7f0000200000 r--p 00000000 08:01 42 /tmp/huge\n\
+        Size:               2048 kB\n\
+        AnonHugePages:         0 kB\n\
+        FilePmdMapped:      2048 kB\n\
+        7f0000200000-7f0000201000 r--p 00000000 08:01 43 /tmp/small\n\
+        Size:                  4 kB\n\
+        AnonHugePages:         0 kB\n\
+        FilePmdMapped:         0 kB\n\
+        VmFlags: rd mr mw me\n";
+
+    assert_eq!(parse_smaps_huge_page_backed(smaps, 0x7f0000000000), Some(true));
+    assert_eq!(parse_smaps_huge_page_backed(smaps, 0x7f00001fffff), Some(true));
+    assert_eq!(parse_smaps_huge_page_backed(smaps, 0x7f0000200000), Some(false));
+    assert_eq!(parse_smaps_huge_page_backed(smaps, 0x7f0000201000), None);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn queue_params_are_resolved_once() {
+    // An empty range is not mapped, but the platform data still holds the file.
+    let file = fs::File::open("src/lib.rs").unwrap();
+    let (_, platform_data) = map_file(file, 0, 0, &OpenOptions::new()).unwrap();
+    assert!(platform_data.queue_params.lock().unwrap().is_none());
+
+    let params = get_cached_queue_params(&platform_data).unwrap();
+    assert_eq!(*platform_data.queue_params.lock().unwrap(), Some(params));
+    assert_eq!(get_cached_queue_params(&platform_data), Some(params));
+
+    let (_, anonymous) = map_anonymous(0).unwrap();
+    assert_eq!(get_cached_queue_params(&anonymous), None);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn split_dev_splits_device_numbers() {
+    // These are `makedev(8, 1)`, `makedev(259, 1)`, and `makedev(8, 0x1234)`.
+    assert_eq!(split_dev(0x0801), (8, 1));
+    assert_eq!(split_dev(0x0001_0301), (259, 1));
+    assert_eq!(split_dev(0x0120_0834), (8, 0x1234));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn get_queue_params_falls_back_to_defaults() {
+    let (read_ahead, nr_requests) = get_queue_params(&fs::File::open("src/lib.rs").unwrap());
+    assert!(read_ahead > 0);
+    assert!(nr_requests > 0);
+
+    // Files in `/proc` do not live on a block device.
+    let (read_ahead, nr_requests) = get_queue_params(&fs::File::open("/proc/self/stat").unwrap());
+    assert_eq!((read_ahead, nr_requests), (128 * 1024, 64));
+}
+
+#[test]
+fn get_file_len_rejects_character_devices() {
+    let file = fs::File::open("/dev/null").unwrap();
+    assert_eq!(get_file_len(&file).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    let file = fs::File::open("src/lib.rs").unwrap();
+    assert_eq!(get_file_len(&file).unwrap(), file.metadata().unwrap().len());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn retry_on_eintr_retries_only_interrupted_calls() {
+    // Fake the failures by setting `errno` directly; raising real signals in a test is flaky.
+    let mut calls = 0;
+    let result = retry_on_eintr(-1, || {
+        calls += 1;
+        let errno = if calls < 3 { libc::EINTR } else { libc::EBADF };
+        unsafe { *libc::__errno_location() = errno; }
+        -1
+    });
+    assert_eq!((result, calls), (-1, 3));
+
+    let mut calls = 0;
+    assert_eq!(retry_on_eintr(-1, || { calls += 1; 0 }), 0);
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn map_file_rejects_lengths_beyond_isize_max() {
+    // The check comes before `mmap`, so the length need not fit in the file or address space.
+    let file = fs::File::open("src/lib.rs").unwrap();
+    let too_long = isize::MAX as usize + 1;
+    let err = map_file(file, 0, too_long, &OpenOptions::new()).unwrap_err();
+    assert_eq!(err.to_string(), "file is larger than address space");
+}
+
+// This test lives here rather than in `lib.rs`, because `FileBuffer` always aligns the range before
+// it calls `prefetch`, so an unaligned address cannot be passed through the public interface.
+#[test]
+fn prefetch_unaligned_is_a_programming_error() {
+    use std::panic;
+
+    let page_size = get_page_size();
+    let (buffer, platform_data) = map_anonymous(page_size * 2).unwrap();
+
+    // `madvise` requires a page-aligned address and fails with `EINVAL` otherwise.
+    let result = panic::catch_unwind(|| prefetch(unsafe { buffer.add(1) }, page_size));
+    unmap_file(buffer, page_size * 2, &platform_data);
+    assert!(result.is_err());
+}
+
+#[cfg(not(feature = "no-residency"))]
+#[test]
+fn get_resident_reports_unmapped_pages_as_not_resident() {
+    let page_size = get_page_size();
+    let buffer = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            page_size * 4,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    assert_ne!(buffer, libc::MAP_FAILED);
+    let buffer = buffer as *mut u8;
+
+    // Touch every page so they are resident, then punch a hole in the range.
+    unsafe {
+        for i in 0..4 {
+            *buffer.add(i * page_size) = 1;
+        }
+        assert_eq!(libc::munmap(buffer.add(page_size * 2) as *mut libc::c_void, page_size), 0);
+    }
+
+    let mut residency = [false; 4];
+    get_resident(buffer, page_size * 4, &mut residency);
+    assert_eq!(residency, [true, true, false, false]);
+
+    unsafe {
+        libc::munmap(buffer as *mut libc::c_void, page_size * 2);
+        libc::munmap(buffer.add(page_size * 3) as *mut libc::c_void, page_size);
+    }
+}
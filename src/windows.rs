@@ -7,88 +7,592 @@
 
 //! This mod contains the platform-specific implementations for Windows based on the winapi crate.
 
+use std::cmp;
+use std::ffi::OsStr;
 use std::fs;
 use std::io;
+use std::iter;
 use std::mem;
-use std::os::windows::io::AsRawHandle;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::path::Path;
 use std::ptr;
 
+use super::{Advice, HeapPages, OpenOptions, WritablePolicy};
+
 extern crate winapi;
 
 #[derive(Debug)]
 pub struct PlatformData {
-    // On Windows, the file must be kept open for the lifetime of the mapping.
-    #[allow(dead_code)] // The field is not dead, the destructor is effectful.
-    file: fs::File,
+    // On Windows, the file must be kept open for the lifetime of the mapping. Anonymous mappings
+    // are not backed by a file.
+    file: Option<fs::File>,
+    // The offset into the file at which the mapping starts.
+    offset: u64,
     mapping_handle: winapi::um::winnt::HANDLE,
+    // `UnmapViewOfFile` does not need the length, but `unmap_file` checks that it unmaps what
+    // was mapped, to catch length bugs that would be fatal on Unix-ish platforms.
+    length: usize,
+    // A mapping that was created by `memmap2` is owned by its `Mmap`, and it is unmapped when
+    // that is dropped, rather than by `unmap_file`.
+    #[cfg(feature = "memmap2")]
+    mmap: Option<memmap2::Mmap>,
+    // The contents of a small file that was read into memory rather than mapped, see `read_file`.
+    heap: Option<HeapPages>,
 }
 
 impl Drop for PlatformData {
     fn drop (&mut self) {
-        if self.mapping_handle != ptr::null_mut() {
+        if !self.mapping_handle.is_null() {
             let success = unsafe { winapi::um::handleapi::CloseHandle(self.mapping_handle) };
             assert!(success != 0);
         }
     }
 }
 
-pub fn map_file(file: fs::File) -> io::Result<(*const u8, usize, PlatformData)> {
+/// See also `unix::get_raw_fd`.
+pub fn get_raw_handle(platform_data: &PlatformData) -> Option<RawHandle> {
+    platform_data.file.as_ref().map(|file| file.as_raw_handle())
+}
+
+/// See also `unix::clone_file`.
+pub fn clone_file(platform_data: &PlatformData) -> io::Result<fs::File> {
+    match platform_data.file {
+        Some(ref file) => file.try_clone(),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is not backed by a file")),
+    }
+}
+
+/// See also `unix::open_path`.
+pub fn open_path(path: &Path, options: &OpenOptions) -> io::Result<fs::File> {
+    trace_event!(DEBUG, path = %path.display(), "opening file");
+    let mut open_opts = fs::OpenOptions::new();
+    open_opts.read(true);
+
+    // TODO: Set `share_mode()` to read-only. This requires the
+    // `open_options_ext` feature that is currently unstable, but it is
+    // required to ensure that a different process does not suddenly modify
+    // the contents of the file. See also Rust issue 27720.
+
+    if !options.follow_symlinks {
+        open_opts.custom_flags(winapi::um::winbase::FILE_FLAG_OPEN_REPARSE_POINT);
+    }
+
+    // Changing the attributes through the handle requires access to write them.
+    if options.writable_policy == WritablePolicy::MakeReadonly {
+        open_opts.access_mode(
+            winapi::um::winnt::GENERIC_READ | winapi::um::winnt::FILE_WRITE_ATTRIBUTES
+        );
+    }
+
+    let file = open_opts.open(path)?;
+    if options.lock_shared { lock_shared(&file)?; }
+    apply_writable_policy(&file, options.writable_policy)?;
+    Ok(file)
+}
+
+/// See also `unix::apply_writable_policy`. Windows has no mode bits, so this checks or sets the
+/// `FILE_ATTRIBUTE_READONLY` attribute instead.
+fn apply_writable_policy(file: &fs::File, policy: WritablePolicy) -> io::Result<()> {
+    if policy == WritablePolicy::Allow { return Ok(()); }
+
+    let mut permissions = file.metadata()?.permissions();
+
+    match policy {
+        WritablePolicy::Refuse if !permissions.readonly() => {
+            let msg = "file is not read-only";
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, msg))
+        }
+        WritablePolicy::MakeReadonly if !permissions.readonly() => {
+            permissions.set_readonly(true);
+            file.set_permissions(permissions)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// See also `unix::lock_shared`. The lock covers the entire file, and it is released when the
+/// handle is closed.
+fn lock_shared(file: &fs::File) -> io::Result<()> {
+    let mut overlapped: winapi::um::minwinbase::OVERLAPPED = unsafe { mem::zeroed() };
+    let success = unsafe {
+        winapi::um::fileapi::LockFileEx(
+            file.as_raw_handle() as *mut winapi::ctypes::c_void,
+            0,                                             // Shared, and wait for the lock.
+            0,                                             // Reserved.
+            winapi::shared::minwindef::DWORD::MAX,         // Low 32 bits of the range length.
+            winapi::shared::minwindef::DWORD::MAX,         // High 32 bits of the range length.
+            &mut overlapped                                // The range starts at offset 0.
+        )
+    };
+
+    if success == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// See also `unix::get_file_len`.
+pub fn get_file_len(file: &fs::File) -> io::Result<u64> {
+    let metadata = file.metadata()?;
+
+    // Opening a directory already fails unless special flags are passed, but if a directory
+    // handle does make it here, reject it with a clear error.
+    if metadata.is_dir() {
+        let msg = "cannot memory-map a directory";
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+
+    Ok(metadata.len())
+}
+
+/// See also `unix::get_backed_len`.
+pub fn get_backed_len(platform_data: &PlatformData) -> io::Result<usize> {
+    // Windows refuses to truncate a file while a view of it is mapped, so the entire mapping is
+    // always backed by the file.
+    Ok(platform_data.length)
+}
+
+/// See also `unix::get_current_file_len`.
+pub fn get_current_file_len(platform_data: &PlatformData) -> io::Result<Option<u64>> {
+    match platform_data.file {
+        Some(ref file) => Ok(Some(file.metadata()?.len())),
+        None => Ok(None),
+    }
+}
+
+/// See also `unix::read_file_range`.
+pub fn read_file_range(
+    platform_data: &PlatformData,
+    offset: usize,
+    length: usize,
+) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    let file = match platform_data.file {
+        Some(ref file) => file,
+        None => return Ok(()),
+    };
+
+    let mut buf = [0u8; 64 * 1024];
+    let mut file_offset = platform_data.offset + offset as u64;
+    let mut remaining = length;
+    while remaining > 0 {
+        let n = cmp::min(remaining, buf.len());
+        match file.seek_read(&mut buf[..n], file_offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file was truncated")),
+            Ok(n_read) => {
+                file_offset += n_read as u64;
+                remaining -= n_read;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// See also `unix::get_fault_counts`. Windows does not distinguish between major and minor faults
+/// in its process counters, so this returns `None`.
+pub fn get_fault_counts() -> Option<(u64, u64)> {
+    None
+}
+
+/// See also `unix::get_file_id`. On Windows, the volume serial number and file index identify the
+/// file.
+pub fn get_file_id(file: &fs::File) -> io::Result<(u64, u64)> {
+    let mut info: winapi::um::fileapi::BY_HANDLE_FILE_INFORMATION = unsafe { mem::zeroed() };
+    let success = unsafe {
+        winapi::um::fileapi::GetFileInformationByHandle(
+            file.as_raw_handle() as *mut winapi::ctypes::c_void,
+            &mut info
+        )
+    };
+
+    if success == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let file_index = ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64;
+    Ok((info.dwVolumeSerialNumber as u64, file_index))
+}
+
+/// See also `unix::map_file`.
+pub fn map_file(
+    file: fs::File,
+    offset: u64,
+    length: usize,
+    options: &OpenOptions,
+) -> io::Result<(*const u8, PlatformData)> {
     let file_handle = file.as_raw_handle();
-    let length = file.metadata()?.len();
 
-    if length > usize::max_value() as u64 {
+    // A named mapping needs a nul-terminated UTF-16 name. Without a name, pass a null pointer.
+    let name = match options.mapping_name {
+        Some(ref name) if name.contains('\0') => {
+            let msg = "mapping name must not contain a nul byte";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        Some(ref name) => OsStr::new(name).encode_wide().chain(iter::once(0)).collect(),
+        None => Vec::new(),
+    };
+    let name_ptr = if name.is_empty() { ptr::null() } else { name.as_ptr() };
+
+    let mut platform_data = PlatformData {
+        file: Some(file),
+        offset,
+        mapping_handle: ptr::null_mut(),
+        length,
+        #[cfg(feature = "memmap2")]
+        mmap: None,
+        heap: None,
+    };
+
+    // Don't try to map anything if the range is empty.
+    if length == 0 {
+        return Ok((ptr::null(), platform_data));
+    }
+
+    // A slice can be at most `isize::MAX` bytes long, a larger mapping could not be dereferenced.
+    if length > isize::MAX as usize {
         return Err(io::Error::new(io::ErrorKind::Other, "file is larger than address space"));
     }
 
+    // Memory-mapping a file on Windows is a two-step process: first we create a file mapping
+    // object, and then we create a view of that mapping in the virtual address space.
+    let create_mapping = |protection| unsafe {
+        winapi::um::memoryapi::CreateFileMappingW(
+            file_handle as *mut winapi::ctypes::c_void,
+            ptr::null_mut(), // Use default security policy.
+            protection,      // The memory will be read-only.
+            0, 0,            // The mapping size is the size of the file.
+            name_ptr         // Create or open a named mapping, if any.
+        )
+    };
+
+    // Large pages need a privilege that few processes hold, and they are not supported for every
+    // kind of mapping. If creating the mapping with large pages fails, try again without.
+    let mut view_access = winapi::um::memoryapi::FILE_MAP_READ;
+    if options.large_pages {
+        let protection = winapi::um::winnt::PAGE_READONLY | winapi::um::winnt::SEC_LARGE_PAGES;
+        platform_data.mapping_handle = create_mapping(protection);
+        if !platform_data.mapping_handle.is_null() {
+            view_access |= winapi::um::memoryapi::FILE_MAP_LARGE_PAGES;
+        }
+    }
+
+    if platform_data.mapping_handle.is_null() {
+        platform_data.mapping_handle = create_mapping(winapi::um::winnt::PAGE_READONLY);
+    }
+
+    if platform_data.mapping_handle.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    let result = unsafe {
+        winapi::um::memoryapi::MapViewOfFile(
+            platform_data.mapping_handle,
+            view_access,                              // The memory mapping will be read-only.
+            (offset >> 32) as winapi::shared::minwindef::DWORD, // High 32 bits of the offset.
+            offset as winapi::shared::minwindef::DWORD,         // Low 32 bits of the offset.
+            length as winapi::shared::basetsd::SIZE_T // Map only the requested range.
+        )
+    };
+
+    if result.is_null() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok((result as *const u8, platform_data))
+    }
+}
+
+/// See also `unix::map_file_cow`.
+pub fn map_file_cow(file: fs::File, length: usize) -> io::Result<(*mut u8, PlatformData)> {
+    let file_handle = file.as_raw_handle();
+
     let mut platform_data = PlatformData {
-        file: file,
+        file: Some(file),
+        offset: 0,
         mapping_handle: ptr::null_mut(),
+        length,
+        #[cfg(feature = "memmap2")]
+        mmap: None,
+        heap: None,
     };
 
     // Don't try to map anything if the file is empty.
     if length == 0 {
-        return Ok((ptr::null(), 0, platform_data));
+        return Ok((ptr::null_mut(), platform_data));
     }
 
-    // Memory-mapping a file on Windows is a two-step process: first we create a file mapping
-    // object, and then we create a view of that mapping in the virtual address space.
     platform_data.mapping_handle = unsafe {
         winapi::um::memoryapi::CreateFileMappingW(
             file_handle as *mut winapi::ctypes::c_void,
-            ptr::null_mut(),                  // Use default security policy.
-            winapi::um::winnt::PAGE_READONLY, // The memory will be read-only.
-            0, 0,                             // The mapping size is the size of the file.
-            ptr::null_mut()                   // The mapping does not have a name.
+            ptr::null_mut(),                   // Use default security policy.
+            winapi::um::winnt::PAGE_WRITECOPY, // Writes go to private copies of the pages.
+            0, 0,                              // The mapping size is the size of the file.
+            ptr::null_mut()                    // The mapping does not have a name.
         )
     };
 
-    if platform_data.mapping_handle == ptr::null_mut() {
+    if platform_data.mapping_handle.is_null() {
         return Err(io::Error::last_os_error());
     }
 
     let result = unsafe {
         winapi::um::memoryapi::MapViewOfFile(
             platform_data.mapping_handle,
-            winapi::um::memoryapi::FILE_MAP_READ,     // The memory mapping will be read-only.
+            winapi::um::memoryapi::FILE_MAP_COPY,     // The memory mapping will be copy-on-write.
             0, 0,                                     // Start offset of the mapping is 0.
             length as winapi::shared::basetsd::SIZE_T // Map the entire file.
         )
     };
 
-    if result == ptr::null_mut() {
+    if result.is_null() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok((result as *mut u8, platform_data))
+    }
+}
+
+/// See also `unix::map_anonymous`.
+pub fn map_anonymous(length: usize) -> io::Result<(*mut u8, PlatformData)> {
+    let mut platform_data = PlatformData {
+        file: None,
+        offset: 0,
+        mapping_handle: ptr::null_mut(),
+        length,
+        #[cfg(feature = "memmap2")]
+        mmap: None,
+        heap: None,
+    };
+
+    // Don't try to map anything if the length is zero, Windows rejects empty mappings.
+    if length == 0 {
+        return Ok((ptr::null_mut(), platform_data));
+    }
+
+    // A file mapping object that is not associated with a file is backed by the paging file.
+    // Unlike for a file, the size of the mapping must be specified explicitly.
+    let length_u64 = length as u64;
+    platform_data.mapping_handle = unsafe {
+        winapi::um::memoryapi::CreateFileMappingW(
+            winapi::um::handleapi::INVALID_HANDLE_VALUE, // Back the mapping by the paging file.
+            ptr::null_mut(),                             // Use default security policy.
+            winapi::um::winnt::PAGE_READWRITE,           // The memory will be writable.
+            (length_u64 >> 32) as winapi::shared::minwindef::DWORD, // High 32 bits of the size.
+            length_u64 as winapi::shared::minwindef::DWORD,         // Low 32 bits of the size.
+            ptr::null_mut()                              // The mapping does not have a name.
+        )
+    };
+
+    if platform_data.mapping_handle.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+
+    let result = unsafe {
+        winapi::um::memoryapi::MapViewOfFile(
+            platform_data.mapping_handle,
+            winapi::um::memoryapi::FILE_MAP_WRITE,    // The memory mapping will be writable.
+            0, 0,                                     // Start offset of the mapping is 0.
+            length as winapi::shared::basetsd::SIZE_T // Map the entire region.
+        )
+    };
+
+    if result.is_null() {
         Err(io::Error::last_os_error())
     } else {
-        Ok((result as *const u8, length as usize, platform_data))
+        Ok((result as *mut u8, platform_data))
+    }
+}
+
+/// See also `unix::protect_readonly`.
+pub fn protect_readonly(
+    buffer: *mut u8,
+    length: usize,
+    platform_data: &PlatformData,
+) -> io::Result<()> {
+    if platform_data.file.is_some() {
+        let success = unsafe {
+            winapi::um::memoryapi::FlushViewOfFile(
+                buffer as *const winapi::ctypes::c_void,
+                length as winapi::shared::basetsd::SIZE_T
+            )
+        };
+        if success == 0 { return Err(io::Error::last_os_error()); }
+    }
+
+    let mut old_protect = 0;
+    let success = unsafe {
+        winapi::um::memoryapi::VirtualProtect(
+            buffer as *mut winapi::ctypes::c_void,
+            length as winapi::shared::basetsd::SIZE_T,
+            winapi::um::winnt::PAGE_READONLY,
+            &mut old_protect
+        )
+    };
+    if success == 0 { return Err(io::Error::last_os_error()); }
+
+    Ok(())
+}
+
+/// See also `unix::lock_memory`.
+pub fn lock_memory(buffer: *const u8, length: usize) -> io::Result<()> {
+    let success = unsafe {
+        winapi::um::memoryapi::VirtualLock(
+            buffer as *mut winapi::ctypes::c_void,
+            length as winapi::shared::basetsd::SIZE_T
+        )
+    };
+    if success == 0 { return Err(io::Error::last_os_error()); }
+
+    Ok(())
+}
+
+/// See also `unix::adopt_mmap`.
+#[cfg(feature = "memmap2")]
+pub fn adopt_mmap(mmap: memmap2::Mmap) -> (*const u8, PlatformData) {
+    let buffer = mmap.as_ptr();
+    let platform_data = PlatformData {
+        file: None,
+        offset: 0,
+        mapping_handle: ptr::null_mut(),
+        length: mmap.len(),
+        mmap: Some(mmap),
+        heap: None,
+    };
+    (buffer, platform_data)
+}
+
+/// See also `unix::is_memory_backed`. Files on a RAM disk are not detected on Windows.
+pub fn is_memory_backed(_platform_data: &PlatformData) -> bool {
+    false
+}
+
+/// See also `unix::read_file`.
+pub(crate) fn read_file(file: fs::File, length: usize) -> io::Result<(*const u8, PlatformData)> {
+    use std::os::windows::fs::FileExt;
+
+    let mut heap = HeapPages::new(length, get_page_size());
+    let buf = heap.as_mut_slice();
+    let mut offset = 0;
+
+    // Read at an explicit offset, because the file position may be shared with a different
+    // handle. Unlike on Unix-ish platforms, there is no `read_exact_at`.
+    while offset < length {
+        match file.seek_read(&mut buf[offset..], offset as u64) {
+            Ok(0) => {
+                let msg = "file is shorter than its reported length";
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+            }
+            Ok(n) => offset += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
     }
+
+    Ok(adopt_heap(Some(file), heap))
+}
+
+/// See also `unix::adopt_heap`.
+pub(crate) fn adopt_heap(file: Option<fs::File>, heap: HeapPages) -> (*const u8, PlatformData) {
+    let buffer = heap.as_ptr();
+    let platform_data = PlatformData {
+        file,
+        offset: 0,
+        mapping_handle: ptr::null_mut(),
+        length: heap.len(),
+        #[cfg(feature = "memmap2")]
+        mmap: None,
+        heap: Some(heap),
+    };
+    (buffer, platform_data)
 }
 
-pub fn unmap_file(buffer: *const u8, _length: usize) {
+/// See also `unix::is_stream`.
+pub fn is_stream(file: &fs::File) -> io::Result<bool> {
+    use winapi::um::winbase::{FILE_TYPE_CHAR, FILE_TYPE_PIPE};
+
+    // Pipes and consoles cannot be mapped. Files on disk have type `FILE_TYPE_DISK`.
+    let handle = file.as_raw_handle() as winapi::um::winnt::HANDLE;
+    let file_type = unsafe { winapi::um::fileapi::GetFileType(handle) };
+    Ok(file_type == FILE_TYPE_PIPE || file_type == FILE_TYPE_CHAR)
+}
+
+/// See also `unix::is_heap_backed`.
+pub(crate) fn is_heap_backed(platform_data: &PlatformData) -> bool {
+    platform_data.heap.is_some()
+}
+
+/// See also `unix::leak_owned_memory`.
+pub(crate) fn leak_owned_memory(platform_data: &mut PlatformData) {
+    mem::forget(platform_data.heap.take());
+
+    #[cfg(feature = "memmap2")]
+    {
+        mem::forget(platform_data.mmap.take());
+    }
+}
+
+/// See also `unix::unmap_file`.
+pub fn unmap_file(buffer: *const u8, length: usize, platform_data: &PlatformData) {
+    debug_assert_eq!(length, platform_data.length, "unmapped length differs from mapped length");
+
+    // A buffer that was read rather than mapped is freed when the platform data is dropped.
+    if platform_data.heap.is_some() { return; }
+
+    // The `Mmap` unmaps its view when the platform data is dropped.
+    #[cfg(feature = "memmap2")]
+    {
+        if platform_data.mmap.is_some() { return; }
+    }
+
     let success = unsafe {
         winapi::um::memoryapi::UnmapViewOfFile(buffer as *mut winapi::ctypes::c_void)
     };
     assert!(success != 0);
 }
 
+/// The state of the region of pages that a mapping starts in, as reported by `VirtualQuery`.
+///
+/// Returned by `FileBuffer::mapped_region_info()`. This type is only available on Windows.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegionInfo {
+    /// The size in bytes of the region, starting at the start of the buffer, in which all pages
+    /// have the same state and protection.
+    pub region_size: usize,
+    /// The state of the pages in the region: `MEM_COMMIT`, `MEM_RESERVE`, or `MEM_FREE`.
+    pub state: u32,
+    /// The access protection of the pages in the region, such as `PAGE_READONLY`.
+    pub protect: u32,
+}
+
+/// Queries the state of the region of pages starting at `buffer`.
+pub fn get_region_info(buffer: *const u8) -> io::Result<RegionInfo> {
+    let mut info: winapi::um::winnt::MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+    let info_size = mem::size_of::<winapi::um::winnt::MEMORY_BASIC_INFORMATION>();
+    let result = unsafe {
+        winapi::um::memoryapi::VirtualQuery(
+            buffer as *const winapi::ctypes::c_void,
+            &mut info,
+            info_size as winapi::shared::basetsd::SIZE_T
+        )
+    };
+
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let region_info = RegionInfo {
+        region_size: info.RegionSize as usize,
+        state: info.State,
+        protect: info.Protect,
+    };
+    Ok(region_info)
+}
+
 /// See also `unix::get_resident`.
 pub fn get_resident(_buffer: *const u8, _length: usize, residency: &mut [bool]) {
     // As far as I am aware, Windows does not expose a way to query whether pages are resident.
@@ -102,25 +606,147 @@ pub fn get_resident(_buffer: *const u8, _length: usize, residency: &mut [bool])
     }
 }
 
+/// See also `unix::readahead`. Windows has no equivalent, so this always calls `prefetch`.
+pub fn readahead(
+    buffer: *const u8,
+    _platform_data: &PlatformData,
+    offset: usize,
+    length: usize,
+) -> io::Result<()> {
+    prefetch(unsafe { buffer.add(offset) }, length)
+}
+
+/// See also `unix::prefetch_deep`. `PrefetchVirtualMemory` submits the reads for the entire
+/// range at once, so there is nothing to add to `prefetch`.
+pub fn prefetch_deep(
+    buffer: *const u8,
+    _platform_data: &PlatformData,
+    offset: usize,
+    length: usize,
+) -> io::Result<()> {
+    prefetch(unsafe { buffer.add(offset) }, length)
+}
+
+/// See also `unix::get_readahead_len`. The readahead window is not exposed on Windows.
+pub fn get_readahead_len(_platform_data: &PlatformData) -> Option<usize> {
+    None
+}
+
 /// See also `unix::prefetch`.
-pub fn prefetch(buffer: *const u8, length: usize) {
+pub fn prefetch(buffer: *const u8, length: usize) -> io::Result<()> {
     let mut entry = winapi::um::memoryapi::WIN32_MEMORY_RANGE_ENTRY {
         VirtualAddress: buffer as *mut winapi::ctypes::c_void,
         NumberOfBytes: length as winapi::shared::basetsd::SIZE_T,
     };
 
-    unsafe {
+    let success = unsafe {
         let current_process_handle = winapi::um::processthreadsapi::GetCurrentProcess();
         winapi::um::memoryapi::PrefetchVirtualMemory(
             current_process_handle, // Prefetch for the current process.
             1, &mut entry,          // An array of length 1 that contains `entry`.
             0                       // Reserved flag that must be 0.
-        );
+        )
+    };
+
+    // MSDN says the function may fail if the system is under memory pressure, in which case it
+    // returns zero.
+    if success != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// See also `unix::prefetch_ranges`.
+pub fn prefetch_ranges(buffer: *const u8, ranges: &[(usize, usize)]) -> io::Result<()> {
+    // Unlike `madvise`, `PrefetchVirtualMemory` takes an array of ranges, so a single call
+    // suffices.
+    let mut entries: Vec<_> = ranges
+        .iter()
+        .map(|&(offset, length)| winapi::um::memoryapi::WIN32_MEMORY_RANGE_ENTRY {
+            VirtualAddress: unsafe { buffer.add(offset) } as *mut winapi::ctypes::c_void,
+            NumberOfBytes: length as winapi::shared::basetsd::SIZE_T,
+        })
+        .collect();
+
+    let success = unsafe {
+        let current_process_handle = winapi::um::processthreadsapi::GetCurrentProcess();
+        winapi::um::memoryapi::PrefetchVirtualMemory(
+            current_process_handle,                           // Prefetch for the current process.
+            entries.len() as winapi::shared::basetsd::ULONG_PTR, // The number of entries.
+            entries.as_mut_ptr(),                             // The array of ranges.
+            0                                                 // Reserved flag that must be 0.
+        )
+    };
+
+    if success != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
     }
+}
 
-    // The return value of `PrefetchVirtualMemory` is ignored. MSDN says the function may fail if
-    // the system is under memory pressure. (It is not entirely clear whether "fail" means
-    // "returns a nonzero value", but I assume it does.)
+/// See also `unix::advise_free`.
+pub fn advise_free(
+    _buffer: *const u8,
+    _platform_data: &PlatformData,
+    _length: usize,
+) -> io::Result<()> {
+    // Windows has `DiscardVirtualMemory` and `OfferVirtualMemory`, but they only apply to private
+    // memory, not to views of a file mapping. This is a no-op, which is allowed for an advice.
+    Ok(())
+}
+
+/// See also `unix::advise_cold`.
+pub fn advise_cold(_buffer: *const u8, _length: usize) -> io::Result<()> {
+    // There is no equivalent on Windows for views of a file mapping. This is a no-op.
+    Ok(())
+}
+
+/// See also `unix::advise_pageout`.
+pub fn advise_pageout(_buffer: *const u8, _length: usize) -> io::Result<()> {
+    // There is no equivalent on Windows for views of a file mapping. This is a no-op.
+    Ok(())
+}
+
+/// See also `unix::advise`.
+pub fn advise(
+    buffer: *const u8,
+    platform_data: &PlatformData,
+    length: usize,
+    advice: Advice,
+) -> io::Result<()> {
+    match advice {
+        Advice::Cold => advise_cold(buffer, length),
+        Advice::PageOut => advise_pageout(buffer, length),
+        Advice::Free => advise_free(buffer, platform_data, length),
+        // Windows has no equivalent of `madvise` for access patterns. This is a no-op, which is
+        // allowed for an advice.
+        _ => Ok(()),
+    }
+}
+
+/// See also `unix::advise_file`.
+pub fn advise_file(
+    _platform_data: &PlatformData,
+    _offset: usize,
+    _length: usize,
+    _advice: Advice,
+) -> io::Result<()> {
+    // Windows has no equivalent of `posix_fadvise`. This is a no-op, which is allowed for an
+    // advice.
+    Ok(())
+}
+
+/// See also `unix::advise_fd`.
+pub fn advise_fd(
+    _file: &fs::File,
+    _offset: u64,
+    _length: usize,
+    _advice: Advice,
+) -> io::Result<()> {
+    // See `advise_file` above.
+    Ok(())
 }
 
 pub fn get_page_size() -> usize {
@@ -130,3 +756,27 @@ pub fn get_page_size() -> usize {
     unsafe { winapi::um::sysinfoapi::GetSystemInfo(&mut sysinfo); }
     sysinfo.dwPageSize as usize
 }
+
+/// See also `unix::get_cpu_count`.
+pub fn get_cpu_count() -> usize {
+    let mut sysinfo: winapi::um::sysinfoapi::SYSTEM_INFO = unsafe { mem::zeroed() };
+    unsafe { winapi::um::sysinfoapi::GetSystemInfo(&mut sysinfo); }
+    cmp::max(1, sysinfo.dwNumberOfProcessors as usize)
+}
+
+/// See also `unix::get_available_memory`. On Windows this is the available physical memory
+/// reported by `GlobalMemoryStatusEx`.
+pub fn get_available_memory() -> Option<u64> {
+    let mut status: winapi::um::sysinfoapi::MEMORYSTATUSEX = unsafe { mem::zeroed() };
+    status.dwLength = mem::size_of::<winapi::um::sysinfoapi::MEMORYSTATUSEX>() as u32;
+    let ok = unsafe { winapi::um::sysinfoapi::GlobalMemoryStatusEx(&mut status) };
+    if ok != 0 { Some(status.ullAvailPhys) } else { None }
+}
+
+/// See also `unix::get_allocation_granularity`. On Windows, views of a file mapping must start at
+/// a multiple of the allocation granularity, which is typically 64 KiB, rather than the page size.
+pub fn get_allocation_granularity() -> usize {
+    let mut sysinfo: winapi::um::sysinfoapi::SYSTEM_INFO = unsafe { mem::zeroed() };
+    unsafe { winapi::um::sysinfoapi::GetSystemInfo(&mut sysinfo); }
+    sysinfo.dwAllocationGranularity as usize
+}